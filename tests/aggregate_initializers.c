@@ -0,0 +1,45 @@
+struct point {
+  int x;
+  int y;
+};
+
+struct line {
+  struct point from, to;
+  char label[4];
+};
+
+int primes[] = {2, 3, 5, 7, 11};
+int grid[2][3] = {{1, 2, 3}, {4, 5, 6}};
+struct point origin = {.y = 3, .x = 1};
+struct line diagonal = {{0, 0}, {4, 4}, "ab"};
+char greeting[] = "hello";
+int sparse[8] = {[3] = 7, 8, [1] = 2};
+char *names[] = {"a", "bc"};
+int *second = primes;
+
+int total_of(struct point p) {
+  return p.x + p.y;
+}
+
+int main() {
+  int local[] = {1, 2, 3};
+  int flat[2][2] = {1, 2, 3, 4};
+  struct point p = {.y = 9};
+  struct line l = {.to.y = 5, .label = "xyz"};
+  char word[8] = "hi";
+  struct point pts[2] = {[1].x = 6, 1};
+  struct point *q = &origin;
+  struct point copied = origin;
+  struct line moved;
+  moved = diagonal;
+  copied.x = copied.x + 1;
+  struct point chosen = total_of(p) > 5 ? copied : origin;
+  struct point other = (total_of(p) < 5 ? copied : p);
+  int total = local[0] + local[2] + flat[1][0] + p.x + p.y + l.to.y + l.from.x;
+  total = total + primes[4] + grid[1][2] + origin.x + q->y + diagonal.to.x;
+  total = total + greeting[1] + sparse[3] + sparse[4] + sparse[1] + sparse[7];
+  total = total + word[1] + word[5] + l.label[2] + pts[1].x + pts[1].y + *names[1];
+  total = total + second[1] + copied.x + copied.y + origin.x + moved.to.y + moved.label[1];
+  total = total + chosen.x + other.y + (p.x ? p : copied).y;
+  return total;
+}
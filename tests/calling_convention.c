@@ -0,0 +1,99 @@
+int add(int a, int b) {
+  return a + b;
+}
+
+long many(char a, int b, long c, char d, int e, long f, int g, char h, long i) {
+  return a + b + c + d + e + f + g + h + i;
+}
+
+int last(int a, int b, int c, int d, int e, int f, int g) {
+  return g;
+}
+
+struct pair {
+  int x;
+  int y;
+};
+
+struct triple {
+  int x;
+  int y;
+  int z;
+};
+
+struct rgb {
+  char r;
+  char g;
+  char b;
+};
+
+struct big {
+  long a;
+  long b;
+  long c;
+};
+
+// laid out like the C library's ldiv_t, which ldiv returns in rax and rdx
+struct quotient {
+  long quot;
+  long rem;
+};
+
+struct quotient ldiv(long numerator, long denominator);
+
+int pair_sum(struct pair p) {
+  return p.x + p.y;
+}
+
+int mixed(struct rgb c, struct triple t, struct big b, int n) {
+  return c.r + c.g + c.b + t.x + t.y + t.z + b.a + b.b + b.c + n;
+}
+
+// the triple doesn't fit in the last register, so it goes on the stack
+// and the int after it still gets a register
+int crowded(int a, int b, int c, int d, int e, struct triple t, int f) {
+  t.z = t.z * 2;
+  return a + b + c + d + e + t.x + t.y + t.z + f;
+}
+
+struct pair make_pair(int x, int y) {
+  struct pair p = {x, y};
+  return p;
+}
+
+struct rgb grey(char level) {
+  struct rgb c = {level, level, level};
+  return c;
+}
+
+struct triple shifted(struct triple t, int by) {
+  t.x = t.x + by;
+  t.z = t.z + by;
+  return t;
+}
+
+// too big for registers, so it's returned to memory the caller passes
+struct big scaled(struct big b, long by) {
+  struct big result = {b.a * by, b.b * by, b.c * by};
+  return result;
+}
+
+int main() {
+  int x = add(add(1, 2), add(3, 4));
+  long y = many(1, 2, 3, 4, 5, 6, 7, 8, 9);
+  struct pair p = {3, 4};
+  struct triple t = {1, 2, 3};
+  struct rgb c = {1, 2, 3};
+  struct big b = {4, 5, 6};
+  int s = pair_sum(p) + mixed(c, t, b, 7) + crowded(1, 2, 3, 4, 5, t, 6);
+  // the callee changed its own copy
+  s = s + t.z;
+  struct pair made = make_pair(5, 6);
+  struct triple moved = shifted(t, 10);
+  struct big grown;
+  grown = scaled(b, 2);
+  struct quotient q = ldiv(47, 5);
+  s = s + made.x * made.y + grey(2).b + moved.x + moved.z + grown.c + scaled(b, 3).a;
+  s = s + pair_sum(make_pair(7, 8)) + q.quot + q.rem + ldiv(9, 4).rem;
+  return x + y + s + last(1, 2, 3, 4, 5, 6, add(20, add(x, 1)));
+}
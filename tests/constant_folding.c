@@ -0,0 +1,32 @@
+int printf(const char *format, ...);
+
+/* every operand here is a constant, or a local that only ever holds one,
+ * so at -O1 all of it is worked out before the program runs */
+int main(void) {
+  int big = 2147483647;
+  unsigned u = 4000000000u;
+  long l = -5;
+  unsigned long ul = 18446744073709551615ul;
+  int shift = 3;
+  int negative = -17;
+  int a = 2 + 3 * 4 - 6 / 4;
+  int b = a;
+  char c = 300;
+  unsigned char uc = -1;
+
+  a = a * 2;
+  b += a;
+  printf("%d %d %d %d\n", a, b, 7 % 3, -7 % 3);
+  printf("%u %u %u %u\n", u + u, u * 3u, u / 7u, u % 7u);
+  printf("%d %d %d\n", negative / 4, negative % 4, -negative);
+  printf("%d %d %d\n", negative >> 2, negative << shift, 1 << 30);
+  printf("%u %d\n", (unsigned)negative >> shift, ~negative);
+  printf("%d %d %d\n", big & 0xff0, big | 7, big ^ 0x55);
+  printf("%ld %lu %lu\n", l * 1000000000000, ul / 3, ul >> 60);
+  printf("%ld %ld %d\n", (long)negative, (long)u, (int)ul);
+  printf("%d %d %d %d\n", c, uc, (char)big, (unsigned char)negative);
+  printf("%d %d %d %d\n", negative < 3, u < 3u, l <= -5, ul > 0);
+  printf("%d %d %d\n", !a, a && negative, 0 || shift);
+  printf("%d %d\n", a == 26 ? 100 : 200, sizeof(long) * 2 == 16);
+  return (unsigned char)(a + b + shift);
+}
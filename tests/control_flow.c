@@ -0,0 +1,95 @@
+int printf(const char *format, ...);
+
+int collatz(long n) {
+  int steps = 0;
+  while (n != 1) {
+    if (n % 2 == 0)
+      n = n / 2;
+    else
+      n = 3 * n + 1;
+    steps++;
+  }
+  return steps;
+}
+
+/* an else goes with the nearest if */
+int sign(int x) {
+  if (x >= 0)
+    if (x == 0)
+      return 0;
+    else
+      return 1;
+  return -1;
+}
+
+int sum_odd_below(int limit) {
+  int total = 0;
+  for (int i = 0;; i++) {
+    if (i >= limit)
+      break;
+    if (i % 2 == 0)
+      continue;
+    total += i;
+  }
+  return total;
+}
+
+int digits(unsigned n) {
+  int count = 0;
+  do {
+    count++;
+    n /= 10;
+  } while (n);
+  return count;
+}
+
+int find(int *values, int count, int wanted) {
+  int i;
+  for (i = 0; i < count; i++) {
+    if (values[i] == wanted)
+      goto found;
+  }
+  return -1;
+found:
+  return i;
+}
+
+void say(int n) {
+  if (n < 0) {
+    printf("negative\n");
+    return;
+  }
+  printf("%d\n", n);
+}
+
+int main(void) {
+  int values[6] = {4, 8, 15, 16, 23, 42};
+  int x = 10;
+  int nested = 0;
+
+  printf("%d %d %d\n", collatz(27), collatz(1), collatz(97));
+  printf("%d %d %d\n", sign(-5), sign(0), sign(7));
+  printf("%d %d\n", sum_odd_below(10), sum_odd_below(0));
+  printf("%d %d %d\n", digits(0), digits(9), digits(4000000000u));
+  printf("%d %d\n", find(values, 6, 23), find(values, 6, 5));
+  say(-1);
+  say(12);
+  {
+    int x = 20;
+    printf("%d\n", x);
+  }
+  printf("%d\n", x);
+  for (int i = 0; i < 4; i++)
+    for (int j = 0; j < 4; j++) {
+      if (j > i)
+        break;
+      nested += i * j;
+    }
+  printf("%d\n", nested);
+  while (1) {
+    if (++x > 15)
+      break;
+  }
+  if (x == 16) ; else printf("wrong\n");
+  return x;
+}
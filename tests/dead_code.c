@@ -0,0 +1,71 @@
+int printf(const char *format, ...);
+
+struct pair {
+  int first;
+  int second;
+};
+
+int sum(int *values, int count) {
+  int total = 0;
+  for (int i = 0; i < count; i++)
+    total += values[i];
+  return total;
+}
+
+void set(int *p, int value) {
+  *p = value;
+}
+
+/* every store here is read, some of them only through a pointer */
+int aliased(int x) {
+  int values[3];
+  int *p = values;
+  int local = 1;
+  int *q = &local;
+  struct pair pair;
+
+  values[0] = x;
+  p[1] = x * 2;
+  *(p + 2) = 7;
+  *q = 5;
+  set(&local, local + 1);
+  pair.first = 3;
+  pair.second = local;
+  return sum(values, 3) + local + pair.first * pair.second;
+}
+
+/* and none of these are */
+int unread(int x) {
+  int scratch[8];
+  struct pair pair;
+  int i;
+  for (i = 0; i < 8; i++)
+    scratch[i] = x * i;
+  pair.first = x;
+  pair.second = scratch[2] + 1;
+  return x + 1;
+  x = scratch[3];
+}
+
+int loops(int n) {
+  int count = 0;
+  while (n > 0) {
+    n -= 3;
+    if (n == 4)
+      continue;
+    count++;
+  }
+  do {
+    count *= 2;
+    break;
+    count = 0;
+  } while (1);
+  return count;
+}
+
+int main(void) {
+  printf("%d %d\n", aliased(4), aliased(-2));
+  printf("%d\n", unread(9));
+  printf("%d %d %d\n", loops(10), loops(7), loops(0));
+  return 0;
+}
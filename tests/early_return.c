@@ -0,0 +1,19 @@
+int twice(int a) {
+  return a + a;
+  return 1;
+}
+
+int store(int *p, int value) {
+  *p = value;
+  return value;
+  *p = 0;
+  return 0;
+}
+
+int main() {
+  int x = 0;
+  int y = store(&x, twice(5));
+  return x + y;
+  x = 100;
+  return x;
+}
@@ -0,0 +1,74 @@
+int printf(const char *format, ...);
+
+/* 256 leaves nested deep enough that evaluating them needs more registers
+ * than there are, so some partial results get spilled */
+#define L1(x) ((x) - ((x) + 1u))
+#define L2(x) (L1(x) - L1((x) * 3u))
+#define L3(x) (L2(x) - L2((x) + 5u))
+#define L4(x) (L3(x) - L3((x) ^ 7u))
+#define L5(x) (L4(x) - L4((x) << 1))
+#define L6(x) (L5(x) - L5((x) | 9u))
+#define L7(x) (L6(x) - L6((x) + 11u))
+#define L8(x) (L7(x) - L7((x) * 13u))
+
+int twice(int x) {
+  return x * 2;
+}
+
+unsigned deep(unsigned x) {
+  return L8(x);
+}
+
+long calls(long a, long b) {
+  return (a + twice(a)) * (b - twice(b)) + twice(twice(a) + twice(b)) % 7;
+}
+
+int main(void) {
+  int a = 47;
+  int b = -6;
+  unsigned u = 3000000000u;
+  int count = 3;
+  int values[5] = {1, 2, 3, 4, 5};
+  int *first = values;
+  int *last = values + 4;
+  long total;
+
+  printf("%u %ld\n", deep(5u), calls(3, 4));
+  printf("%d %d %d %d\n", a / b, a % b, -a / 5, -a % 5);
+  printf("%u %u %u\n", u / 7u, u % 7u, u >> count);
+  printf("%d %d %d %d\n", a << 2, b >> 1, a << count, b >> count);
+  printf("%d %d %d %d\n", a & 12, a | b, a ^ b, ~a);
+  printf("%d %d %d %d %d %d\n", a < b, a > b, a <= 47, a >= 48, a == 47,
+         a != 47);
+  printf("%d %d\n", u > 1u, b < 0u);
+  printf("%d %d %d %d %d\n", a && b, a && 0, 0 || b, 0 || 0, !a);
+  printf("%d %d\n", a > b ? a : b, a < b ? a : b);
+  printf("%ld %d %d\n", last - first, *(last - 2), (int)sizeof(values));
+
+  total = a;
+  total += b;
+  total *= 3;
+  total -= 1;
+  total /= 2;
+  total %= 100;
+  total <<= 3;
+  total >>= 1;
+  total |= 1;
+  total &= 255;
+  total ^= 16;
+  printf("%ld\n", total);
+
+  count = a++;
+  printf("%d %d", count, a);
+  count = ++a;
+  printf(" %d %d", count, a);
+  count = b--;
+  printf(" %d %d", count, b);
+  count = --b;
+  printf(" %d %d\n", count, b);
+  first++;
+  ++first;
+  printf("%d\n", *first);
+
+  return (a - b) % 256;
+}
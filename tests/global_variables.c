@@ -0,0 +1,13 @@
+int counter;
+int start = 40;
+const int step = 2;
+int *cursor = &start;
+
+int bump() {
+  counter = *cursor + step;
+  return counter;
+}
+
+int main() {
+  return bump();
+}
@@ -0,0 +1,57 @@
+int printf(const char *format, ...);
+
+static inline int square(int x) { return x * x; }
+
+static int clamp(int value, int low, int high) {
+  if (value < low)
+    return low;
+  if (value > high)
+    return high;
+  return value;
+}
+
+/* a local array and a loop, which have to be copied along with it */
+static inline long sum_to(int n) {
+  long digits[4];
+  long total = 0;
+  int i;
+  for (i = 0; i < 4; i++)
+    digits[i] = i;
+  for (i = 1; i <= n; i++)
+    total += i + digits[i % 4] - digits[i % 4];
+  return total;
+}
+
+/* a char parameter is passed as an int */
+static char next(char c) { return c + 1; }
+
+static void say(int value) { printf("said %d\n", value); }
+
+/* calls itself, so it's never inlined */
+static int factorial(int n) {
+  if (n <= 1)
+    return 1;
+  return n * factorial(n - 1);
+}
+
+/* call each other, so they're only inlined so many levels deep */
+static int is_even(int n);
+static int is_odd(int n) { return n == 0 ? 0 : is_even(n - 1); }
+static int is_even(int n) { return n == 0 ? 1 : is_odd(n - 1); }
+
+/* its address is taken, so calls to it stay */
+static int twice(int x) { return 2 * x; }
+
+int main(void) {
+  int i;
+  int a = 7;
+  for (i = 0; i < 3; i++)
+    printf("%d %d\n", square(i + a), clamp(square(i) - 2, 0, 3));
+  printf("%ld %ld\n", sum_to(10), sum_to(a));
+  printf("%d %d\n", next('a'), next(127));
+  say(square(3));
+  printf("%d %d\n", factorial(5), factorial(a));
+  printf("%d %d %d\n", is_even(10), is_odd(7), is_even(3));
+  printf("%d %d\n", twice(a), &twice != 0);
+  return square(2);
+}
@@ -0,0 +1,11 @@
+long big = 0x100000000;
+unsigned int mask = 0xffffffffu;
+
+int main() {
+  long a = 017 + 0x7f + 0b101 + 10u + 1UL;
+  unsigned long huge = 18446744073709551615ul;
+  long sum = a + big + mask + huge;
+  unsigned char c = 250;
+  int wide = c + 10;
+  return sum + (sum + 2147483648) + wide;
+}
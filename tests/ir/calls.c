@@ -0,0 +1,18 @@
+#include <stdarg.h>
+
+int printf(const char *format, ...);
+
+int sum(int count, ...) {
+    va_list ap;
+    int total = 0;
+    va_start(ap, count);
+    total += va_arg(ap, int);
+    total += va_arg(ap, int);
+    va_end(ap);
+    return total;
+}
+
+int main() {
+    printf("%d\n", sum(2, 3, 4));
+    return 0;
+}
@@ -0,0 +1,21 @@
+/* flags: -O1 */
+/* divisions that trap are left for the program to run into, and shift
+ * counts are masked the way the hardware masks them */
+int traps(int x) {
+    int zero = 0;
+    int min = -2147483647 - 1;
+    return x ? 1 / zero : min / -1;
+}
+
+long shifts(void) {
+    int count = 33;
+    return (1 << count) + (1L << count);
+}
+
+/* a local is only constant after a branch if both sides agree on it */
+int merge(int x) {
+    int same = 4;
+    int different = 4;
+    x ? (different = 5) : (same = 4);
+    return same * 10 + different;
+}
@@ -0,0 +1,12 @@
+/* the operators that only evaluate some of their operands branch */
+int pick(int a, int b) {
+    return a < b && b != 0 ? a : b;
+}
+
+int any(int a, int b) {
+    return a || b;
+}
+
+int main() {
+    return pick(1, 2) + any(0, 3);
+}
@@ -0,0 +1,41 @@
+/* flags: -O1 */
+int printf(const char *format, ...);
+
+/* nothing after a return, break or goto runs, and the warnings point at
+ * the first statement of each stretch */
+int first_even(int *values, int count) {
+    int i;
+    for (i = 0; i < count; i++) {
+        if (values[i] % 2 == 0)
+            goto found;
+        continue;
+        printf("skipped\n");
+    }
+    return -1;
+found:
+    return values[i];
+    i = 0;
+}
+
+/* the product is never used, and neither is the array nobody reads */
+int unused(int a, int b) {
+    int scratch[4];
+    int product = a * b;
+    scratch[0] = a;
+    scratch[1] = product;
+    while (1) {
+        a += b;
+        break;
+        a = 0;
+    }
+    return a;
+}
+
+/* the branch on a constant leaves an empty block behind */
+int constant_branch(int x) {
+    if (1 == 2) {
+        x = x * 10;
+    } else {
+    }
+    return x;
+}
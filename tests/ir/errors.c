@@ -0,0 +1,36 @@
+int add(int a, int b);
+int add(int a, int b) { return a + b; }
+int add(int a, int b) { return a - b; }
+long add(int, int);
+
+int twice(int x) {
+    int y = x;
+    int y = 2 * x;
+    return y;
+}
+
+int main(int argc, int argc) {
+    return add(1) + add(1, 2, 3);
+}
+
+int count(int n);
+int first(int *p);
+void *copy(void *dest, const char *src);
+
+int arguments(void) {
+    int a[2];
+    long l;
+    // these convert as they would when assigned
+    copy(a, "s");
+    first(0);
+    first(a);
+    count('c');
+    return count(&l) + count("s") + first(3) + first(&l);
+}
+
+int shared(void);
+static int shared(void) { return 1; }
+static int hidden;
+int hidden;
+int twice_initialized = 1;
+int twice_initialized = 2;
@@ -0,0 +1,39 @@
+/* flags: -O2 --inline-threshold=3 */
+int printf(const char *format, ...);
+
+/* small enough for the threshold, so its calls go away */
+static int add(int a, int b) { return a + b; }
+
+/* too big for the threshold, but declared inline */
+static inline int clamp(int value, int low, int high) {
+    if (value < low)
+        return low;
+    if (value > high)
+        return high;
+    return value;
+}
+
+/* too big, so it's called */
+int mix(int a, int b) {
+    int x = a * b + a;
+    return x / b - x % a;
+}
+
+/* its address is taken, so it's called even though it's small */
+static int negate(int a) { return -a; }
+
+/* calls itself, so it's never inlined */
+static int countdown(int n) {
+    if (n == 0)
+        return 0;
+    return countdown(n - 1);
+}
+
+int count_down(int n) {
+    return countdown(n);
+}
+
+int calls(int x) {
+    printf("%p\n", &negate);
+    return clamp(add(x, 1), 0, 10) + mix(x, 3) + negate(x);
+}
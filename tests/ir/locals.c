@@ -0,0 +1,13 @@
+/* locals whose address isn't taken live in registers */
+int scale(int a, long b) {
+    int c = a * 3;
+    long d = b + c;
+    c += 1;
+    return d - c;
+}
+
+int main() {
+    char c = 'a';
+    c++;
+    return scale(c, 2);
+}
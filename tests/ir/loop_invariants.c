@@ -0,0 +1,41 @@
+/* flags: -O2 */
+int printf(const char *format, ...);
+
+/* i * n doesn't change in the inner loop, and neither does the address of
+ * the array, so they're worked out before it */
+long sum(int *a, int n) {
+    long total = 0;
+    int i;
+    int j;
+    for (i = 0; i < n; i++)
+        for (j = 0; j < n; j++)
+            total += a[i * n + j];
+    return total;
+}
+
+/* the same product twice is only computed once, even in another block */
+int twice(int x, int y) {
+    int first = x * y + 1;
+    if (x > 0)
+        return x * y - first;
+    return first;
+}
+
+/* the loop stores to the array, so the loads stay inside it */
+int stores(int n) {
+    int counts[4];
+    int i;
+    counts[0] = n;
+    for (i = 1; i < 4; i++)
+        counts[i] = counts[0] + counts[i - 1];
+    return counts[3];
+}
+
+/* the division could trap, so it isn't worked out before the loop */
+int divides(int n, int d) {
+    int total = 0;
+    int i;
+    for (i = 0; i < n; i++)
+        total += 100 / d;
+    return total;
+}
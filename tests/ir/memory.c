@@ -0,0 +1,20 @@
+/* arrays, structs and locals whose address is taken live in memory */
+struct point {
+    int x;
+    int y;
+};
+
+int global = 3;
+
+int set(int *p, int value) {
+    *p = value;
+    return value;
+}
+
+int main() {
+    int a = 1;
+    int values[3] = {1, 2, 3};
+    struct point pt = {4, 5};
+    set(&a, values[2]);
+    return a + pt.y + global;
+}
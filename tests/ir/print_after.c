@@ -0,0 +1,11 @@
+/* flags: -O2 --print-after=inline --print-after=tail-calls */
+
+/* the IR after inlining still has the recursive call, and after the tail
+ * call pass it's a loop */
+static int square(int x) { return x * x; }
+
+int sum_squares(int n, int total) {
+    if (n == 0)
+        return total;
+    return sum_squares(n - 1, total + square(n));
+}
@@ -0,0 +1,42 @@
+/* flags: -O2 --inline-threshold=0 */
+int printf(const char *format, ...);
+
+/* calls itself in tail position, so it becomes a loop */
+int gcd(int a, int b) {
+    if (b == 0)
+        return a;
+    return gcd(b, a % b);
+}
+
+/* a call to another function in tail position is marked */
+int forward(int x) {
+    return gcd(x, 12);
+}
+
+/* the result of the call is used */
+int factorial(int n) {
+    if (n <= 1)
+        return 1;
+    return n * factorial(n - 1);
+}
+
+/* the callee might use the local, whose frame has to stay */
+int read(int *p) {
+    return *p;
+}
+int pass_local(int n) {
+    int local = n;
+    return read(&local);
+}
+
+/* the seventh argument is on the stack, which only matters for calls to
+ * other functions */
+int seven(int a, int b, int c, int d, int e, int f, int g) {
+    if (a == 0)
+        return g;
+    return seven(a - 1, b, c, d, e, f, g);
+}
+
+int call_seven(int a) {
+    return seven(a, 1, 2, 3, 4, 5, 6);
+}
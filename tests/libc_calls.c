@@ -0,0 +1,18 @@
+int printf(const char *format, ...);
+int puts(const char *s);
+void *malloc(unsigned long size);
+void free(void *p);
+
+int main(void) {
+  char c = 'x';
+  long big = 1234567890123;
+  int *p = malloc(16);
+  puts("hello from u-cc");
+  printf("%d %c %ld %s\n", 42, c, big, "done");
+  printf("%d %d %d %d %d %d %d %d\n", 1, 2, 3, 4, 5, 6, 7, 8);
+  p[1] = 7;
+  p[3] = printf("%d\n", p[1]);
+  int result = p[1] + p[3];
+  free(p);
+  return result;
+}
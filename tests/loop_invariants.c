@@ -0,0 +1,72 @@
+int printf(const char *format, ...);
+
+int counter;
+
+int bump(void) { return ++counter; }
+
+/* the loads of counter can't be reused past the call or the store */
+int reloads(int *p) {
+  int a = counter + 1;
+  int b = counter + 1;
+  bump();
+  int c = counter + 1;
+  *p = 10;
+  int d = counter + 1;
+  return a * 1000 + b * 100 + c * 10 + d;
+}
+
+/* the pointer might point into the array, so the loads stay in the loop */
+int aliased(int *p, int n) {
+  int values[3];
+  int total = 0;
+  int i;
+  values[0] = 1;
+  for (i = 0; i < n; i++) {
+    total += values[0];
+    *p = total;
+  }
+  return total;
+}
+
+long matrix(int n) {
+  int cells[16];
+  long total = 0;
+  int i;
+  int j;
+  for (i = 0; i < n; i++)
+    for (j = 0; j < n; j++)
+      cells[i * n + j] = i * n + j;
+  for (i = 0; i < n; i++)
+    for (j = 0; j < n; j++)
+      total += cells[i * n + j] * (i * n + 1);
+  return total;
+}
+
+/* the loop might not run at all, and the division mustn't happen then */
+int never(int n, int d) {
+  int total = 0;
+  int i;
+  for (i = 0; i < n; i++)
+    total += 100 / d + n * 2;
+  return total;
+}
+
+int reassigned(int x) {
+  int y = x * 3;
+  x = x + 1;
+  int z = x * 3;
+  return y + z;
+}
+
+int main(void) {
+  int values[3];
+  values[0] = 5;
+  int result = reloads(&counter);
+  printf("%d %d\n", result, counter);
+  result = aliased(values, 4);
+  printf("%d %d\n", result, values[0]);
+  printf("%ld %ld\n", matrix(4), matrix(0));
+  printf("%d %d\n", never(0, 0), never(3, 7));
+  printf("%d\n", reassigned(4));
+  return 0;
+}
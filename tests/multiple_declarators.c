@@ -0,0 +1,10 @@
+int main() {
+  int a, b = 2, *c;
+  int x;
+  int y = 5;
+  c = &b;
+  a = *c;
+  *c = y;
+  x = a + b;
+  return x;
+}
@@ -0,0 +1,33 @@
+int add(int a, int b);
+long widen(long x);
+char narrow(char c);
+int unprototyped();
+int later();
+int later(void);
+
+int main(void) {
+  int sum = add(1, 2);
+  long wide = widen(sum);
+  char c = narrow(300);
+  return sum + wide + c + unprototyped(4, 5) + later();
+}
+
+int add(int x, int y) {
+  return x + y;
+}
+
+long widen(long x) {
+  return x + 4000000000;
+}
+
+char narrow(char c) {
+  return c;
+}
+
+int unprototyped(int a, int b) {
+  return a + b;
+}
+
+int later() {
+  return 5;
+}
@@ -0,0 +1,28 @@
+int printf(const char *format, ...);
+
+int id(int x) {
+  return x;
+}
+
+long wide(long x) {
+  return x * 1000000007;
+}
+
+/* each call's result stays live across the calls to its right, so there
+ * are more live values than callee saved registers to keep them in */
+int many_live(int a) {
+  return id(a) + (id(a + 1) * (id(a + 2) - (id(a + 3) ^ (id(a + 4) + (id(a + 5) * (id(a + 6) - (id(a + 7) + (id(a + 8) | (id(a + 9) + (id(a + 10) - (id(a + 11) + id(a + 12))))))))))));
+}
+
+/* division and shifts need particular registers while other values are
+ * live in them */
+long fixed_registers(long a, int b, int c) {
+  return (wide(a) / b + wide(b) % c) * ((a << c) + (wide(c) >> b) + id(c) / id(b) - (a % c << (b & 3)));
+}
+
+int main(void) {
+  int a = 3;
+  printf("%d %d\n", many_live(a), many_live(-a));
+  printf("%ld %ld\n", fixed_registers(12345, 3, 5), fixed_registers(-77, 7, 2));
+  return many_live(1) % 100;
+}
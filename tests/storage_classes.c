@@ -0,0 +1,50 @@
+int printf(const char *format, ...);
+
+extern int total;
+static int base = 3;
+int tentative;
+int tentative;
+static long hidden;
+
+static int count(void) {
+  static int calls;
+  calls = calls + 1;
+  return calls;
+}
+
+static int start_at_ten(void) {
+  static int calls = 10;
+  calls = calls + 1;
+  return calls;
+}
+
+int pick(int a) {
+  if (a) {
+    static int x = 1;
+    x = x + 1;
+    return x;
+  } else {
+    static int x = 10;
+    x = x + 1;
+    return x;
+  }
+}
+
+int read_total(void) {
+  extern int total;
+  return total;
+}
+
+int total = 4;
+
+int main(void) {
+  count();
+  count();
+  start_at_ten();
+  tentative = 5;
+  hidden = base;
+  pick(1);
+  printf("%d %d %d %d\n", count(), start_at_ten(), read_total(), pick(0));
+  printf("%d\n", pick(1));
+  return count() + tentative + hidden + read_total();
+}
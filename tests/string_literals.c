@@ -0,0 +1,8 @@
+char *greeting = "hi" " there";
+
+int main() {
+  char *s = "a\tb\n\\\101\x42\0c";
+  char c = 'x';
+  char *p = s + 3;
+  return *p + *(greeting + 3) + c + '\n' + *(s + 6) + '\0' + '\'';
+}
@@ -0,0 +1,96 @@
+int printf(const char *format, ...);
+void *malloc(unsigned long size);
+
+struct node {
+  int value;
+  struct node *next;
+};
+
+/* walks the list by recursing on the rest of it, which is a loop at -O2 */
+long sum(struct node *list, long total) {
+  if (list == 0)
+    return total;
+  return sum(list->next, total + list->value);
+}
+
+struct node *last(struct node *list) {
+  if (list->next == 0)
+    return list;
+  return last(list->next);
+}
+
+/* the arguments swap, so they all have to be read before any is set */
+int gcd(int a, int b) {
+  if (b == 0)
+    return a;
+  return gcd(b, a % b);
+}
+
+/* calls each other in tail position, which leaves the stack as it was */
+int is_odd(int n);
+int is_even(int n) {
+  if (n == 0)
+    return 1;
+  return is_odd(n - 1);
+}
+int is_odd(int n) {
+  if (n == 0)
+    return 0;
+  return is_even(n - 1);
+}
+
+/* the result is used after the call, so it isn't in tail position */
+int depth(struct node *list) {
+  if (list == 0)
+    return 0;
+  return 1 + depth(list->next);
+}
+
+/* a char parameter is passed as an int */
+char shout(char c, int times) {
+  if (times == 0)
+    return c;
+  return shout(c - 1, times - 1);
+}
+
+void count_down(int n) {
+  if (n == 0) {
+    printf("liftoff\n");
+    return;
+  }
+  count_down(n - 1);
+}
+
+/* the last argument is on the stack, which a loop doesn't mind */
+long seven(int a, int b, int c, int d, int e, int f, long g) {
+  if (a == 0)
+    return b + c + d + e + f + g;
+  return seven(a - 1, c, b, d, e, f, g + a);
+}
+
+/* the callee might use the local, so the frame has to stay */
+int read(int *p) { return *p; }
+int pass_local(int n) {
+  int local = n * 2;
+  return read(&local);
+}
+
+int main(void) {
+  struct node *list = 0;
+  int i;
+  for (i = 0; i < 50000; i++) {
+    struct node *node = malloc(sizeof(struct node));
+    node->value = i;
+    node->next = list;
+    list = node;
+  }
+  printf("%ld %d\n", sum(list, 0), last(list)->value);
+  printf("%d %d\n", gcd(1071, 462), gcd(17, 5));
+  printf("%d %d\n", is_even(10000), is_odd(7));
+  printf("%d\n", depth(list));
+  printf("%c\n", shout('z', 25));
+  count_down(10000);
+  printf("%d\n", pass_local(21));
+  printf("%ld\n", seven(100000, 1, 2, 3, 4, 5, 6));
+  return 0;
+}
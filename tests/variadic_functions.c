@@ -0,0 +1,51 @@
+#include <stdarg.h>
+
+int printf(const char *format, ...);
+int vprintf(const char *format, va_list ap);
+
+/* there are no loops yet, so this always reads eight numbers */
+int sum(int n, ...) {
+  va_list ap;
+  va_start(ap, n);
+  int total = n;
+  total = total + va_arg(ap, int) + va_arg(ap, int) + va_arg(ap, int);
+  total = total + va_arg(ap, int) + va_arg(ap, int) + va_arg(ap, int);
+  total = total + va_arg(ap, int) + va_arg(ap, int);
+  va_end(ap);
+  return total;
+}
+
+long second(va_list ap) {
+  va_arg(ap, long);
+  return va_arg(ap, long);
+}
+
+// the copy starts where the original was when it was copied
+long copies(int a, int b, int c, int d, int e, int f, int g, ...) {
+  va_list ap;
+  va_list copy;
+  va_start(ap, g);
+  va_arg(ap, long);
+  va_copy(copy, ap);
+  long from_copy = second(copy);
+  long from_original = va_arg(ap, long);
+  va_end(copy);
+  va_end(ap);
+  return from_copy + from_original;
+}
+
+int print(const char *format, ...) {
+  va_list ap;
+  va_start(ap, format);
+  int printed = vprintf(format, ap);
+  va_end(ap);
+  return printed;
+}
+
+int main() {
+  char c = 'z';
+  int total = sum(8, 1, 2, 3, 4, 5, 6, 7, 8);
+  long copied = copies(1, 2, 3, 4, 5, 6, 7, 100L, 20L, 3L);
+  print("%d %ld %c %s\n", total, copied, c, "end");
+  return total + copied;
+}
@@ -1,10 +1,10 @@
 use colored::*;
 use std::path::{Path, PathBuf};
-use std::{env, fs, io};
+use std::{fs, io};
 
 fn workdir() -> io::Result<PathBuf> {
     let pid = std::process::id();
-    let temp_dir = std::env::temp_dir().join(&format!("u-cc-test-{}", pid));
+    let temp_dir = std::env::temp_dir().join(format!("u-cc-test-{}", pid));
 
     if !temp_dir.exists() {
         fs::create_dir(&temp_dir)?;
@@ -12,13 +12,48 @@ fn workdir() -> io::Result<PathBuf> {
     Ok(temp_dir)
 }
 
+/// every test runs at each of these, and has to behave the same
+const OPT_LEVELS: &[&str] = &["-O0", "-O1", "-O2"];
+
 struct TestCase {
     file_path: PathBuf,
+    opt_level: &'static str,
 }
 
 enum TestResult {
     Passed,
-    WrongStatusCode { expected: i32, received: i32 },
+    WrongStatusCode {
+        expected: i32,
+        received: i32,
+    },
+    WrongOutput {
+        expected: String,
+        received: String,
+    },
+    /// the AT&T output, or the object u-cc wrote itself, has different
+    /// code or data than the NASM output, which one saying
+    DifferentObjects(&'static str),
+    /// the executable u-cc linked itself behaved differently from the one
+    /// gcc linked
+    DifferentExecution {
+        linked: Execution,
+        expected: Execution,
+    },
+}
+
+/// what running a program produced
+#[derive(PartialEq)]
+struct Execution {
+    status: i32,
+    stdout: String,
+}
+
+fn execute(executable: &Path) -> io::Result<Execution> {
+    let output = duct::cmd!(executable).unchecked().stdout_capture().run()?;
+    Ok(Execution {
+        status: output.status.code().unwrap(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+    })
 }
 
 #[cfg(target_os = "linux")]
@@ -31,9 +66,63 @@ fn nasm_format() -> &'static str {
     "macho64"
 }
 
+/// the disassembly and data of an object, placed by linking it alone at
+/// fixed addresses with undefined symbols left at zero. Assemblers resolve
+/// references within an object differently, leaving some to relocations,
+/// but the result once they're applied is the same.
+#[cfg(target_os = "linux")]
+fn contents(object: &Path) -> io::Result<String> {
+    let linked = object.with_extension("fixed");
+    duct::cmd!(
+        "ld",
+        "-z",
+        "noexecstack",
+        "-e",
+        "main",
+        "--unresolved-symbols=ignore-all",
+        "-Ttext=0x400000",
+        "--section-start=.rodata=0x500000",
+        "--section-start=.data=0x600000",
+        "--section-start=.bss=0x700000",
+        "-o",
+        &linked,
+        object
+    )
+    .run()?;
+    let dump =
+        duct::cmd!("objdump", "-d", "-s", "-j", ".text", "-j", ".rodata", "-j", ".data", &linked)
+            .stderr_null()
+            .read()?;
+    // the first lines name the file, and NASM keeps local labels as
+    // symbols where other assemblers don't, so the lines that start at a
+    // symbol and the names after addresses are left out
+    let lines = dump
+        .lines()
+        .skip_while(|line| !line.starts_with("Contents"))
+        .filter(|line| !line.ends_with(">:"))
+        .map(|line| match (line.find(" <"), line.rfind('>')) {
+            // only disassembly has tabs, and not the hex dumps of data,
+            // which could have anything
+            (Some(start), Some(end)) if line.contains('\t') && start < end => {
+                format!("{}{}", &line[..start], &line[end + 1..])
+            }
+            _ => line.to_string(),
+        });
+    Ok(lines.collect::<Vec<_>>().join("\n"))
+}
+
+/// only Linux has a linker that can be told where to put each section
+#[cfg(not(target_os = "linux"))]
+fn contents(_object: &Path) -> io::Result<String> {
+    Ok(String::new())
+}
+
 impl TestCase {
-    pub fn new(file_path: PathBuf) -> TestCase {
-        TestCase { file_path }
+    pub fn new(file_path: PathBuf, opt_level: &'static str) -> TestCase {
+        TestCase {
+            file_path,
+            opt_level,
+        }
     }
     /// runs the input file by compiling it to gcc
     /// and returns the status code of the resulting
@@ -43,18 +132,16 @@ impl TestCase {
         fs::create_dir(&dir)?;
         Ok(dir)
     }
-    fn run_gcc(&self) -> io::Result<i32> {
+    fn run_gcc(&self) -> io::Result<Execution> {
         let gcc_workdir = self.gcc_workdir()?;
         let gcc_exe_path = gcc_workdir.join("exec");
         duct::cmd!("gcc", "-o", &gcc_exe_path, self.file_path()?).run()?;
 
-        let output = duct::cmd!(gcc_exe_path).unchecked().run()?;
-        let status = &output.status;
-        Ok(status.code().unwrap())
+        execute(&gcc_exe_path)
     }
 
     fn workdir(&self) -> io::Result<PathBuf> {
-        let workdir = workdir()?.join(&self.name());
+        let workdir = workdir()?.join(format!("{}{}", self.name(), self.opt_level));
         if !workdir.exists() {
             fs::create_dir(&workdir)?;
         }
@@ -67,14 +154,57 @@ impl TestCase {
     fn asm_file_path(&self) -> io::Result<PathBuf> {
         Ok(self.workdir()?.join(self.filename()?.with_extension("asm")))
     }
+    fn gas_obj_file_path(&self) -> io::Result<PathBuf> {
+        Ok(self
+            .workdir()?
+            .join(self.filename()?.with_extension("gas.o")))
+    }
+    fn gas_file_path(&self) -> io::Result<PathBuf> {
+        Ok(self.workdir()?.join(self.filename()?.with_extension("s")))
+    }
+    fn direct_obj_file_path(&self) -> io::Result<PathBuf> {
+        Ok(self
+            .workdir()?
+            .join(self.filename()?.with_extension("direct.o")))
+    }
     fn executable_file_path(&self) -> io::Result<PathBuf> {
         Ok(self.workdir()?.join("out"))
     }
-    // returns the file path to the generated asm
-    fn compile_c_file(&self) -> io::Result<()> {
-        let cmd = duct::cmd!("cargo", "run", "--bin", "u-cc", "--", self.file_path()?)
-            .stderr_null()
-            .stdout(self.asm_file_path()?);
+    fn static_executable_file_path(&self) -> io::Result<PathBuf> {
+        Ok(self.workdir()?.join("static"))
+    }
+    /// writes the assembly in the syntax to the file
+    fn compile_c_file(&self, syntax: &str, asm_file_path: PathBuf) -> io::Result<()> {
+        let cmd = duct::cmd!(
+            "cargo",
+            "run",
+            "--bin",
+            "u-cc",
+            "--",
+            self.opt_level,
+            format!("--syntax={}", syntax),
+            self.file_path()?
+        )
+        .stderr_null()
+        .stdout(asm_file_path);
+        cmd.run()?;
+        Ok(())
+    }
+    /// writes an object file without an assembler
+    fn compile_c_file_to_object(&self) -> io::Result<()> {
+        let cmd = duct::cmd!(
+            "cargo",
+            "run",
+            "--bin",
+            "u-cc",
+            "--",
+            self.opt_level,
+            "-c",
+            "-o",
+            self.direct_obj_file_path()?,
+            self.file_path()?
+        )
+        .stderr_null();
         cmd.run()?;
         Ok(())
     }
@@ -90,6 +220,44 @@ impl TestCase {
         cmd.run()?;
         Ok(())
     }
+    fn compile_gas_file(&self) -> io::Result<()> {
+        let cmd = duct::cmd!(
+            "gcc",
+            "-c",
+            "-o",
+            self.gas_obj_file_path()?,
+            self.gas_file_path()?
+        );
+        cmd.run()?;
+        Ok(())
+    }
+    /// links the object u-cc wrote with u-cc alone, which can't when the
+    /// program uses the C library, so then there's nothing to run
+    fn link_obj_file_statically(&self) -> io::Result<Option<PathBuf>> {
+        let output = duct::cmd!(
+            "cargo",
+            "run",
+            "-q",
+            "--bin",
+            "u-cc",
+            "--",
+            "--link",
+            "-o",
+            self.static_executable_file_path()?,
+            self.direct_obj_file_path()?
+        )
+        .stderr_capture()
+        .unchecked()
+        .run()?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if output.status.success() {
+            Ok(Some(self.static_executable_file_path()?))
+        } else if stderr.contains("undefined reference") {
+            Ok(None)
+        } else {
+            Err(io::Error::other(stderr.into_owned()))
+        }
+    }
     fn link_obj_file(&self) -> io::Result<()> {
         let cmd = duct::cmd!(
             "gcc",
@@ -102,23 +270,41 @@ impl TestCase {
     }
     pub fn run(&self) -> io::Result<TestResult> {
         // nasm -f macho64 ret_const.asm && gcc ret_const.o && ./a.out
-        self.compile_c_file()?;
+        self.compile_c_file("nasm", self.asm_file_path()?)?;
         self.compile_asm_file()?;
         self.link_obj_file()?;
+        self.compile_c_file("att", self.gas_file_path()?)?;
+        self.compile_gas_file()?;
+        self.compile_c_file_to_object()?;
+        let expected = contents(&self.obj_file_path()?)?;
+        if contents(&self.gas_obj_file_path()?)? != expected {
+            return Ok(TestResult::DifferentObjects("AT&T syntax"));
+        }
+        if contents(&self.direct_obj_file_path()?)? != expected {
+            return Ok(TestResult::DifferentObjects("u-cc -c"));
+        }
 
-        let status_code = {
-            let output = duct::cmd!(self.executable_file_path()?).unchecked().run()?;
-
-            output.status.code().unwrap()
-        };
-        let expected_status = self.run_gcc()?;
-        if status_code != expected_status {
+        let received = execute(&self.executable_file_path()?)?;
+        let expected = self.run_gcc()?;
+        if received.status != expected.status {
             return Ok(TestResult::WrongStatusCode {
-                expected: expected_status,
-                received: status_code,
+                expected: expected.status,
+                received: received.status,
             });
         }
-        return Ok(TestResult::Passed);
+        if received.stdout != expected.stdout {
+            return Ok(TestResult::WrongOutput {
+                expected: expected.stdout,
+                received: received.stdout,
+            });
+        }
+        if let Some(executable) = self.link_obj_file_statically()? {
+            let linked = execute(&executable)?;
+            if linked != expected {
+                return Ok(TestResult::DifferentExecution { linked, expected });
+            }
+        }
+        Ok(TestResult::Passed)
     }
 
     pub fn name(&self) -> String {
@@ -133,7 +319,7 @@ impl TestCase {
     }
 
     pub fn file_path(&self) -> io::Result<&Path> {
-        return Ok(self.file_path.as_path());
+        Ok(self.file_path.as_path())
     }
 
     pub fn filename(&self) -> io::Result<PathBuf> {
@@ -141,25 +327,145 @@ impl TestCase {
     }
 }
 
-fn collect_test_cases() -> io::Result<impl Iterator<Item = io::Result<TestCase>>> {
-    Ok(fs::read_dir("tests")?
-        .map(|test_case| -> io::Result<_> { Ok(TestCase::new(test_case?.path())) }))
+fn collect_test_cases() -> io::Result<Vec<TestCase>> {
+    let mut test_cases = vec![];
+    for path in c_files("tests")? {
+        let path = path?;
+        for opt_level in OPT_LEVELS {
+            test_cases.push(TestCase::new(path.clone(), opt_level));
+        }
+    }
+    Ok(test_cases)
+}
+
+/// the C files directly in a directory
+fn c_files(dir: &str) -> io::Result<impl Iterator<Item = io::Result<PathBuf>>> {
+    Ok(fs::read_dir(dir)?.filter_map(|entry| match entry {
+        Ok(entry) if entry.path().extension() == Some("c".as_ref()) => Some(Ok(entry.path())),
+        Ok(_) => None,
+        Err(err) => Some(Err(err)),
+    }))
+}
+
+/// a C file in tests/ir, whose IR and warnings, or errors if it doesn't
+/// compile, have to match the .ir file next to it. A first line like `/* flags: -O1 */` passes flags to the
+/// compiler.
+struct GoldenTest {
+    file_path: PathBuf,
+}
+
+impl GoldenTest {
+    fn expected_path(&self) -> PathBuf {
+        self.file_path.with_extension("ir")
+    }
+    fn flags(&self) -> io::Result<Vec<String>> {
+        let source = fs::read_to_string(&self.file_path)?;
+        let first_line = source.lines().next().unwrap_or_default();
+        Ok(match first_line.trim().strip_prefix("/* flags:") {
+            Some(flags) => flags
+                .trim_end_matches("*/")
+                .split_whitespace()
+                .map(String::from)
+                .collect(),
+            None => vec![],
+        })
+    }
+    /// compares the IR with the expected one, or with `bless` makes it
+    /// the expected one
+    fn run(&self, bless: bool) -> io::Result<TestResult> {
+        let mut args: Vec<String> = ["run", "-q", "--bin", "u-cc", "--", "--emit=ir"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        args.extend(self.flags()?);
+        args.push(self.file_path.to_string_lossy().into_owned());
+        let received = duct::cmd("cargo", args)
+            .stderr_to_stdout()
+            .unchecked()
+            .read()?
+            + "\n";
+        if bless {
+            fs::write(self.expected_path(), &received)?;
+            return Ok(TestResult::Passed);
+        }
+        let expected = fs::read_to_string(self.expected_path()).unwrap_or_default();
+        if received != expected {
+            return Ok(TestResult::WrongOutput { expected, received });
+        }
+        Ok(TestResult::Passed)
+    }
+    fn name(&self) -> String {
+        let stem = self.file_path.file_stem().unwrap().to_string_lossy();
+        format!("ir/{}", stem)
+    }
 }
 
 fn main() -> io::Result<()> {
+    // --bless rewrites the expected IR instead of checking it
+    let bless = std::env::args().any(|arg| arg == "--bless");
     for test_case in collect_test_cases()? {
-        let test_case = test_case?;
         match test_case.run()? {
             TestResult::Passed => {
-                println!("{} {}", "[PASSED]".green(), test_case.name());
+                println!(
+                    "{} {} {}",
+                    "[PASSED]".green(),
+                    test_case.name(),
+                    test_case.opt_level
+                );
             }
             TestResult::WrongStatusCode { expected, received } => println!(
-                "{} Expected {}, Received {}",
+                "{} {} {} Expected {}, Received {}",
                 "[FAILED]".red(),
+                test_case.name(),
+                test_case.opt_level,
                 expected,
                 received
             ),
+            TestResult::WrongOutput { expected, received } => println!(
+                "{} {} {} printed {:?}, expected {:?}",
+                "[FAILED]".red(),
+                test_case.name(),
+                test_case.opt_level,
+                received,
+                expected
+            ),
+            TestResult::DifferentObjects(from) => println!(
+                "{} {} {} assembled differently from {}",
+                "[FAILED]".red(),
+                test_case.name(),
+                test_case.opt_level,
+                from
+            ),
+            TestResult::DifferentExecution { linked, expected } => println!(
+                "{} {} {} linked by u-cc --link exited with {} and printed {:?}, expected {} and {:?}",
+                "[FAILED]".red(),
+                test_case.name(),
+                test_case.opt_level,
+                linked.status,
+                linked.stdout,
+                expected.status,
+                expected.stdout
+            ),
+        }
+    }
+    for file_path in c_files("tests/ir")? {
+        let test = GoldenTest {
+            file_path: file_path?,
+        };
+        match test.run(bless)? {
+            TestResult::Passed => println!("{} {}", "[PASSED]".green(), test.name()),
+            TestResult::WrongOutput { expected, received } => println!(
+                "{} {} emitted IR that differs from {}:\n{}\nexpected:\n{}",
+                "[FAILED]".red(),
+                test.name(),
+                test.expected_path().display(),
+                received,
+                expected
+            ),
+            TestResult::WrongStatusCode { .. }
+            | TestResult::DifferentObjects(_)
+            | TestResult::DifferentExecution { .. } => unreachable!(),
         }
     }
-    return Ok(());
+    Ok(())
 }
@@ -1,33 +1,232 @@
-use crate::asm::Address::Indirect;
+use crate::platform;
 use std::fmt::{self, Display};
 
-#[derive(Debug, Clone, PartialEq)]
+mod att;
+
+/// the assemblers the output can be written for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    /// Intel syntax, which the Display impls here write
+    Nasm,
+    /// AT&T syntax, for GNU as
+    Att,
+}
+
+/// the 16 general purpose registers, at 8, 4, 2 and 1 bytes wide, and the
+/// virtual registers code is generated with before register allocation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[allow(dead_code)]
 pub enum Register {
     Rax,
-    Rbp,
+    Rcx,
+    Rdx,
+    Rbx,
     Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
     Eax,
+    Ecx,
+    Edx,
+    Ebx,
+    Esp,
     Ebp,
-    Edi,
     Esi,
-    Esp,
+    Edi,
+    R8d,
+    R9d,
+    R10d,
+    R11d,
+    R12d,
+    R13d,
+    R14d,
+    R15d,
+    Ax,
+    Cx,
+    Dx,
+    Bx,
+    Sp,
+    Bp,
+    Si,
+    Di,
+    R8w,
+    R9w,
+    R10w,
+    R11w,
+    R12w,
+    R13w,
+    R14w,
+    R15w,
+    Al,
+    Cl,
+    Dl,
+    Bl,
+    Spl,
+    Bpl,
+    Sil,
+    Dil,
+    R8b,
+    R9b,
+    R10b,
+    R11b,
+    R12b,
+    R13b,
+    R14b,
+    R15b,
+    /// a virtual register, by number, and how many bytes of it are used
+    Virtual(usize, usize),
+}
+
+use Register::*;
+
+/// each register at every width, widest first, in encoding order
+const REGISTERS: [[Register; 4]; 16] = [
+    [Rax, Eax, Ax, Al],
+    [Rcx, Ecx, Cx, Cl],
+    [Rdx, Edx, Dx, Dl],
+    [Rbx, Ebx, Bx, Bl],
+    [Rsp, Esp, Sp, Spl],
+    [Rbp, Ebp, Bp, Bpl],
+    [Rsi, Esi, Si, Sil],
+    [Rdi, Edi, Di, Dil],
+    [R8, R8d, R8w, R8b],
+    [R9, R9d, R9w, R9b],
+    [R10, R10d, R10w, R10b],
+    [R11, R11d, R11w, R11b],
+    [R12, R12d, R12w, R12b],
+    [R13, R13d, R13w, R13b],
+    [R14, R14d, R14w, R14b],
+    [R15, R15d, R15w, R15b],
+];
+
+impl Register {
+    fn row(self) -> &'static [Register; 4] {
+        REGISTERS.iter().find(|row| row.contains(&self)).unwrap()
+    }
+    /// how many bytes wide the register is
+    pub fn size(self) -> usize {
+        if let Virtual(_, size) = self {
+            return size;
+        }
+        match self.row().iter().position(|register| *register == self) {
+            Some(0) => 8,
+            Some(1) => 4,
+            Some(2) => 2,
+            _ => 1,
+        }
+    }
+    /// the number instruction encodings use for the register, from 0 for
+    /// rax to 15 for r15
+    pub fn number(self) -> u8 {
+        REGISTERS
+            .iter()
+            .position(|row| row.contains(&self))
+            .unwrap() as u8
+    }
+    /// the same register at another width, so `Rax.resize(4)` is `Eax`
+    pub fn resize(self, size: usize) -> Register {
+        if let Virtual(number, _) = self {
+            return Virtual(number, size);
+        }
+        let row = self.row();
+        match size {
+            1 => row[3],
+            2 => row[2],
+            4 => row[1],
+            _ => row[0],
+        }
+    }
 }
 
 impl Display for Register {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use Register::*;
+        // virtual registers are named like r8, r8d, r8w and r8b
+        if let Virtual(number, size) = self {
+            let suffix = match size {
+                1 => "b",
+                2 => "w",
+                4 => "d",
+                _ => "",
+            };
+            return write!(f, "v{}{}", number, suffix);
+        }
         write!(
             f,
             "{}",
             match self {
                 Rax => "rax",
-                Rbp => "rbp",
+                Rcx => "rcx",
+                Rdx => "rdx",
+                Rbx => "rbx",
                 Rsp => "rsp",
+                Rbp => "rbp",
+                Rsi => "rsi",
+                Rdi => "rdi",
+                R8 => "r8",
+                R9 => "r9",
+                R10 => "r10",
+                R11 => "r11",
+                R12 => "r12",
+                R13 => "r13",
+                R14 => "r14",
+                R15 => "r15",
                 Eax => "eax",
-                Ebp => "ebp",
+                Ecx => "ecx",
+                Edx => "edx",
+                Ebx => "ebx",
                 Esp => "esp",
-                Edi => "edi",
+                Ebp => "ebp",
                 Esi => "esi",
+                Edi => "edi",
+                R8d => "r8d",
+                R9d => "r9d",
+                R10d => "r10d",
+                R11d => "r11d",
+                R12d => "r12d",
+                R13d => "r13d",
+                R14d => "r14d",
+                R15d => "r15d",
+                Ax => "ax",
+                Cx => "cx",
+                Dx => "dx",
+                Bx => "bx",
+                Sp => "sp",
+                Bp => "bp",
+                Si => "si",
+                Di => "di",
+                R8w => "r8w",
+                R9w => "r9w",
+                R10w => "r10w",
+                R11w => "r11w",
+                R12w => "r12w",
+                R13w => "r13w",
+                R14w => "r14w",
+                R15w => "r15w",
+                Al => "al",
+                Cl => "cl",
+                Dl => "dl",
+                Bl => "bl",
+                Spl => "spl",
+                Bpl => "bpl",
+                Sil => "sil",
+                Dil => "dil",
+                R8b => "r8b",
+                R9b => "r9b",
+                R10b => "r10b",
+                R11b => "r11b",
+                R12b => "r12b",
+                R13b => "r13b",
+                R14b => "r14b",
+                R15b => "r15b",
+                Virtual(..) => unreachable!(),
             }
         )
     }
@@ -36,8 +235,10 @@ impl Display for Register {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Address {
     Register(Register),
-    Immediate(i32),
+    Immediate(i64),
     Indirect(IndirectAddress),
+    /// a symbol, only meaningful as the base of an IndirectAddress
+    Label(String),
 }
 
 impl Display for Address {
@@ -46,6 +247,24 @@ impl Display for Address {
             Address::Immediate(val) => Display::fmt(val, f),
             Address::Register(reg) => Display::fmt(reg, f),
             Address::Indirect(indirect) => Display::fmt(indirect, f),
+            Address::Label(label) => write!(f, "{}", label),
+        }
+    }
+}
+
+impl Address {
+    /// how many bytes the operand is, if it says
+    pub fn size(&self) -> Option<usize> {
+        match self {
+            Address::Register(register) => Some(register.size()),
+            Address::Indirect(IndirectAddress {
+                size: Some(size), ..
+            }) => Some(match size {
+                IndirectSize::Byte => 1,
+                IndirectSize::Dword => 4,
+                IndirectSize::Qword => 8,
+            }),
+            _ => None,
         }
     }
 }
@@ -56,6 +275,12 @@ impl From<Register> for Address {
     }
 }
 
+impl From<i64> for Address {
+    fn from(val: i64) -> Address {
+        Address::Immediate(val)
+    }
+}
+
 impl From<IndirectAddress> for Address {
     fn from(addr: IndirectAddress) -> Address {
         Address::Indirect(addr)
@@ -71,16 +296,22 @@ pub struct IndirectAddress {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum IndirectSize {
+    Byte,
     Dword,
-    Qword
+    Qword,
 }
 
 impl Display for IndirectSize {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", match self {
-            IndirectSize::Dword => "dword",
-            IndirectSize::Qword => "qword"
-        })
+        write!(
+            f,
+            "{}",
+            match self {
+                IndirectSize::Byte => "byte",
+                IndirectSize::Dword => "dword",
+                IndirectSize::Qword => "qword",
+            }
+        )
     }
 }
 
@@ -93,7 +324,19 @@ impl IndirectAddress {
         }
     }
     pub fn indirect(name: Box<Address>) -> IndirectAddress {
-        IndirectAddress { name, offset: None, size: None }
+        IndirectAddress {
+            name,
+            offset: None,
+            size: None,
+        }
+    }
+    /// [rel label], addressed relative to rip so the output can be position independent
+    pub fn rip_relative(label: String) -> IndirectAddress {
+        IndirectAddress::indirect(Box::new(Address::Label(label)))
+    }
+    pub fn byte(mut self) -> IndirectAddress {
+        self.size = Some(IndirectSize::Byte);
+        self
     }
     pub fn dword(mut self) -> IndirectAddress {
         self.size = Some(IndirectSize::Dword);
@@ -103,57 +346,369 @@ impl IndirectAddress {
         self.size = Some(IndirectSize::Qword);
         self
     }
-    pub fn no_size(mut self) -> IndirectAddress {
-        self.size = None;
+    pub fn add_offset(mut self, offset: i32) -> IndirectAddress {
+        self.offset = Some(self.offset.unwrap_or(0) + offset);
         self
     }
+    pub fn base(&self) -> &Address {
+        &self.name
+    }
+    pub fn displacement(&self) -> i32 {
+        self.offset.unwrap_or(0)
+    }
 }
 
 impl Display for IndirectAddress {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match (self.offset, &self.size) {
-            (Some(offset), Some(size)) => {
-                write!(f, "{} [{} {}]", size, self.name, offset)
-            },
-            (Some(offset), None) => {
-                write!(f, "[{} {}]", self.name, offset)
+        if let Some(size) = &self.size {
+            write!(f, "{} ", size)?;
+        }
+        match &*self.name {
+            Address::Label(label) => write!(f, "[rel {}", label)?,
+            name => write!(f, "[{}", name)?,
+        }
+        match self.offset {
+            Some(offset) if offset < 0 => write!(f, " - {}]", -i64::from(offset)),
+            Some(offset) => write!(f, " + {}]", offset),
+            None => write!(f, "]"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Section {
+    Text,
+    Data,
+    Bss,
+    Rodata,
+    /// an empty section saying the stack doesn't have to be executable,
+    /// which ELF linkers otherwise assume it does
+    NoteGnuStack,
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Section::Text => ".text",
+                Section::Data => ".data",
+                Section::Bss => ".bss",
+                Section::Rodata => ".rodata",
+                Section::NoteGnuStack => ".note.GNU-stack",
             }
-            (None, Some(size)) => {
-                write!(f, "{} [{}]", size, self.name)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataValue {
+    Int(i64),
+    /// the address of a symbol, resolved by the linker
+    Label(String),
+}
+
+impl Display for DataValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataValue::Int(val) => Display::fmt(val, f),
+            DataValue::Label(label) => write!(f, "{}", label),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Data {
+    /// db
+    Byte(Vec<u8>),
+    /// dd
+    Dword(Vec<i32>),
+    /// dq
+    Qword(Vec<DataValue>),
+    /// resb, only valid in .bss
+    Reserve(usize),
+    /// times n db 0, for padding in initialized sections
+    Zero(usize),
+}
+
+impl Display for Data {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn list<T: Display>(f: &mut fmt::Formatter, directive: &str, values: &[T]) -> fmt::Result {
+            write!(f, "{} ", directive)?;
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", value)?;
             }
-            (None, None) => write!(f, "[{}]", self.name),
+            Ok(())
+        }
+        match self {
+            Data::Byte(bytes) => list(f, "db", bytes),
+            Data::Dword(values) => list(f, "dd", values),
+            Data::Qword(values) => list(f, "dq", values),
+            Data::Reserve(size) => write!(f, "resb {}", size),
+            Data::Zero(size) => write!(f, "times {} db 0", size),
+        }
+    }
+}
+
+/// the condition codes of conditional jumps. Below and above compare
+/// unsigned values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+pub enum Condition {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Below,
+    BelowEqual,
+    Above,
+    AboveEqual,
+}
+
+impl Condition {
+    /// the condition that holds exactly when this one doesn't
+    pub fn negate(self) -> Condition {
+        match self {
+            Condition::Equal => Condition::NotEqual,
+            Condition::NotEqual => Condition::Equal,
+            Condition::Less => Condition::GreaterEqual,
+            Condition::LessEqual => Condition::Greater,
+            Condition::Greater => Condition::LessEqual,
+            Condition::GreaterEqual => Condition::Less,
+            Condition::Below => Condition::AboveEqual,
+            Condition::BelowEqual => Condition::Above,
+            Condition::Above => Condition::BelowEqual,
+            Condition::AboveEqual => Condition::Below,
         }
     }
 }
 
+impl Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Condition::Equal => "e",
+                Condition::NotEqual => "ne",
+                Condition::Less => "l",
+                Condition::LessEqual => "le",
+                Condition::Greater => "g",
+                Condition::GreaterEqual => "ge",
+                Condition::Below => "b",
+                Condition::BelowEqual => "be",
+                Condition::Above => "a",
+                Condition::AboveEqual => "ae",
+            }
+        )
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Instruction {
+    /// a symbol other objects can use
+    Global(String),
+    /// a symbol defined in another object
+    Extern(String),
+    Section(Section),
+    /// pad with zeroes to a multiple of n bytes
+    Align(usize),
+    /// like Align, but reserves the padding in .bss instead
+    AlignB(usize),
+    Data(Data),
     Label(String),
     Push(Register),
     /// dest, src
     Mov(Address, Address),
+    /// dest, src: sign extends a byte
+    Movsx(Address, Address),
+    /// dest, src: zero extends a byte
+    Movzx(Address, Address),
+    /// dest, src: sign extends a dword
+    Movsxd(Address, Address),
     // dest, adder
     Add(Address, Address),
+    // dest, subtrahend
+    Sub(Address, Address),
+    // dest, multiplier
+    Imul(Address, Address),
+    /// two's complement negation
+    Neg(Address),
+    /// bitwise complement
+    Not(Address),
+    /// dest, src
+    And(Address, Address),
+    /// dest, src
+    Or(Address, Address),
+    /// dest, src
+    Xor(Address, Address),
+    /// dest, count: the count is an immediate or cl
+    Shl(Address, Address),
+    /// dest, count: arithmetic shift right, which keeps the sign
+    Sar(Address, Address),
+    /// dest, count: logical shift right, which shifts in zeroes
+    Shr(Address, Address),
+    /// sign extends eax into edx, before a 32 bit idiv
+    Cdq,
+    /// sign extends rax into rdx, before a 64 bit idiv
+    Cqo,
+    /// divisor: signed division of rdx:rax, leaving the quotient in rax and
+    /// the remainder in rdx
+    Idiv(Address),
+    /// divisor: like Idiv, but unsigned
+    Div(Address),
     // load effective address
     Lea(Address, Address),
+    /// lhs, rhs: sets the flags for lhs - rhs
+    Cmp(Address, Address),
+    /// label, and how many arguments are passed in registers
+    Call(String, usize),
+    /// like Call, but to a function that may be in a shared library, so
+    /// it's called through the PLT where the platform has one
+    CallExternal(String, usize),
+    /// like Call, but jumps to a function after the stack frame is gone,
+    /// so it returns straight to the caller
+    TailCall(String, usize),
+    /// like TailCall, but through the PLT like CallExternal
+    TailCallExternal(String, usize),
     /// label
-    Call(String),
+    Jmp(String),
+    /// jumps to the label if the flags meet the condition
+    Jcc(Condition, String),
+    /// sets a byte register to 1 if the flags meet the condition, else 0
+    Setcc(Condition, Register),
 
     Pop(Register),
     Ret,
+    /// shows up in the output as a comment, and does nothing
+    Comment(String),
+}
+
+impl Instruction {
+    /// the instruction in the syntax, for printing
+    pub fn display(&self, syntax: Syntax) -> Printed<'_> {
+        Printed {
+            instruction: self,
+            syntax,
+        }
+    }
+    /// every register the instruction names, including the ones memory
+    /// operands are addressed through
+    pub fn registers_mut(&mut self) -> Vec<&mut Register> {
+        let operands = match self {
+            Instruction::Mov(dest, src)
+            | Instruction::Movsx(dest, src)
+            | Instruction::Movzx(dest, src)
+            | Instruction::Movsxd(dest, src)
+            | Instruction::Add(dest, src)
+            | Instruction::Sub(dest, src)
+            | Instruction::Imul(dest, src)
+            | Instruction::And(dest, src)
+            | Instruction::Or(dest, src)
+            | Instruction::Xor(dest, src)
+            | Instruction::Shl(dest, src)
+            | Instruction::Sar(dest, src)
+            | Instruction::Shr(dest, src)
+            | Instruction::Lea(dest, src)
+            | Instruction::Cmp(dest, src) => vec![dest, src],
+            Instruction::Neg(dest)
+            | Instruction::Not(dest)
+            | Instruction::Idiv(dest)
+            | Instruction::Div(dest) => vec![dest],
+            Instruction::Push(register)
+            | Instruction::Pop(register)
+            | Instruction::Setcc(_, register) => return vec![register],
+            _ => vec![],
+        };
+        operands
+            .into_iter()
+            .filter_map(|operand| match operand {
+                Address::Register(register) => Some(register),
+                Address::Indirect(IndirectAddress { name, .. }) => match &mut **name {
+                    Address::Register(register) => Some(register),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// an instruction written in one of the syntaxes
+pub struct Printed<'a> {
+    instruction: &'a Instruction,
+    syntax: Syntax,
+}
+
+impl Display for Printed<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.syntax {
+            Syntax::Nasm => Display::fmt(self.instruction, f),
+            Syntax::Att => att::fmt(self.instruction, f),
+        }
+    }
 }
 
 impl Display for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            Instruction::Global(label) => write!(f, "global {}", label),
+            Instruction::Extern(label) => write!(f, "extern {}", label),
+            Instruction::Section(Section::NoteGnuStack) => write!(
+                f,
+                "section {} noalloc noexec nowrite progbits",
+                Section::NoteGnuStack
+            ),
+            Instruction::Section(section) => write!(f, "section {}", section),
+            Instruction::Align(bytes) => write!(f, "align {}, db 0", bytes),
+            Instruction::AlignB(bytes) => write!(f, "alignb {}", bytes),
+            Instruction::Data(data) => write!(f, "{}", data),
             Instruction::Label(label) => write!(f, "{}:", label),
             Instruction::Push(reg) => write!(f, "push {}", reg),
             Instruction::Mov(src, dest) => write!(f, "mov {}, {}", src, dest),
+            Instruction::Movsx(src, dest) => write!(f, "movsx {}, {}", src, dest),
+            Instruction::Movzx(src, dest) => write!(f, "movzx {}, {}", src, dest),
+            Instruction::Movsxd(src, dest) => write!(f, "movsxd {}, {}", src, dest),
             Instruction::Add(src, dest) => write!(f, "add {}, {}", src, dest),
+            Instruction::Sub(src, dest) => write!(f, "sub {}, {}", src, dest),
+            Instruction::Imul(src, dest) => write!(f, "imul {}, {}", src, dest),
+            Instruction::Neg(dest) => write!(f, "neg {}", dest),
+            Instruction::Not(dest) => write!(f, "not {}", dest),
+            Instruction::And(dest, src) => write!(f, "and {}, {}", dest, src),
+            Instruction::Or(dest, src) => write!(f, "or {}, {}", dest, src),
+            Instruction::Xor(dest, src) => write!(f, "xor {}, {}", dest, src),
+            Instruction::Shl(dest, count) => write!(f, "shl {}, {}", dest, count),
+            Instruction::Sar(dest, count) => write!(f, "sar {}, {}", dest, count),
+            Instruction::Shr(dest, count) => write!(f, "shr {}, {}", dest, count),
+            Instruction::Cdq => write!(f, "cdq"),
+            Instruction::Cqo => write!(f, "cqo"),
+            Instruction::Idiv(divisor) => write!(f, "idiv {}", divisor),
+            Instruction::Div(divisor) => write!(f, "div {}", divisor),
             Instruction::Lea(src, dest) => write!(f, "lea {}, {}", src, dest),
-            Instruction::Call(label) => write!(f, "call {}", label),
+            Instruction::Cmp(lhs, rhs) => write!(f, "cmp {}, {}", lhs, rhs),
+            Instruction::Call(label, _) => write!(f, "call {}", label),
+            Instruction::CallExternal(label, _) => {
+                write!(f, "call {}{}", label, platform::plt_suffix(Syntax::Nasm))
+            }
+            // always near, since GNU as leaves jumps to global functions
+            // near for the linker to fill in, and the code has to come out
+            // the same from both assemblers
+            Instruction::TailCall(label, _) => write!(f, "jmp near {}", label),
+            Instruction::TailCallExternal(label, _) => {
+                write!(f, "jmp {}{}", label, platform::plt_suffix(Syntax::Nasm))
+            }
+            Instruction::Jmp(label) => write!(f, "jmp {}", label),
+            Instruction::Jcc(condition, label) => write!(f, "j{} {}", condition, label),
+            Instruction::Setcc(condition, reg) => write!(f, "set{} {}", condition, reg),
             Instruction::Pop(reg) => write!(f, "pop {}", reg),
             Instruction::Ret => write!(f, "ret"),
+            Instruction::Comment(comment) => write!(f, "; {}", comment),
         }
     }
 }
@@ -0,0 +1,171 @@
+//! The assembly in AT&T syntax, for the GNU assembler, so the output can go
+//! straight through `gcc -c`. Operands go source first, registers and
+//! immediates are marked with % and $, and the operand size is a suffix on
+//! the mnemonic instead of a keyword on memory operands.
+
+use super::{Address, Data, Instruction, Section, Syntax};
+use crate::platform;
+use std::fmt::{self, Display};
+
+fn suffix(size: Option<usize>) -> &'static str {
+    match size {
+        Some(1) => "b",
+        Some(2) => "w",
+        Some(4) => "l",
+        Some(8) => "q",
+        _ => "",
+    }
+}
+
+struct Operand<'a>(&'a Address);
+
+impl Display for Operand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Address::Register(register) => write!(f, "%{}", register),
+            Address::Immediate(val) => write!(f, "${}", val),
+            Address::Label(label) => write!(f, "{}", label),
+            Address::Indirect(indirect) => {
+                let offset = indirect.displacement();
+                match indirect.base() {
+                    Address::Label(label) if offset < 0 => {
+                        write!(f, "{}-{}(%rip)", label, -i64::from(offset))
+                    }
+                    Address::Label(label) if offset > 0 => {
+                        write!(f, "{}+{}(%rip)", label, offset)
+                    }
+                    Address::Label(label) => write!(f, "{}(%rip)", label),
+                    name if offset != 0 => write!(f, "{}({})", offset, Operand(name)),
+                    name => write!(f, "({})", Operand(name)),
+                }
+            }
+        }
+    }
+}
+
+/// writes `mnemonic src, dest`, sized by the destination, or by the source
+/// if the destination doesn't say
+fn binary(f: &mut fmt::Formatter, mnemonic: &str, dest: &Address, src: &Address) -> fmt::Result {
+    let suffix = suffix(dest.size().or_else(|| src.size()));
+    write!(
+        f,
+        "{}{} {}, {}",
+        mnemonic,
+        suffix,
+        Operand(src),
+        Operand(dest)
+    )
+}
+
+fn unary(f: &mut fmt::Formatter, mnemonic: &str, operand: &Address) -> fmt::Result {
+    write!(
+        f,
+        "{}{} {}",
+        mnemonic,
+        suffix(operand.size()),
+        Operand(operand)
+    )
+}
+
+/// like binary, but the count is cl, which isn't the size of what's
+/// shifted
+fn shift(f: &mut fmt::Formatter, mnemonic: &str, dest: &Address, count: &Address) -> fmt::Result {
+    let suffix = suffix(dest.size());
+    write!(
+        f,
+        "{}{} {}, {}",
+        mnemonic,
+        suffix,
+        Operand(count),
+        Operand(dest)
+    )
+}
+
+/// writes a move that extends `src` to the size of `dest`, like movsbl
+fn extend(f: &mut fmt::Formatter, mnemonic: &str, dest: &Address, src: &Address) -> fmt::Result {
+    write!(
+        f,
+        "{}{}{} {}, {}",
+        mnemonic,
+        suffix(src.size()),
+        suffix(dest.size()),
+        Operand(src),
+        Operand(dest)
+    )
+}
+
+fn data(f: &mut fmt::Formatter, data: &Data) -> fmt::Result {
+    fn list<T: Display>(f: &mut fmt::Formatter, directive: &str, values: &[T]) -> fmt::Result {
+        write!(f, "{} ", directive)?;
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", value)?;
+        }
+        Ok(())
+    }
+    match data {
+        Data::Byte(bytes) => list(f, ".byte", bytes),
+        Data::Dword(values) => list(f, ".long", values),
+        Data::Qword(values) => list(f, ".quad", values),
+        Data::Reserve(size) | Data::Zero(size) => write!(f, ".zero {}", size),
+    }
+}
+
+pub fn fmt(instruction: &Instruction, f: &mut fmt::Formatter) -> fmt::Result {
+    let plt = platform::plt_suffix(Syntax::Att);
+    match instruction {
+        Instruction::Global(label) => write!(f, ".globl {}", label),
+        Instruction::Extern(label) => write!(f, ".extern {}", label),
+        Instruction::Section(Section::NoteGnuStack) => {
+            write!(f, ".section {},\"\",@progbits", Section::NoteGnuStack)
+        }
+        Instruction::Section(section) => write!(f, ".section {}", section),
+        Instruction::Align(bytes) => write!(f, ".balign {}, 0", bytes),
+        Instruction::AlignB(bytes) => write!(f, ".balign {}", bytes),
+        Instruction::Data(contents) => data(f, contents),
+        Instruction::Label(label) => write!(f, "{}:", label),
+        Instruction::Push(reg) => write!(f, "pushq %{}", reg),
+        // NASM moves a small positive immediate into the 32 bit half of
+        // the register, which clears the upper half anyway, in fewer bytes,
+        // so the same goes here to get the same code
+        Instruction::Mov(Address::Register(reg), Address::Immediate(val))
+            if reg.size() == 8 && (0..=i64::from(u32::MAX)).contains(val) =>
+        {
+            write!(f, "movl ${}, %{}", val, reg.resize(4))
+        }
+        Instruction::Mov(dest, src) => binary(f, "mov", dest, src),
+        Instruction::Movsx(dest, src) => extend(f, "movs", dest, src),
+        Instruction::Movzx(dest, src) => extend(f, "movz", dest, src),
+        Instruction::Movsxd(dest, src) => extend(f, "movs", dest, src),
+        Instruction::Add(dest, src) => binary(f, "add", dest, src),
+        Instruction::Sub(dest, src) => binary(f, "sub", dest, src),
+        Instruction::Imul(dest, src) => binary(f, "imul", dest, src),
+        Instruction::Neg(dest) => unary(f, "neg", dest),
+        Instruction::Not(dest) => unary(f, "not", dest),
+        Instruction::And(dest, src) => binary(f, "and", dest, src),
+        Instruction::Or(dest, src) => binary(f, "or", dest, src),
+        Instruction::Xor(dest, src) => binary(f, "xor", dest, src),
+        Instruction::Shl(dest, count) => shift(f, "shl", dest, count),
+        Instruction::Sar(dest, count) => shift(f, "sar", dest, count),
+        Instruction::Shr(dest, count) => shift(f, "shr", dest, count),
+        Instruction::Cdq => write!(f, "cltd"),
+        Instruction::Cqo => write!(f, "cqto"),
+        Instruction::Idiv(divisor) => unary(f, "idiv", divisor),
+        Instruction::Div(divisor) => unary(f, "div", divisor),
+        Instruction::Lea(dest, src) => binary(f, "lea", dest, src),
+        Instruction::Cmp(lhs, rhs) => binary(f, "cmp", lhs, rhs),
+        Instruction::Call(label, _) => write!(f, "call {}", label),
+        Instruction::CallExternal(label, _) => write!(f, "call {}{}", label, plt),
+        // near, like the NASM syntax says
+        Instruction::TailCall(label, _) => write!(f, "{{disp32}} jmp {}", label),
+        Instruction::TailCallExternal(label, _) => write!(f, "jmp {}{}", label, plt),
+        Instruction::Jmp(label) => write!(f, "jmp {}", label),
+        Instruction::Jcc(condition, label) => write!(f, "j{} {}", condition, label),
+        Instruction::Setcc(condition, reg) => write!(f, "set{} %{}", condition, reg),
+        Instruction::Pop(reg) => write!(f, "popq %{}", reg),
+        Instruction::Ret => write!(f, "ret"),
+        Instruction::Comment(comment) => write!(f, "# {}", comment),
+    }
+}
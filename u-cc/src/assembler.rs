@@ -0,0 +1,275 @@
+//! An assembler for the instructions code generation makes, so `u-cc -c`
+//! can write an object file itself instead of going through NASM. Jumps
+//! start out short and are made long until every target is in reach,
+//! references to labels in the same section are filled in straight away,
+//! and the rest are left to the linker as relocations.
+
+mod encoding;
+
+use crate::asm::{Condition, Data, Instruction, Section};
+use crate::elf;
+use encoding::Encoded;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// a piece of a section, whose size may depend on where it ends up
+enum Item {
+    Code(Encoded),
+    /// a jump to a label in the same section, which is short if the label
+    /// is close enough
+    Jump {
+        condition: Option<Condition>,
+        label: String,
+        short: bool,
+    },
+    /// padding up to a multiple of the alignment
+    Align(usize),
+    /// space in .bss, which takes none in the file
+    Reserve(usize),
+    Label(String),
+}
+
+impl Item {
+    fn size(&self, offset: usize) -> usize {
+        match self {
+            Item::Code(encoded) => encoded.bytes.len(),
+            Item::Jump { short: true, .. } => 2,
+            Item::Jump {
+                condition: None, ..
+            } => 5,
+            Item::Jump { .. } => 6,
+            Item::Align(align) => (align - offset % align) % align,
+            Item::Reserve(size) => *size,
+            Item::Label(_) => 0,
+        }
+    }
+}
+
+/// where each item starts, and where each label is
+fn layout(items: &[Item]) -> (Vec<usize>, HashMap<&str, usize>) {
+    let mut offsets = vec![];
+    let mut labels = HashMap::new();
+    let mut offset = 0;
+    for item in items {
+        offsets.push(offset);
+        if let Item::Label(label) = item {
+            labels.insert(label.as_str(), offset);
+        }
+        offset += item.size(offset);
+    }
+    offsets.push(offset);
+    (offsets, labels)
+}
+
+/// makes the jumps that can't reach their labels long, until they all can.
+/// Jumps only ever get longer, so this ends.
+fn relax(items: &mut [Item]) {
+    loop {
+        let (offsets, labels) = layout(items);
+        let too_far: Vec<usize> = items
+            .iter()
+            .enumerate()
+            .filter(|(i, item)| match item {
+                Item::Jump {
+                    label, short: true, ..
+                } => {
+                    let end = offsets[*i] as i64 + 2;
+                    let target = labels[label.as_str()] as i64;
+                    !(-128..128).contains(&(target - end))
+                }
+                _ => false,
+            })
+            .map(|(i, _)| i)
+            .collect();
+        if too_far.is_empty() {
+            return;
+        }
+        for i in too_far {
+            if let Item::Jump { short, .. } = &mut items[i] {
+                *short = false;
+            }
+        }
+    }
+}
+
+/// the symbols of the object, each added the first time it comes up
+#[derive(Default)]
+struct Symbols {
+    symbols: Vec<elf::Symbol>,
+    indices: HashMap<String, usize>,
+}
+
+impl Symbols {
+    fn add(&mut self, symbol: elf::Symbol) -> usize {
+        let index = self.symbols.len();
+        self.indices.insert(symbol.name.clone(), index);
+        self.symbols.push(symbol);
+        index
+    }
+}
+
+fn properties(section: Section) -> (bool, bool, usize) {
+    // writable, executable, and the alignment NASM gives them
+    match section {
+        Section::Text => (false, true, 16),
+        Section::Data | Section::Bss => (true, false, 4),
+        Section::Rodata => (false, false, 4),
+        Section::NoteGnuStack => unreachable!("{} is left to the ELF writer", section),
+    }
+}
+
+/// the object file for the instructions
+pub fn assemble(instructions: &[Instruction]) -> elf::Object {
+    let mut globals = HashSet::new();
+    let mut sections: BTreeMap<Section, Vec<Item>> = BTreeMap::new();
+    let mut current = Section::Text;
+    for instruction in instructions {
+        let item = match instruction {
+            Instruction::Global(label) => {
+                globals.insert(label.as_str());
+                continue;
+            }
+            // anything not defined here is, whether it's declared or not
+            Instruction::Extern(_) | Instruction::Comment(_) => continue,
+            // the ELF writer always adds one
+            Instruction::Section(Section::NoteGnuStack) => continue,
+            Instruction::Section(section) => {
+                current = *section;
+                sections.entry(current).or_default();
+                continue;
+            }
+            Instruction::Align(align) | Instruction::AlignB(align) => Item::Align(*align),
+            Instruction::Data(Data::Reserve(size)) => Item::Reserve(*size),
+            Instruction::Data(data) => Item::Code(encoding::data(data)),
+            Instruction::Label(label) => Item::Label(label.clone()),
+            Instruction::Jmp(label) => Item::Jump {
+                condition: None,
+                label: label.clone(),
+                short: true,
+            },
+            Instruction::Jcc(condition, label) => Item::Jump {
+                condition: Some(*condition),
+                label: label.clone(),
+                short: true,
+            },
+            _ => Item::Code(encoding::encode(instruction)),
+        };
+        sections.entry(current).or_default().push(item);
+    }
+
+    // where every label is, by section
+    let mut object = elf::Object::default();
+    let mut symbols = Symbols::default();
+    let mut layouts = vec![];
+    for (index, (section, items)) in sections.iter_mut().enumerate() {
+        relax(items);
+        let (offsets, labels) = layout(items);
+        for item in items.iter() {
+            if let Item::Label(label) = item {
+                // like the ones GNU as makes, .L labels are only for the
+                // assembler
+                if !label.starts_with(".L") {
+                    symbols.add(elf::Symbol {
+                        name: label.clone(),
+                        section: Some(index),
+                        value: labels[label.as_str()] as u64,
+                        global: globals.contains(label.as_str()),
+                    });
+                }
+            }
+        }
+        let labels: HashMap<String, usize> = labels
+            .into_iter()
+            .map(|(label, offset)| (label.to_string(), offset))
+            .collect();
+        layouts.push((*section, offsets, labels));
+    }
+    let section_of = |name: &str| {
+        layouts
+            .iter()
+            .position(|(_, _, labels)| labels.contains_key(name))
+    };
+
+    for (index, (section, items)) in sections.iter().enumerate() {
+        let (_, offsets, labels) = &layouts[index];
+        let (writable, executable, align) = properties(*section);
+        let align = items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Align(align) => Some(*align),
+                _ => None,
+            })
+            .fold(align, usize::max);
+        let mut contents = vec![];
+        let mut relocations = vec![];
+        for (i, item) in items.iter().enumerate() {
+            let offset = offsets[i];
+            match item {
+                Item::Code(encoded) => {
+                    let mut bytes = encoded.bytes.clone();
+                    for fixup in &encoded.fixups {
+                        let position = offset + fixup.offset;
+                        // a relative reference within the section is the
+                        // same wherever the section goes
+                        if fixup.kind != elf::R_X86_64_64
+                            && section_of(&fixup.symbol) == Some(index)
+                        {
+                            let value =
+                                labels[&fixup.symbol] as i64 + fixup.addend - position as i64;
+                            bytes[fixup.offset..fixup.offset + 4]
+                                .copy_from_slice(&(value as i32).to_le_bytes());
+                            continue;
+                        }
+                        let symbol = match symbols.indices.get(&fixup.symbol) {
+                            Some(&symbol) => symbol,
+                            None => {
+                                let section = section_of(&fixup.symbol);
+                                let value =
+                                    section.map_or(0, |section| layouts[section].2[&fixup.symbol]);
+                                symbols.add(elf::Symbol {
+                                    name: fixup.symbol.clone(),
+                                    section,
+                                    value: value as u64,
+                                    global: section.is_none(),
+                                })
+                            }
+                        };
+                        relocations.push(elf::Relocation {
+                            offset: position as u64,
+                            symbol,
+                            kind: fixup.kind,
+                            addend: fixup.addend,
+                        });
+                    }
+                    contents.extend(bytes);
+                }
+                Item::Jump {
+                    condition,
+                    label,
+                    short,
+                } => {
+                    let end = offsets[i + 1];
+                    let target = labels[label.as_str()];
+                    contents.extend(encoding::jump(
+                        *condition,
+                        *short,
+                        (target as i64 - end as i64) as i32,
+                    ));
+                }
+                Item::Align(_) | Item::Reserve(_) if *section == Section::Bss => {}
+                Item::Align(_) | Item::Reserve(_) => contents.resize(offsets[i + 1], 0),
+                Item::Label(_) => {}
+            }
+        }
+        object.sections.push(elf::Section {
+            name: section.to_string(),
+            contents,
+            size: offsets[items.len()],
+            writable,
+            executable,
+            align,
+            relocations,
+        });
+    }
+    object.symbols = symbols.symbols;
+    object
+}
@@ -0,0 +1,581 @@
+//! Encoding instructions as x86-64 machine code. Where there's a choice of
+//! encodings, this makes the one NASM does, so an object comes out the same
+//! whichever wrote it: the shortest immediate, the short forms for the
+//! accumulator, and register to register moves in the r/m, reg direction.
+
+use crate::asm::{Address, Condition, Data, DataValue, Instruction, Register};
+use crate::elf;
+use std::convert::TryFrom;
+
+/// a reference to a symbol, which can only be filled in once the symbol
+/// has an address
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixup {
+    /// where the value goes, from the start of the instruction
+    pub offset: usize,
+    pub symbol: String,
+    /// the ELF relocation that fills it in
+    pub kind: u32,
+    pub addend: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Encoded {
+    pub bytes: Vec<u8>,
+    pub fixups: Vec<Fixup>,
+}
+
+/// what goes in the reg field of the ModRM byte
+#[derive(Clone, Copy)]
+enum Reg {
+    Register(Register),
+    /// part of the opcode, written /digit in the manuals
+    Digit(u8),
+}
+
+/// spl, bpl, sil and dil are only there with a REX prefix, without which
+/// their numbers mean ah, ch, dh and bh
+fn needs_rex(register: Register) -> bool {
+    register.size() == 1 && (4..8).contains(&register.number())
+}
+
+/// the opcode for byte operands, or the one after it for wider operands
+fn sized(opcode: u8, size: usize) -> u8 {
+    match size {
+        1 => opcode,
+        _ => opcode + 1,
+    }
+}
+
+fn fits_byte(val: i64) -> bool {
+    i8::try_from(val).is_ok()
+}
+
+fn register(operand: &Address) -> Register {
+    match operand {
+        Address::Register(register) => *register,
+        _ => unreachable!("{} isn't a register", operand),
+    }
+}
+
+fn operand_size(dest: &Address, src: &Address) -> usize {
+    dest.size().or_else(|| src.size()).unwrap_or(8)
+}
+
+/// an instruction with a ModRM byte, whose operands are `size` bytes wide,
+/// and which ends with `immediate`
+fn modrm(opcode: &[u8], size: usize, reg: Reg, rm: &Address, immediate: &[u8]) -> Encoded {
+    let reg_number = match reg {
+        Reg::Register(register) => register.number(),
+        Reg::Digit(digit) => digit,
+    };
+    let mut rex = 0;
+    if size == 8 {
+        rex |= 8;
+    }
+    if reg_number >= 8 {
+        rex |= 4;
+    }
+    let mut byte_register = matches!(reg, Reg::Register(register) if needs_rex(register));
+    // the ModRM byte and everything up to the immediate
+    let mut tail = vec![];
+    let mut fixup = None;
+    match rm {
+        Address::Register(register) => {
+            if register.number() >= 8 {
+                rex |= 1;
+            }
+            byte_register |= needs_rex(*register);
+            tail.push(0xc0 | (reg_number & 7) << 3 | register.number() & 7);
+        }
+        Address::Indirect(indirect) => {
+            let displacement = indirect.displacement();
+            match indirect.base() {
+                Address::Register(base) => {
+                    if base.number() >= 8 {
+                        rex |= 1;
+                    }
+                    let low = base.number() & 7;
+                    // rbp and r13 can't go without a displacement, since
+                    // that's how rip relative addresses are written
+                    let mode = if displacement == 0 && low != 5 {
+                        0
+                    } else if fits_byte(displacement.into()) {
+                        1
+                    } else {
+                        2
+                    };
+                    tail.push(mode << 6 | (reg_number & 7) << 3 | low);
+                    // and rsp and r12 need a SIB byte
+                    if low == 4 {
+                        tail.push(0x24);
+                    }
+                    match mode {
+                        1 => tail.push(displacement as u8),
+                        2 => tail.extend_from_slice(&displacement.to_le_bytes()),
+                        _ => {}
+                    }
+                }
+                Address::Label(label) => {
+                    tail.push((reg_number & 7) << 3 | 5);
+                    // relative to the end of the instruction
+                    fixup = Some(Fixup {
+                        offset: tail.len(),
+                        symbol: label.clone(),
+                        kind: elf::R_X86_64_PC32,
+                        addend: i64::from(displacement) - 4 - immediate.len() as i64,
+                    });
+                    tail.extend_from_slice(&[0; 4]);
+                }
+                base => unreachable!("can't address memory through {}", base),
+            }
+        }
+        _ => unreachable!("{} isn't a register or memory", rm),
+    }
+    let mut encoded = Encoded::default();
+    if size == 2 {
+        encoded.bytes.push(0x66);
+    }
+    if rex != 0 || byte_register {
+        encoded.bytes.push(0x40 | rex);
+    }
+    encoded.bytes.extend_from_slice(opcode);
+    if let Some(mut fixup) = fixup {
+        fixup.offset += encoded.bytes.len();
+        encoded.fixups.push(fixup);
+    }
+    encoded.bytes.extend(tail);
+    encoded.bytes.extend_from_slice(immediate);
+    encoded
+}
+
+/// an instruction with the register in the low bits of the opcode, like
+/// push
+fn with_register(opcode: u8, register: Register, wide: bool, immediate: &[u8]) -> Encoded {
+    let mut rex = 0;
+    if wide {
+        rex |= 8;
+    }
+    if register.number() >= 8 {
+        rex |= 1;
+    }
+    let mut bytes = vec![];
+    if rex != 0 || needs_rex(register) {
+        bytes.push(0x40 | rex);
+    }
+    bytes.push(opcode + (register.number() & 7));
+    bytes.extend_from_slice(immediate);
+    Encoded {
+        bytes,
+        fixups: vec![],
+    }
+}
+
+/// an opcode followed by a 4 byte offset to the symbol from the end of
+/// the instruction
+fn relative(opcode: &[u8], symbol: &str, kind: u32) -> Encoded {
+    let mut bytes = opcode.to_vec();
+    bytes.extend_from_slice(&[0; 4]);
+    Encoded {
+        bytes,
+        fixups: vec![Fixup {
+            offset: opcode.len(),
+            symbol: symbol.to_string(),
+            kind,
+            addend: -4,
+        }],
+    }
+}
+
+fn mov(dest: &Address, src: &Address) -> Encoded {
+    let size = operand_size(dest, src);
+    match (dest, src) {
+        (Address::Register(register), Address::Immediate(val)) => {
+            let val = *val;
+            match size {
+                1 => with_register(0xb0, *register, false, &[val as u8]),
+                // a positive value zero extends, so it can go in the dword
+                // half, in fewer bytes
+                8 if (0..=i64::from(u32::MAX)).contains(&val) => {
+                    with_register(0xb8, register.resize(4), false, &(val as u32).to_le_bytes())
+                }
+                8 if i32::try_from(val).is_ok() => {
+                    modrm(&[0xc7], 8, Reg::Digit(0), dest, &(val as i32).to_le_bytes())
+                }
+                8 => with_register(0xb8, *register, true, &val.to_le_bytes()),
+                _ => with_register(0xb8, *register, false, &(val as i32).to_le_bytes()),
+            }
+        }
+        (_, Address::Immediate(val)) => match size {
+            1 => modrm(&[0xc6], 1, Reg::Digit(0), dest, &[*val as u8]),
+            _ => modrm(
+                &[0xc7],
+                size,
+                Reg::Digit(0),
+                dest,
+                &(*val as i32).to_le_bytes(),
+            ),
+        },
+        (_, Address::Register(src)) => {
+            modrm(&[sized(0x88, size)], size, Reg::Register(*src), dest, &[])
+        }
+        _ => modrm(
+            &[sized(0x8a, size)],
+            size,
+            Reg::Register(register(dest)),
+            src,
+            &[],
+        ),
+    }
+}
+
+/// add, or, and, sub, xor and cmp, which differ only in the digit
+fn arithmetic(digit: u8, dest: &Address, src: &Address) -> Encoded {
+    let size = operand_size(dest, src);
+    let accumulator = matches!(dest, Address::Register(register) if register.number() == 0);
+    match src {
+        Address::Immediate(val) => {
+            let val = *val;
+            if size == 1 && accumulator {
+                Encoded {
+                    bytes: vec![0x04 + digit * 8, val as u8],
+                    fixups: vec![],
+                }
+            } else if size == 1 {
+                modrm(&[0x80], 1, Reg::Digit(digit), dest, &[val as u8])
+            } else if fits_byte(val) {
+                modrm(&[0x83], size, Reg::Digit(digit), dest, &[val as u8])
+            } else if accumulator {
+                let mut bytes = match size {
+                    8 => vec![0x48],
+                    _ => vec![],
+                };
+                bytes.push(0x05 + digit * 8);
+                bytes.extend_from_slice(&(val as i32).to_le_bytes());
+                Encoded {
+                    bytes,
+                    fixups: vec![],
+                }
+            } else {
+                modrm(
+                    &[0x81],
+                    size,
+                    Reg::Digit(digit),
+                    dest,
+                    &(val as i32).to_le_bytes(),
+                )
+            }
+        }
+        Address::Register(src) => modrm(
+            &[sized(digit * 8, size)],
+            size,
+            Reg::Register(*src),
+            dest,
+            &[],
+        ),
+        _ => modrm(
+            &[sized(digit * 8 + 2, size)],
+            size,
+            Reg::Register(register(dest)),
+            src,
+            &[],
+        ),
+    }
+}
+
+/// neg, not, div and idiv, which differ only in the digit
+fn unary(digit: u8, operand: &Address) -> Encoded {
+    let size = operand.size().unwrap_or(8);
+    modrm(&[sized(0xf6, size)], size, Reg::Digit(digit), operand, &[])
+}
+
+fn shift(digit: u8, dest: &Address, count: &Address) -> Encoded {
+    let size = dest.size().unwrap_or(8);
+    match count {
+        Address::Immediate(1) => modrm(&[sized(0xd0, size)], size, Reg::Digit(digit), dest, &[]),
+        Address::Immediate(count) => modrm(
+            &[sized(0xc0, size)],
+            size,
+            Reg::Digit(digit),
+            dest,
+            &[*count as u8],
+        ),
+        // by cl
+        _ => modrm(&[sized(0xd2, size)], size, Reg::Digit(digit), dest, &[]),
+    }
+}
+
+fn imul(dest: &Address, src: &Address) -> Encoded {
+    let size = operand_size(dest, src);
+    let reg = Reg::Register(register(dest));
+    match src {
+        Address::Immediate(val) if fits_byte(*val) => {
+            modrm(&[0x6b], size, reg, dest, &[*val as u8])
+        }
+        Address::Immediate(val) => modrm(&[0x69], size, reg, dest, &(*val as i32).to_le_bytes()),
+        _ => modrm(&[0x0f, 0xaf], size, reg, src, &[]),
+    }
+}
+
+/// the low four bits of the opcodes that test the condition
+fn condition_code(condition: Condition) -> u8 {
+    match condition {
+        Condition::Below => 0x2,
+        Condition::AboveEqual => 0x3,
+        Condition::Equal => 0x4,
+        Condition::NotEqual => 0x5,
+        Condition::BelowEqual => 0x6,
+        Condition::Above => 0x7,
+        Condition::Less => 0xc,
+        Condition::GreaterEqual => 0xd,
+        Condition::LessEqual => 0xe,
+        Condition::Greater => 0xf,
+    }
+}
+
+/// a jmp, or a jcc with the condition, with the offset to the target from
+/// the end of the instruction in the last byte if it's short, or the last
+/// four if it's not
+pub fn jump(condition: Option<Condition>, short: bool, offset: i32) -> Vec<u8> {
+    let mut bytes = match (condition, short) {
+        (None, true) => vec![0xeb],
+        (None, false) => vec![0xe9],
+        (Some(condition), true) => vec![0x70 | condition_code(condition)],
+        (Some(condition), false) => vec![0x0f, 0x80 | condition_code(condition)],
+    };
+    match short {
+        true => bytes.push(offset as u8),
+        false => bytes.extend_from_slice(&offset.to_le_bytes()),
+    }
+    bytes
+}
+
+/// the bytes of initialized data
+pub fn data(data: &Data) -> Encoded {
+    let mut encoded = Encoded::default();
+    match data {
+        Data::Byte(bytes) => encoded.bytes.extend_from_slice(bytes),
+        Data::Dword(values) => {
+            for value in values {
+                encoded.bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        Data::Qword(values) => {
+            for value in values {
+                match value {
+                    DataValue::Int(val) => encoded.bytes.extend_from_slice(&val.to_le_bytes()),
+                    DataValue::Label(label) => {
+                        encoded.fixups.push(Fixup {
+                            offset: encoded.bytes.len(),
+                            symbol: label.clone(),
+                            kind: elf::R_X86_64_64,
+                            addend: 0,
+                        });
+                        encoded.bytes.extend_from_slice(&[0; 8]);
+                    }
+                }
+            }
+        }
+        Data::Reserve(size) | Data::Zero(size) => encoded.bytes.resize(*size, 0),
+    }
+    encoded
+}
+
+/// the machine code for an instruction, other than jumps to labels, which
+/// are shorter when the label is close, and directives
+pub fn encode(instruction: &Instruction) -> Encoded {
+    let simple = |bytes: &[u8]| Encoded {
+        bytes: bytes.to_vec(),
+        fixups: vec![],
+    };
+    match instruction {
+        Instruction::Push(register) => with_register(0x50, *register, false, &[]),
+        Instruction::Pop(register) => with_register(0x58, *register, false, &[]),
+        Instruction::Mov(dest, src) => mov(dest, src),
+        Instruction::Movsx(dest, src) => {
+            let size = dest.size().unwrap_or(8);
+            modrm(&[0x0f, 0xbe], size, Reg::Register(register(dest)), src, &[])
+        }
+        Instruction::Movzx(dest, src) => {
+            let size = dest.size().unwrap_or(8);
+            modrm(&[0x0f, 0xb6], size, Reg::Register(register(dest)), src, &[])
+        }
+        Instruction::Movsxd(dest, src) => {
+            modrm(&[0x63], 8, Reg::Register(register(dest)), src, &[])
+        }
+        Instruction::Add(dest, src) => arithmetic(0, dest, src),
+        Instruction::Or(dest, src) => arithmetic(1, dest, src),
+        Instruction::And(dest, src) => arithmetic(4, dest, src),
+        Instruction::Sub(dest, src) => arithmetic(5, dest, src),
+        Instruction::Xor(dest, src) => arithmetic(6, dest, src),
+        Instruction::Cmp(lhs, rhs) => arithmetic(7, lhs, rhs),
+        Instruction::Imul(dest, src) => imul(dest, src),
+        Instruction::Not(dest) => unary(2, dest),
+        Instruction::Neg(dest) => unary(3, dest),
+        Instruction::Div(divisor) => unary(6, divisor),
+        Instruction::Idiv(divisor) => unary(7, divisor),
+        Instruction::Shl(dest, count) => shift(4, dest, count),
+        Instruction::Shr(dest, count) => shift(5, dest, count),
+        Instruction::Sar(dest, count) => shift(7, dest, count),
+        Instruction::Cdq => simple(&[0x99]),
+        Instruction::Cqo => simple(&[0x48, 0x99]),
+        Instruction::Lea(dest, src) => {
+            let size = dest.size().unwrap_or(8);
+            modrm(&[0x8d], size, Reg::Register(register(dest)), src, &[])
+        }
+        Instruction::Call(label, _) => relative(&[0xe8], label, elf::R_X86_64_PC32),
+        Instruction::CallExternal(label, _) => relative(&[0xe8], label, elf::R_X86_64_PLT32),
+        Instruction::TailCall(label, _) => relative(&[0xe9], label, elf::R_X86_64_PC32),
+        Instruction::TailCallExternal(label, _) => relative(&[0xe9], label, elf::R_X86_64_PLT32),
+        Instruction::Setcc(condition, register) => modrm(
+            &[0x0f, 0x90 | condition_code(*condition)],
+            1,
+            Reg::Digit(0),
+            &Address::Register(*register),
+            &[],
+        ),
+        Instruction::Ret => simple(&[0xc3]),
+        Instruction::Global(_)
+        | Instruction::Extern(_)
+        | Instruction::Section(_)
+        | Instruction::Align(_)
+        | Instruction::AlignB(_)
+        | Instruction::Data(_)
+        | Instruction::Label(_)
+        | Instruction::Jmp(_)
+        | Instruction::Jcc(..)
+        | Instruction::Comment(_) => unreachable!("{} isn't encoded on its own", instruction),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, jump, Fixup};
+    use crate::asm::{Address, Condition, IndirectAddress, Instruction, Register::*};
+    use crate::elf;
+
+    fn bytes(instruction: Instruction) -> Vec<u8> {
+        let encoded = encode(&instruction);
+        assert!(encoded.fixups.is_empty());
+        encoded.bytes
+    }
+
+    fn local(offset: i32) -> IndirectAddress {
+        IndirectAddress::offset(Box::new(Rbp.into()), offset)
+    }
+
+    #[test]
+    fn encodes_register_to_register_moves() {
+        assert_eq!(
+            bytes(Instruction::Mov(Rax.into(), Rcx.into())),
+            [0x48, 0x89, 0xc8]
+        );
+        assert_eq!(
+            bytes(Instruction::Mov(R12d.into(), Eax.into())),
+            [0x41, 0x89, 0xc4]
+        );
+        assert_eq!(
+            bytes(Instruction::Mov(Sil.into(), Al.into())),
+            [0x40, 0x88, 0xc6]
+        );
+    }
+
+    #[test]
+    fn encodes_immediates_in_the_fewest_bytes() {
+        assert_eq!(
+            bytes(Instruction::Mov(Rax.into(), 5.into())),
+            [0xb8, 5, 0, 0, 0]
+        );
+        assert_eq!(
+            bytes(Instruction::Mov(Rax.into(), (-1).into())),
+            [0x48, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(
+            bytes(Instruction::Mov(R9.into(), 0x1_0000_0000.into())),
+            [0x49, 0xb9, 0, 0, 0, 0, 1, 0, 0, 0]
+        );
+        assert_eq!(
+            bytes(Instruction::Sub(Rsp.into(), 16.into())),
+            [0x48, 0x83, 0xec, 16]
+        );
+        assert_eq!(
+            bytes(Instruction::Add(Eax.into(), 1000.into())),
+            [0x05, 0xe8, 0x03, 0, 0]
+        );
+        assert_eq!(
+            bytes(Instruction::Cmp(Ecx.into(), 1000.into())),
+            [0x81, 0xf9, 0xe8, 0x03, 0, 0]
+        );
+    }
+
+    #[test]
+    fn encodes_memory_operands() {
+        assert_eq!(
+            bytes(Instruction::Mov(local(-8).dword().into(), Eax.into())),
+            [0x89, 0x45, 0xf8]
+        );
+        assert_eq!(
+            bytes(Instruction::Mov(local(-8).qword().into(), 3.into())),
+            [0x48, 0xc7, 0x45, 0xf8, 3, 0, 0, 0]
+        );
+        let stack = IndirectAddress::offset(Box::new(Rsp.into()), 8);
+        assert_eq!(
+            bytes(Instruction::Mov(Rax.into(), stack.qword().into())),
+            [0x48, 0x8b, 0x44, 0x24, 0x08]
+        );
+        let pointer = IndirectAddress::indirect(Box::new(R13.into()));
+        assert_eq!(
+            bytes(Instruction::Movsx(Eax.into(), pointer.byte().into())),
+            [0x41, 0x0f, 0xbe, 0x45, 0]
+        );
+        assert_eq!(
+            bytes(Instruction::Lea(Rdi.into(), local(-400).into())),
+            [0x48, 0x8d, 0xbd, 0x70, 0xfe, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn encodes_rip_relative_addresses_with_a_fixup() {
+        let counter = IndirectAddress::rip_relative("counter".to_string()).add_offset(4);
+        let encoded = encode(&Instruction::Mov(counter.dword().into(), 5.into()));
+        assert_eq!(encoded.bytes, [0xc7, 0x05, 0, 0, 0, 0, 5, 0, 0, 0]);
+        assert_eq!(
+            encoded.fixups,
+            [Fixup {
+                offset: 2,
+                symbol: "counter".to_string(),
+                kind: elf::R_X86_64_PC32,
+                addend: -4,
+            }]
+        );
+    }
+
+    #[test]
+    fn encodes_shifts_and_conditions() {
+        assert_eq!(
+            bytes(Instruction::Shl(Rax.into(), Cl.into())),
+            [0x48, 0xd3, 0xe0]
+        );
+        assert_eq!(bytes(Instruction::Sar(Eax.into(), 1.into())), [0xd1, 0xf8]);
+        assert_eq!(
+            bytes(Instruction::Shr(Edx.into(), 3.into())),
+            [0xc1, 0xea, 3]
+        );
+        assert_eq!(
+            bytes(Instruction::Setcc(Condition::Less, Dil)),
+            [0x40, 0x0f, 0x9c, 0xc7]
+        );
+        assert_eq!(jump(Some(Condition::Equal), true, -2), [0x74, 0xfe]);
+        assert_eq!(jump(None, false, 256), [0xe9, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn encodes_pushes_of_extended_registers() {
+        assert_eq!(bytes(Instruction::Push(R12)), [0x41, 0x54]);
+        assert_eq!(bytes(Instruction::Pop(Rbp)), [0x5d]);
+        assert_eq!(
+            bytes(Instruction::Idiv(Address::Register(R8))),
+            [0x49, 0xf7, 0xf8]
+        );
+    }
+}
@@ -1,12 +1,20 @@
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program {
-    pub functions: Vec<FunctionDefinition>,
+    pub items: Vec<ExternalDeclaration>,
 }
 
+/// anything that can appear at file scope
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExternalDeclaration {
+    FunctionDefinition(FunctionDefinition),
+    Declaration(Declaration),
+}
+
+/// the name can only be left out in a prototype
 #[derive(Debug, Clone, PartialEq)]
 pub struct FunctionParameter {
     pub ty: Type,
-    pub name: String,
+    pub name: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -14,55 +22,575 @@ pub struct FunctionDefinition {
     pub return_type: Type,
     pub name: String,
     pub parameters: Vec<FunctionParameter>,
+    /// whether the parameters end with `...`
+    pub variadic: bool,
+    pub storage_class: Option<StorageClass>,
+    /// whether it was declared `inline`
+    pub is_inline: bool,
     pub body: Vec<Statement>,
 }
 
 impl FunctionDefinition {
+    /// `int f(int a) { ... }` parses as a declarator of function type
+    /// followed by a body
+    pub fn new(
+        specifiers: DeclarationSpecifiers,
+        mut declarator: Declarator,
+        body: Vec<Statement>,
+    ) -> Result<Self, &'static str> {
+        // a definition always says what its parameters are, so `int f() {}`
+        // is taken to mean `int f(void) {}`
+        let (parameters, variadic) = match declarator.derived.pop() {
+            Some(DerivedDeclarator::Function {
+                parameters,
+                variadic,
+            }) => (parameters.unwrap_or_default(), variadic),
+            _ => return Err("function definition declared without a parameter list"),
+        };
+        if parameters.iter().any(|param| param.name.is_none()) {
+            return Err("parameter name omitted in function definition");
+        }
+        Ok(FunctionDefinition {
+            return_type: declarator.type_of(&specifiers.ty),
+            name: declarator.name,
+            parameters,
+            variadic,
+            storage_class: specifiers.storage_class,
+            is_inline: specifiers.is_inline,
+            body,
+        })
+    }
     pub fn type_of(&self) -> Type {
         Type::Function {
             return_type: Box::new(self.return_type.clone()),
-            arguments: self.parameters.iter().map(|arg| arg.ty.clone()).collect(),
+            arguments: Some(self.parameters.iter().map(|arg| arg.ty.clone()).collect()),
+            variadic: self.variadic,
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
+    Void,
+    Char,
+    UnsignedChar,
     Int,
+    UnsignedInt,
+    /// long long is the same width, so it shares this representation
+    Long,
+    UnsignedLong,
     Function {
         return_type: Box<Type>,
-        arguments: Vec<Type>,
+        /// None for a declaration without a prototype like `int f()`, which
+        /// says nothing about the arguments
+        arguments: Option<Vec<Type>>,
+        variadic: bool,
     },
     Pointer(Box<Type>),
+    /// the length is None for `int a[]` until an initializer completes it
+    Array(Box<Type>, Option<usize>),
+    Struct(StructType),
 }
 
 impl Type {
     pub fn stack_size(&self) -> usize {
-        use std::usize;
         match self {
             // ok I mean this is probably the worst way to do this but whatever.
-            Type::Int => 4,
+            Type::Char | Type::UnsignedChar => 1,
+            // like gcc, so arithmetic on void pointers works in bytes
+            Type::Void => 1,
+            Type::Int | Type::UnsignedInt => 4,
+            Type::Long | Type::UnsignedLong => 8,
             Type::Function { .. } => 0,
             Type::Pointer(_) => 8,
+            Type::Array(elem, len) => elem.stack_size() * len.unwrap_or(0),
+            Type::Struct(struct_type) => struct_type.layout().size,
+        }
+    }
+    pub fn align(&self) -> usize {
+        match self {
+            Type::Array(elem, _) => elem.align(),
+            Type::Struct(struct_type) => struct_type.layout().align,
+            Type::Function { .. } => 1,
+            scalar => scalar.stack_size(),
+        }
+    }
+    /// whether values of this type fit in a register
+    pub fn is_scalar(&self) -> bool {
+        !matches!(
+            self,
+            Type::Void | Type::Array(..) | Type::Struct(_) | Type::Function { .. }
+        )
+    }
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            Type::Char
+                | Type::UnsignedChar
+                | Type::Int
+                | Type::UnsignedInt
+                | Type::Long
+                | Type::UnsignedLong
+        )
+    }
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Type::Char | Type::Int | Type::Long)
+    }
+    /// the SysV va_list, an array of one struct so that it's passed by
+    /// reference:
+    /// `struct { unsigned gp_offset, fp_offset; void *overflow_arg_area, *reg_save_area; }[1]`
+    pub fn va_list() -> Type {
+        let member = |name: &str, ty| StructMember {
+            name: name.to_string(),
+            ty,
+        };
+        let void_pointer = Type::Pointer(Box::new(Type::Void));
+        let tag = StructType {
+            tag: Some("__va_list_tag".to_string()),
+            members: Some(vec![
+                member("gp_offset", Type::UnsignedInt),
+                member("fp_offset", Type::UnsignedInt),
+                member("overflow_arg_area", void_pointer.clone()),
+                member("reg_save_area", void_pointer),
+            ]),
+        };
+        Type::Array(Box::new(Type::Struct(tag)), Some(1))
+    }
+    /// parameters declared as arrays are really pointers
+    pub fn adjust_parameter(self) -> Type {
+        match self {
+            Type::Array(elem, _) => Type::Pointer(elem),
+            ty => ty,
+        }
+    }
+    /// the type of something declared as both `self` and `other`, which
+    /// combines what each declaration knows. None if they conflict.
+    pub fn composite(&self, other: &Type) -> Option<Type> {
+        match (self, other) {
+            (
+                Type::Function {
+                    return_type,
+                    arguments,
+                    variadic,
+                },
+                Type::Function {
+                    return_type: other_return_type,
+                    arguments: other_arguments,
+                    variadic: other_variadic,
+                },
+            ) => {
+                let return_type = return_type.composite(other_return_type)?;
+                let arguments = match (arguments, other_arguments) {
+                    (Some(arguments), Some(other_arguments)) => {
+                        if variadic != other_variadic || arguments.len() != other_arguments.len() {
+                            return None;
+                        }
+                        let arguments = arguments
+                            .iter()
+                            .zip(other_arguments)
+                            .map(|(ty, other)| ty.composite(other))
+                            .collect::<Option<_>>()?;
+                        Some(arguments)
+                    }
+                    // without a prototype, calls promote their arguments, so
+                    // a prototype with narrow or variadic parameters conflicts
+                    (Some(arguments), None) | (None, Some(arguments)) => {
+                        let variadic = *variadic || *other_variadic;
+                        if variadic || arguments.iter().any(|ty| ty.promote() != *ty) {
+                            return None;
+                        }
+                        Some(arguments.clone())
+                    }
+                    (None, None) => None,
+                };
+                Some(Type::Function {
+                    return_type: Box::new(return_type),
+                    arguments,
+                    variadic: *variadic,
+                })
+            }
+            // int a[]; int a[3];
+            (Type::Array(elem, len), Type::Array(other_elem, other_len)) => {
+                let len = match (len, other_len) {
+                    (Some(len), Some(other_len)) if len != other_len => return None,
+                    (len, other_len) => len.or(*other_len),
+                };
+                Some(Type::Array(Box::new(elem.composite(other_elem)?), len))
+            }
+            (Type::Pointer(pointee), Type::Pointer(other_pointee)) => {
+                Some(Type::Pointer(Box::new(pointee.composite(other_pointee)?)))
+            }
+            (ty, other) if ty == other => Some(ty.clone()),
+            _ => None,
+        }
+    }
+    /// integer promotion: anything narrower than int is converted to int
+    pub fn promote(&self) -> Type {
+        match self {
+            Type::Char | Type::UnsignedChar => Type::Int,
+            other => other.clone(),
+        }
+    }
+    /// the type both operands of an arithmetic operator are converted to
+    pub fn common(lhs: &Type, rhs: &Type) -> Type {
+        match (lhs.promote(), rhs.promote()) {
+            (Type::UnsignedLong, _) | (_, Type::UnsignedLong) => Type::UnsignedLong,
+            // long can hold every unsigned int, so it wins
+            (Type::Long, _) | (_, Type::Long) => Type::Long,
+            (Type::UnsignedInt, _) | (_, Type::UnsignedInt) => Type::UnsignedInt,
+            _ => Type::Int,
+        }
+    }
+}
+
+/// struct tag { members }, or just `struct tag` when the members are
+/// declared somewhere else
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructType {
+    pub tag: Option<String>,
+    pub members: Option<Vec<StructMember>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructMember {
+    pub name: String,
+    pub ty: Type,
+}
+
+/// where each member of a struct lives
+pub struct StructLayout {
+    pub offsets: Vec<usize>,
+    pub size: usize,
+    pub align: usize,
+}
+
+impl StructType {
+    pub fn layout(&self) -> StructLayout {
+        let members = match &self.members {
+            Some(members) => members,
+            None => panic!(
+                "struct {} is incomplete",
+                self.tag.as_deref().unwrap_or("<anonymous>")
+            ),
+        };
+        let mut offsets = vec![];
+        let mut size = 0;
+        let mut align = 1;
+        for member in members {
+            let member_align = member.ty.align();
+            size = round_up(size, member_align);
+            offsets.push(size);
+            size += member.ty.stack_size();
+            align = align.max(member_align);
+        }
+        StructLayout {
+            offsets,
+            size: round_up(size, align),
+            align,
+        }
+    }
+    /// the offset and type of a member
+    pub fn member(&self, name: &str) -> Option<(usize, &Type)> {
+        let members = self.members.as_ref()?;
+        let index = members.iter().position(|member| member.name == name)?;
+        Some((self.layout().offsets[index], &members[index].ty))
+    }
+}
+
+pub fn round_up(value: usize, align: usize) -> usize {
+    value.div_ceil(align) * align
+}
+
+/// the value of an integer constant, along with the type C gives it
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntConstant {
+    /// the bit pattern of the constant, so unsigned long values above
+    /// i64::MAX are negative here
+    pub value: i64,
+    pub ty: Type,
+}
+
+impl IntConstant {
+    pub fn int(value: i32) -> IntConstant {
+        IntConstant {
+            value: i64::from(value),
+            ty: Type::Int,
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
-    Return(Box<Expr>),
+    /// `return;` has no value
+    Return(Option<Box<Expr>>),
     Expr(Box<Expr>),
-    VariableDefinition {
-        ty: Type,
-        name: String,
-        value: Box<Expr>,
+    Declaration(Declaration),
+    /// { ... }, which is a scope of its own
+    Compound(Vec<Statement>),
+    /// ;
+    Empty,
+    If {
+        cond: Box<Expr>,
+        then: Box<Statement>,
+        otherwise: Option<Box<Statement>>,
     },
+    While {
+        cond: Box<Expr>,
+        body: Box<Statement>,
+    },
+    DoWhile {
+        body: Box<Statement>,
+        cond: Box<Expr>,
+    },
+    /// for (init; cond; step) body. init is an expression statement, a
+    /// declaration or empty, and a missing cond is always true.
+    For {
+        init: Box<Statement>,
+        cond: Option<Box<Expr>>,
+        step: Option<Box<Expr>>,
+        body: Box<Statement>,
+    },
+    Break,
+    Continue,
+    Goto(String),
+    /// label: statement
+    Labeled(String, Box<Statement>),
 }
 
+/// `for (init; cond; step)`, which the grammar parses apart from the body
+/// since the body can be open or closed
+pub struct ForHead {
+    pub init: Statement,
+    pub cond: Option<Box<Expr>>,
+    pub step: Option<Box<Expr>>,
+}
+
+impl ForHead {
+    pub fn with_body(self, body: Statement) -> Statement {
+        Statement::For {
+            init: Box::new(self.init),
+            cond: self.cond,
+            step: self.step,
+            body: Box::new(body),
+        }
+    }
+}
+
+/// int a, b = 2, *c;
+#[derive(Debug, Clone, PartialEq)]
+pub struct Declaration {
+    pub specifiers: DeclarationSpecifiers,
+    pub declarators: Vec<InitDeclarator>,
+}
+
+/// static inline const int
 #[derive(Debug, Clone, PartialEq)]
+pub struct DeclarationSpecifiers {
+    pub ty: Type,
+    pub is_const: bool,
+    pub storage_class: Option<StorageClass>,
+    pub is_inline: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageClass {
+    Static,
+    Extern,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeclarationSpecifier {
+    Type(TypeSpecifier),
+    Const,
+    StorageClass(StorageClass),
+    Inline,
+}
+
+/// the keywords that combine into a type, like `unsigned long int`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeSpecifier {
+    Void,
+    Char,
+    Int,
+    Long,
+    Signed,
+    Unsigned,
+    Struct(StructType),
+    /// __builtin_va_list
+    VaList,
+}
+
+impl DeclarationSpecifiers {
+    pub fn new(specifiers: Vec<DeclarationSpecifier>) -> Result<Self, &'static str> {
+        let mut type_specifiers = vec![];
+        let mut is_const = false;
+        let mut storage_class = None;
+        let mut is_inline = false;
+        for specifier in specifiers {
+            match specifier {
+                DeclarationSpecifier::Type(specifier) => type_specifiers.push(specifier),
+                DeclarationSpecifier::Const => is_const = true,
+                DeclarationSpecifier::Inline => is_inline = true,
+                DeclarationSpecifier::StorageClass(class) => {
+                    if storage_class.is_some() {
+                        return Err("multiple storage classes in declaration specifiers");
+                    }
+                    storage_class = Some(class);
+                }
+            }
+        }
+        let ty = Self::combine(&type_specifiers)?;
+        Ok(DeclarationSpecifiers {
+            ty,
+            is_const,
+            storage_class,
+            is_inline,
+        })
+    }
+    fn combine(specifiers: &[TypeSpecifier]) -> Result<Type, &'static str> {
+        // void and structs can't be combined with anything
+        let alone = match specifiers.first() {
+            Some(TypeSpecifier::Void) => Some(Type::Void),
+            Some(TypeSpecifier::Struct(struct_type)) => Some(Type::Struct(struct_type.clone())),
+            Some(TypeSpecifier::VaList) => Some(Type::va_list()),
+            _ => None,
+        };
+        if let Some(ty) = alone {
+            if specifiers.len() > 1 {
+                return Err("two or more data types in declaration specifiers");
+            }
+            return Ok(ty);
+        }
+        if specifiers.iter().any(|specifier| {
+            matches!(
+                specifier,
+                TypeSpecifier::Void | TypeSpecifier::Struct(_) | TypeSpecifier::VaList
+            )
+        }) {
+            return Err("two or more data types in declaration specifiers");
+        }
+        let count = |kind| specifiers.iter().filter(|&s| *s == kind).count();
+        let (chars, ints, longs) = (
+            count(TypeSpecifier::Char),
+            count(TypeSpecifier::Int),
+            count(TypeSpecifier::Long),
+        );
+        let (signed, unsigned) = (count(TypeSpecifier::Signed), count(TypeSpecifier::Unsigned));
+        if specifiers.is_empty() {
+            return Err("declaration specifiers are missing a type");
+        }
+        if signed + unsigned > 1 {
+            return Err("both signed and unsigned in declaration specifiers");
+        }
+        if chars > 1 || ints > 1 || longs > 2 || (chars == 1 && ints + longs > 0) {
+            return Err("two or more data types in declaration specifiers");
+        }
+        let unsigned = unsigned == 1;
+        Ok(match (chars, longs, unsigned) {
+            (1, _, false) => Type::Char,
+            (1, _, true) => Type::UnsignedChar,
+            (_, 0, false) => Type::Int,
+            (_, 0, true) => Type::UnsignedInt,
+            (_, _, false) => Type::Long,
+            (_, _, true) => Type::UnsignedLong,
+        })
+    }
+}
+
+/// b = 2
+#[derive(Debug, Clone, PartialEq)]
+pub struct InitDeclarator {
+    pub declarator: Declarator,
+    pub initializer: Option<Initializer>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Initializer {
+    /// = 2
+    Expr(Box<Expr>),
+    /// = { 1, [3] = 7, .x = 1 }
+    List(Vec<(Vec<Designator>, Initializer)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Designator {
+    /// [3]
+    Index(Box<Expr>),
+    /// .x
+    Member(String),
+}
+
+/// *c
+#[derive(Debug, Clone, PartialEq)]
+pub struct Declarator {
+    pub name: String,
+    /// applied innermost first, so `*const *a` is `[Pointer { is_const: true }, Pointer { .. }]`
+    /// and `*a[2][3]` is `[Pointer, Array(3), Array(2)]`
+    pub derived: Vec<DerivedDeclarator>,
+}
+
+impl Declarator {
+    /// the type of the declared name, given the base type of the declaration
+    pub fn type_of(&self, base: &Type) -> Type {
+        self.derived
+            .iter()
+            .fold(base.clone(), |ty, derived| match derived {
+                DerivedDeclarator::Pointer { .. } => Type::Pointer(Box::new(ty)),
+                DerivedDeclarator::Array(len) => {
+                    let len = len.as_ref().map(|len| match len.integer_constant() {
+                        Some(len) if len >= 0 => len as usize,
+                        _ => panic!("Size of array {} is not a constant", self.name),
+                    });
+                    Type::Array(Box::new(ty), len)
+                }
+                DerivedDeclarator::Function {
+                    parameters,
+                    variadic,
+                } => Type::Function {
+                    return_type: Box::new(ty),
+                    arguments: parameters.as_ref().map(|parameters| {
+                        parameters.iter().map(|param| param.ty.clone()).collect()
+                    }),
+                    variadic: *variadic,
+                },
+            })
+    }
+    /// whether the declared object itself is read-only. `const int *a` is
+    /// a mutable pointer to const, while `int *const a` can't be assigned.
+    pub fn is_const(&self, specifiers: &DeclarationSpecifiers) -> bool {
+        // an array is as const as its elements
+        let outermost = self
+            .derived
+            .iter()
+            .rev()
+            .find(|derived| !matches!(derived, DerivedDeclarator::Array(_)));
+        match outermost {
+            Some(DerivedDeclarator::Pointer { is_const }) => *is_const,
+            _ => specifiers.is_const,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DerivedDeclarator {
+    Pointer {
+        is_const: bool,
+    },
+    Array(Option<Box<Expr>>),
+    /// (int a, const char *, ...), or () which leaves the parameters unknown
+    Function {
+        parameters: Option<Vec<FunctionParameter>>,
+        variadic: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::enum_variant_names)]
 pub enum Expr {
-    /// 1
-    Number(i32),
+    /// 1, 0x7fu, 10L
+    Number(IntConstant),
+    /// "hello", without the terminating nul
+    StringLiteral(Vec<u8>),
     /// a
     Ident(String),
     /// &a
@@ -77,6 +605,8 @@ pub enum Expr {
     PostIncrement(Box<Expr>),
     /// a--
     PostDecrement(Box<Expr>),
+    /// a[b]
+    Index(Box<Expr>, Box<Expr>),
     /// a->b
     ArrowProperty(Box<Expr>, String),
     /// a.b
@@ -93,6 +623,8 @@ pub enum Expr {
 
     /// -a
     Neg(Box<Expr>),
+    /// ~a
+    BitNot(Box<Expr>),
     /// ++a
     PrefixIncrement(Box<Expr>),
     /// --a
@@ -111,6 +643,15 @@ pub enum Expr {
         op: AssignmentOp,
         value: Box<Expr>,
     },
+    /// __builtin_va_start(ap, last), the last named parameter is only
+    /// there for the C standard's sake
+    VaStart(Box<Expr>),
+    /// __builtin_va_arg(ap, int)
+    VaArg(Box<Expr>, Type),
+    /// __builtin_va_end(ap)
+    VaEnd(Box<Expr>),
+    /// __builtin_va_copy(dest, src)
+    VaCopy(Box<Expr>, Box<Expr>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -128,6 +669,25 @@ pub enum AssignmentOp {
     OrAssign,
 }
 
+impl AssignmentOp {
+    /// the operator a compound assignment applies, so `a += b` is `a = a + b`
+    pub fn binary_op(&self) -> Option<BinaryOp> {
+        Some(match self {
+            AssignmentOp::Assign => return None,
+            AssignmentOp::MulAssign => BinaryOp::Mul,
+            AssignmentOp::DivAssign => BinaryOp::Div,
+            AssignmentOp::ModAssign => BinaryOp::Mod,
+            AssignmentOp::AddAssign => BinaryOp::Add,
+            AssignmentOp::SubAssign => BinaryOp::Sub,
+            AssignmentOp::LeftShiftAssign => BinaryOp::LeftShift,
+            AssignmentOp::RightShiftAssign => BinaryOp::RightShift,
+            AssignmentOp::AndAssign => BinaryOp::BitAnd,
+            AssignmentOp::XorAssign => BinaryOp::BitXor,
+            AssignmentOp::OrAssign => BinaryOp::BitOr,
+        })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum BinaryOp {
     Mul,
@@ -153,5 +713,163 @@ pub enum BinaryOp {
 #[derive(Debug, Clone, PartialEq)]
 pub struct FunctionCall {
     pub name: String,
-    pub arguments: Vec<Box<Expr>>,
+    pub arguments: Vec<Expr>,
+}
+
+impl Expr {
+    /// evaluates integer constant expressions, like array sizes
+    pub fn integer_constant(&self) -> Option<i64> {
+        match self {
+            Expr::Number(constant) => Some(constant.value),
+            Expr::Plus(expr) => expr.integer_constant(),
+            Expr::Neg(expr) => Some(expr.integer_constant()?.wrapping_neg()),
+            Expr::BitNot(expr) => Some(!expr.integer_constant()?),
+            Expr::Op(lhs, op, rhs) => {
+                let (lhs, rhs) = (lhs.integer_constant()?, rhs.integer_constant()?);
+                match op {
+                    BinaryOp::Add => Some(lhs.wrapping_add(rhs)),
+                    BinaryOp::Sub => Some(lhs.wrapping_sub(rhs)),
+                    BinaryOp::Mul => Some(lhs.wrapping_mul(rhs)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+    /// calls `f` on this expression and then on each one inside it
+    pub fn visit(&self, f: &mut dyn FnMut(&Expr)) {
+        f(self);
+        match self {
+            Expr::Number(_)
+            | Expr::StringLiteral(_)
+            | Expr::Ident(_)
+            | Expr::AddressOf(_)
+            | Expr::SizeofType(_) => {}
+            Expr::FunctionCall(call) => {
+                for argument in &call.arguments {
+                    argument.visit(f);
+                }
+            }
+            Expr::Op(lhs, _, rhs) | Expr::Index(lhs, rhs) | Expr::VaCopy(lhs, rhs) => {
+                lhs.visit(f);
+                rhs.visit(f);
+            }
+            Expr::Assignment { lhs, value, .. } => {
+                lhs.visit(f);
+                value.visit(f);
+            }
+            Expr::Ternary {
+                cond,
+                truthy,
+                falsey,
+            } => {
+                cond.visit(f);
+                truthy.visit(f);
+                falsey.visit(f);
+            }
+            Expr::Dereference(expr)
+            | Expr::PostIncrement(expr)
+            | Expr::PostDecrement(expr)
+            | Expr::ArrowProperty(expr, _)
+            | Expr::DotProperty(expr, _)
+            | Expr::SizeofExpr(expr)
+            | Expr::Not(expr)
+            | Expr::Plus(expr)
+            | Expr::Neg(expr)
+            | Expr::BitNot(expr)
+            | Expr::PrefixIncrement(expr)
+            | Expr::PrefixDecrement(expr)
+            | Expr::Cast(_, expr)
+            | Expr::VaStart(expr)
+            | Expr::VaArg(expr, _)
+            | Expr::VaEnd(expr) => expr.visit(f),
+        }
+    }
+}
+
+impl Statement {
+    /// calls `f` on every expression in the statement, including those
+    /// inside other expressions and initializers
+    pub fn visit_exprs(&self, f: &mut dyn FnMut(&Expr)) {
+        match self {
+            Statement::Return(Some(expr)) | Statement::Expr(expr) => expr.visit(f),
+            Statement::Declaration(decl) => {
+                for init_declarator in &decl.declarators {
+                    if let Some(initializer) = &init_declarator.initializer {
+                        initializer.visit_exprs(f);
+                    }
+                }
+            }
+            Statement::Compound(body) => {
+                for stmt in body {
+                    stmt.visit_exprs(f);
+                }
+            }
+            Statement::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                cond.visit(f);
+                then.visit_exprs(f);
+                if let Some(otherwise) = otherwise {
+                    otherwise.visit_exprs(f);
+                }
+            }
+            Statement::While { cond, body } | Statement::DoWhile { body, cond } => {
+                cond.visit(f);
+                body.visit_exprs(f);
+            }
+            Statement::For {
+                init,
+                cond,
+                step,
+                body,
+            } => {
+                init.visit_exprs(f);
+                for expr in cond.iter().chain(step) {
+                    expr.visit(f);
+                }
+                body.visit_exprs(f);
+            }
+            Statement::Labeled(_, stmt) => stmt.visit_exprs(f),
+            Statement::Return(None)
+            | Statement::Empty
+            | Statement::Break
+            | Statement::Continue
+            | Statement::Goto(_) => {}
+        }
+    }
+    /// what to call the statement in a diagnostic. Declarations, blocks
+    /// and labels are only named by what's inside them.
+    pub fn kind(&self) -> Option<&'static str> {
+        Some(match self {
+            Statement::Return(_) => "return",
+            Statement::Expr(_) => "expression",
+            Statement::If { .. } => "if",
+            Statement::While { .. } => "while",
+            Statement::DoWhile { .. } => "do",
+            Statement::For { .. } => "for",
+            Statement::Break => "break",
+            Statement::Continue => "continue",
+            Statement::Goto(_) => "goto",
+            Statement::Declaration(_)
+            | Statement::Compound(_)
+            | Statement::Empty
+            | Statement::Labeled(..) => return None,
+        })
+    }
+}
+
+impl Initializer {
+    fn visit_exprs(&self, f: &mut dyn FnMut(&Expr)) {
+        match self {
+            Initializer::Expr(expr) => expr.visit(f),
+            Initializer::List(entries) => {
+                for (_, initializer) in entries {
+                    initializer.visit_exprs(f);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,745 @@
+//! Turns the IR into x86-64 assembly. Each IR instruction is selected into
+//! a few machine instructions over virtual registers, numbered like the IR
+//! registers they hold, and the allocator then maps those onto machine
+//! registers one function at a time.
+
+use crate::asm::{
+    self, Address, Condition, IndirectAddress, Instruction, Register, Register::*, Section,
+};
+use crate::ir::{self, BinaryOp, Comparison, Operand, Terminator, Ty, UnaryOp};
+use crate::platform;
+use crate::Options;
+use registers::ARGUMENTS;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+
+mod allocator;
+mod liveness;
+pub mod registers;
+
+/// the size of the general purpose part of the register save area
+pub const GP_SAVE_AREA: usize = 48;
+/// the end of the vector register part, which is left out since there are
+/// no floating point arguments. va_start marks all of it as used.
+const FP_SAVE_AREA_END: usize = GP_SAVE_AREA + 16 * 8;
+
+/// the stack frame of a function, growing down from rbp
+#[derive(Default)]
+struct Frame {
+    offset: i32,
+}
+
+impl Frame {
+    /// reserves naturally aligned space, and returns its offset from rbp
+    fn alloc(&mut self, size: usize, align: usize) -> i32 {
+        self.offset -= size as i32;
+        self.offset -= self.offset.rem_euclid(align as i32);
+        self.offset
+    }
+    /// the space the frame takes up, keeping rsp 16 byte aligned so calls
+    /// don't need to adjust it
+    fn size(&self) -> usize {
+        crate::ast::round_up(-self.offset as usize, 16)
+    }
+}
+
+fn rbp(offset: i32) -> IndirectAddress {
+    IndirectAddress::offset(Box::new(Rbp.into()), offset)
+}
+
+fn sized(addr: IndirectAddress, ty: Ty) -> IndirectAddress {
+    match ty {
+        Ty::I8 => addr.byte(),
+        Ty::I32 => addr.dword(),
+        Ty::I64 => addr.qword(),
+    }
+}
+
+/// the virtual register holding an IR register
+fn virtual_register(reg: ir::Reg) -> Register {
+    Virtual(reg.number, reg.ty.size())
+}
+
+fn condition(comparison: Comparison) -> Condition {
+    match comparison {
+        Comparison::Eq => Condition::Equal,
+        Comparison::Ne => Condition::NotEqual,
+        Comparison::SLt => Condition::Less,
+        Comparison::SLe => Condition::LessEqual,
+        Comparison::SGt => Condition::Greater,
+        Comparison::SGe => Condition::GreaterEqual,
+        Comparison::ULt => Condition::Below,
+        Comparison::ULe => Condition::BelowEqual,
+        Comparison::UGt => Condition::Above,
+        Comparison::UGe => Condition::AboveEqual,
+    }
+}
+
+struct FunctionCodegen<'a> {
+    function: &'a ir::Function,
+    /// the functions the module defines, which are called directly
+    defined: &'a HashSet<&'a str>,
+    instructions: Vec<Instruction>,
+    frame: Frame,
+    /// the rbp offset of each slot
+    slots: Vec<i32>,
+    /// rbp offset of the saved argument registers, in variadic functions
+    save_area: i32,
+    /// rbp offset of where structs returned in registers are stored, in
+    /// functions that call something returning one
+    return_area: i32,
+    labels: HashMap<ir::BlockId, String>,
+    return_label: String,
+    /// how many times each register is read
+    uses: HashMap<usize, usize>,
+    /// numbers the virtual registers instructions need besides the IR's
+    next_register: usize,
+}
+
+impl<'a> FunctionCodegen<'a> {
+    fn gen(&mut self, instruction: Instruction) -> &mut Self {
+        self.instructions.push(instruction);
+        self
+    }
+    fn temporary(&mut self, ty: Ty) -> Register {
+        self.next_register += 1;
+        Virtual(self.next_register - 1, ty.size())
+    }
+    fn memory(&self, address: &ir::Address) -> IndirectAddress {
+        let addr = match &address.base {
+            ir::Base::Slot(slot) => return rbp(self.slots[*slot] + address.offset),
+            ir::Base::Global(label) => IndirectAddress::rip_relative(label.clone()),
+            ir::Base::Reg(reg) => {
+                IndirectAddress::indirect(Box::new(virtual_register(*reg).into()))
+            }
+        };
+        match address.offset {
+            0 => addr,
+            offset => addr.add_offset(offset),
+        }
+    }
+    /// an operand in a register, loading constants into a new one
+    fn register(&mut self, operand: Operand) -> Register {
+        match operand {
+            Operand::Reg(reg) => virtual_register(reg),
+            Operand::Const(val, ty) => {
+                let register = self.temporary(ty);
+                self.gen(Instruction::Mov(register.into(), val.into()));
+                register
+            }
+        }
+    }
+    /// an operand as it is, which only mov can take when it's a 64 bit
+    /// immediate
+    fn operand(&self, operand: Operand) -> Address {
+        match operand {
+            Operand::Const(val, _) => val.into(),
+            Operand::Reg(reg) => virtual_register(reg).into(),
+        }
+    }
+    /// an operand as the source of an instruction, which can be an
+    /// immediate unless it needs more than 32 bits
+    fn source(&mut self, operand: Operand) -> Address {
+        match operand {
+            Operand::Const(val, _) if i32::try_from(val).is_ok() => val.into(),
+            operand => self.register(operand).into(),
+        }
+    }
+    /// moves an operand into a register, unless it's there already
+    fn copy(&mut self, dest: Register, src: Operand) {
+        let src = self.operand(src);
+        if Address::from(dest) != src {
+            self.gen(Instruction::Mov(dest.into(), src));
+        }
+    }
+
+    fn compile_instruction(&mut self, instruction: &ir::Instruction) {
+        match instruction {
+            ir::Instruction::Copy { dest, src } => self.copy(virtual_register(*dest), *src),
+            ir::Instruction::Unary { op, dest, src } => {
+                let dest = virtual_register(*dest);
+                self.copy(dest, *src);
+                self.gen(match op {
+                    UnaryOp::Neg => Instruction::Neg(dest.into()),
+                    UnaryOp::Not => Instruction::Not(dest.into()),
+                });
+            }
+            ir::Instruction::Binary { op, dest, lhs, rhs } => match op {
+                BinaryOp::SDiv | BinaryOp::UDiv | BinaryOp::SRem | BinaryOp::URem => {
+                    self.divide(*op, *dest, *lhs, *rhs)
+                }
+                BinaryOp::Shl | BinaryOp::Sar | BinaryOp::Shr => self.shift(*op, *dest, *lhs, *rhs),
+                _ => self.arithmetic(*op, *dest, *lhs, *rhs),
+            },
+            ir::Instruction::Compare {
+                comparison,
+                dest,
+                lhs,
+                rhs,
+            } => {
+                self.compare(*lhs, *rhs);
+                let dest = virtual_register(*dest);
+                self.gen(Instruction::Setcc(condition(*comparison), dest.resize(1)))
+                    .gen(Instruction::Movzx(
+                        dest.resize(4).into(),
+                        dest.resize(1).into(),
+                    ));
+            }
+            ir::Instruction::Convert { signed, dest, src } => self.convert(*signed, *dest, *src),
+            ir::Instruction::Load { dest, address } => {
+                let src = sized(self.memory(address), dest.ty);
+                self.gen(Instruction::Mov(virtual_register(*dest).into(), src.into()));
+            }
+            ir::Instruction::Store { address, src } => {
+                // there is no encoding for storing a 64 bit immediate
+                let value = self.source(*src);
+                let dest = sized(self.memory(address), src.ty());
+                self.gen(Instruction::Mov(dest.into(), value));
+            }
+            ir::Instruction::AddressOf { dest, address } => {
+                let src = self.memory(address);
+                self.gen(Instruction::Lea(virtual_register(*dest).into(), src.into()));
+            }
+            ir::Instruction::Call {
+                dest,
+                function,
+                arguments,
+                in_registers,
+                returned_struct,
+                variadic,
+                tail,
+            } => match returned_struct {
+                Some(size) => {
+                    self.call(None, function, arguments, *in_registers, *variadic, *tail);
+                    self.store_returned_struct(dest.unwrap(), *size);
+                }
+                None => self.call(*dest, function, arguments, *in_registers, *variadic, *tail),
+            },
+            ir::Instruction::VaStart { list } => self.va_start(*list),
+        }
+    }
+
+    /// operators that map onto one two operand instruction
+    fn arithmetic(&mut self, op: BinaryOp, dest: ir::Reg, lhs: Operand, rhs: Operand) {
+        // moving lhs into dest first would overwrite rhs
+        let target = match rhs == Operand::Reg(dest) && lhs != rhs {
+            true => self.temporary(dest.ty),
+            false => virtual_register(dest),
+        };
+        self.copy(target, lhs);
+        let src = self.source(rhs);
+        let target_operand = Address::from(target);
+        self.gen(match op {
+            BinaryOp::Add => Instruction::Add(target_operand, src),
+            BinaryOp::Sub => Instruction::Sub(target_operand, src),
+            BinaryOp::Mul => Instruction::Imul(target_operand, src),
+            BinaryOp::And => Instruction::And(target_operand, src),
+            BinaryOp::Or => Instruction::Or(target_operand, src),
+            BinaryOp::Xor => Instruction::Xor(target_operand, src),
+            _ => unreachable!("{} is not arithmetic", op),
+        });
+        if target != virtual_register(dest) {
+            self.gen(Instruction::Mov(
+                virtual_register(dest).into(),
+                target.into(),
+            ));
+        }
+    }
+
+    /// idiv divides rdx:rax, and leaves the quotient in rax and the
+    /// remainder in rdx
+    fn divide(&mut self, op: BinaryOp, dest: ir::Reg, lhs: Operand, rhs: Operand) {
+        let size = dest.ty.size();
+        let signed = matches!(op, BinaryOp::SDiv | BinaryOp::SRem);
+        // there's no encoding for dividing by an immediate
+        let divisor = self.register(rhs);
+        let dividend = self.operand(lhs);
+        self.gen(Instruction::Mov(Rax.resize(size).into(), dividend));
+        match (signed, size) {
+            (true, 8) => self.gen(Instruction::Cqo),
+            (true, _) => self.gen(Instruction::Cdq),
+            (false, _) => self.gen(Instruction::Xor(Edx.into(), Edx.into())),
+        };
+        self.gen(match signed {
+            true => Instruction::Idiv(divisor.into()),
+            false => Instruction::Div(divisor.into()),
+        });
+        let result = match op {
+            BinaryOp::SDiv | BinaryOp::UDiv => Rax,
+            _ => Rdx,
+        };
+        self.gen(Instruction::Mov(
+            virtual_register(dest).into(),
+            result.resize(size).into(),
+        ));
+    }
+
+    /// the count of a shift is an immediate or in cl
+    fn shift(&mut self, op: BinaryOp, dest: ir::Reg, lhs: Operand, rhs: Operand) {
+        let count = match rhs {
+            Operand::Const(val, _) => Address::Immediate(val),
+            Operand::Reg(count) => {
+                // before dest is written, in case it's the count
+                self.gen(Instruction::Mov(
+                    Rcx.resize(count.ty.size()).into(),
+                    virtual_register(count).into(),
+                ));
+                Cl.into()
+            }
+        };
+        let dest = virtual_register(dest);
+        self.copy(dest, lhs);
+        self.gen(match op {
+            BinaryOp::Shl => Instruction::Shl(dest.into(), count),
+            BinaryOp::Sar => Instruction::Sar(dest.into(), count),
+            _ => Instruction::Shr(dest.into(), count),
+        });
+    }
+
+    /// sets the flags for lhs - rhs
+    fn compare(&mut self, lhs: Operand, rhs: Operand) {
+        let lhs = self.register(lhs);
+        let rhs = self.source(rhs);
+        self.gen(Instruction::Cmp(lhs.into(), rhs));
+    }
+
+    fn convert(&mut self, signed: bool, dest: ir::Reg, src: Operand) {
+        let target = virtual_register(dest);
+        let src = match src {
+            Operand::Reg(reg) => reg,
+            Operand::Const(val, ty) => {
+                let val = match signed || ty == Ty::I64 {
+                    true => val,
+                    false => val & ((1 << (8 * ty.size())) - 1),
+                };
+                self.gen(Instruction::Mov(
+                    target.into(),
+                    dest.ty.truncate(val).into(),
+                ));
+                return;
+            }
+        };
+        let from = virtual_register(src);
+        let instruction = match (src.ty, dest.ty, signed) {
+            (_, Ty::I8, _) | (Ty::I32, Ty::I32, _) | (Ty::I64, _, _) => {
+                // narrowing just uses the low bits of the register
+                Instruction::Mov(target.into(), from.resize(dest.ty.size()).into())
+            }
+            (Ty::I8, _, true) => Instruction::Movsx(target.into(), from.into()),
+            // zero extending into a dword register also clears the upper half
+            (Ty::I8, _, false) => Instruction::Movzx(target.resize(4).into(), from.into()),
+            (Ty::I32, Ty::I64, true) => Instruction::Movsxd(target.into(), from.into()),
+            (Ty::I32, Ty::I64, false) => Instruction::Mov(target.resize(4).into(), from.into()),
+        };
+        self.gen(instruction);
+    }
+
+    /// calls a function following the SysV ABI. The arguments that go in
+    /// registers are moved there and the rest are pushed right to left,
+    /// with rsp 16 byte aligned at the call. A tail call only has arguments
+    /// in registers, and the epilogue goes before it once the frame is
+    /// known.
+    fn call(
+        &mut self,
+        dest: Option<ir::Reg>,
+        function: &str,
+        arguments: &[Operand],
+        in_registers: usize,
+        variadic: bool,
+        tail: bool,
+    ) {
+        let stack_arguments = arguments.len() - in_registers;
+        let padding = (stack_arguments % 2) * 8;
+        if padding > 0 {
+            self.gen(Instruction::Sub(
+                Rsp.into(),
+                Address::Immediate(padding as i64),
+            ));
+        }
+        for argument in arguments[in_registers..].iter().rev() {
+            self.copy(Rax.resize(argument.ty().size()), *argument);
+            self.gen(Instruction::Push(Rax));
+        }
+        for (argument, register) in arguments[..in_registers].iter().zip(ARGUMENTS.iter()) {
+            self.copy(register.resize(argument.ty().size()), *argument);
+        }
+        if variadic {
+            // al holds the number of vector registers used for arguments,
+            // and there are no floating point arguments
+            self.gen(Instruction::Mov(Al.into(), Address::Immediate(0)));
+        }
+        if tail {
+            assert_eq!(stack_arguments, 0, "Tail call with arguments on the stack");
+            self.gen(match self.defined.contains(function) {
+                true => Instruction::TailCall(function.to_string(), in_registers),
+                false => Instruction::TailCallExternal(function.to_string(), in_registers),
+            });
+            return;
+        }
+        self.gen(match self.defined.contains(function) {
+            true => Instruction::Call(function.to_string(), in_registers),
+            false => Instruction::CallExternal(function.to_string(), in_registers),
+        });
+        let cleanup = padding + 8 * stack_arguments;
+        if cleanup > 0 {
+            self.gen(Instruction::Add(
+                Rsp.into(),
+                Address::Immediate(cleanup as i64),
+            ));
+        }
+        if let Some(dest) = dest {
+            self.gen(Instruction::Mov(
+                virtual_register(dest).into(),
+                Rax.resize(dest.ty.size()).into(),
+            ));
+        }
+    }
+
+    /// stores a struct returned in rax and rdx to the return area, where
+    /// it stays until the next such call, and sets dest to its address
+    fn store_returned_struct(&mut self, dest: ir::Reg, size: usize) {
+        let area = self.return_area;
+        self.gen(Instruction::Mov(rbp(area).qword().into(), Rax.into()));
+        if size > 8 {
+            self.gen(Instruction::Mov(rbp(area + 8).qword().into(), Rdx.into()));
+        }
+        self.gen(Instruction::Lea(
+            virtual_register(dest).into(),
+            rbp(area).into(),
+        ));
+    }
+
+    /// points the va_list at the unnamed arguments, in the save area and
+    /// then on the stack
+    fn va_start(&mut self, list: Operand) {
+        let in_registers = self.function.parameters_in_registers;
+        let on_stack = self.function.parameters.len() - in_registers;
+        let gp_offset = 8 * in_registers;
+        let overflow_area = 16 + 8 * on_stack as i32;
+        let list = self.register(list);
+        let field =
+            |offset: i32| IndirectAddress::indirect(Box::new(list.into())).add_offset(offset);
+        let scratch = self.temporary(Ty::I64);
+        let save_area = self.save_area;
+        self.gen(Instruction::Mov(
+            field(0).dword().into(),
+            Address::Immediate(gp_offset as i64),
+        ))
+        .gen(Instruction::Mov(
+            field(4).dword().into(),
+            Address::Immediate(FP_SAVE_AREA_END as i64),
+        ))
+        .gen(Instruction::Lea(scratch.into(), rbp(overflow_area).into()))
+        .gen(Instruction::Mov(field(8).qword().into(), scratch.into()))
+        .gen(Instruction::Lea(scratch.into(), rbp(save_area).into()))
+        .gen(Instruction::Mov(field(16).qword().into(), scratch.into()));
+    }
+
+    /// jumps to a block, unless it comes next anyway
+    fn jump(&mut self, target: ir::BlockId, next: Option<ir::BlockId>) {
+        if Some(target) != next {
+            let label = self.labels[&target].clone();
+            self.gen(Instruction::Jmp(label));
+        }
+    }
+
+    /// a branch on a comparison that's only used by the branch tests the
+    /// flags directly, instead of setting a register to test
+    fn compile_block(&mut self, block: &ir::Block, next: Option<ir::BlockId>) {
+        let (instructions, fused) = match (block.instructions.split_last(), &block.terminator) {
+            (
+                Some((
+                    ir::Instruction::Compare {
+                        comparison,
+                        dest,
+                        lhs,
+                        rhs,
+                    },
+                    rest,
+                )),
+                Terminator::Branch {
+                    cond: Operand::Reg(cond),
+                    ..
+                },
+            ) if dest == cond && self.uses.get(&dest.number) == Some(&1) => {
+                (rest, Some((*comparison, *lhs, *rhs)))
+            }
+            _ => (&block.instructions[..], None),
+        };
+        for instruction in instructions {
+            self.compile_instruction(instruction);
+        }
+        // the callee returns for this function
+        if let Some(ir::Instruction::Call { tail: true, .. }) = instructions.last() {
+            return;
+        }
+        match &block.terminator {
+            Terminator::Jump(target) => self.jump(*target, next),
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let condition = match (fused, cond) {
+                    (Some((comparison, lhs, rhs)), _) => {
+                        self.compare(lhs, rhs);
+                        condition(comparison)
+                    }
+                    (None, Operand::Const(val, _)) => {
+                        let target = if *val != 0 { then } else { otherwise };
+                        return self.jump(*target, next);
+                    }
+                    (None, Operand::Reg(reg)) => {
+                        self.gen(Instruction::Cmp(
+                            virtual_register(*reg).into(),
+                            Address::Immediate(0),
+                        ));
+                        Condition::NotEqual
+                    }
+                };
+                if Some(*then) == next {
+                    let label = self.labels[otherwise].clone();
+                    self.gen(Instruction::Jcc(condition.negate(), label));
+                } else {
+                    let label = self.labels[then].clone();
+                    self.gen(Instruction::Jcc(condition, label));
+                    self.jump(*otherwise, next);
+                }
+            }
+            Terminator::Return(value) => {
+                if let Some(value) = value {
+                    self.copy(Rax.resize(value.ty().size()), *value);
+                }
+                // a struct is returned from where the value points
+                if let (Some(size), Some(_)) = (self.function.returned_struct, value) {
+                    let eightbyte = |offset| IndirectAddress::offset(Box::new(Rax.into()), offset);
+                    if size > 8 {
+                        self.gen(Instruction::Mov(Rdx.into(), eightbyte(8).qword().into()));
+                    }
+                    self.gen(Instruction::Mov(Rax.into(), eightbyte(0).qword().into()));
+                }
+                // the epilogue comes right after the last block
+                if next.is_some() {
+                    let label = self.return_label.clone();
+                    self.gen(Instruction::Jmp(label));
+                }
+            }
+        }
+    }
+
+    /// moves the arguments where the body expects them. Variadic functions
+    /// save every argument register first, for va_arg to read the unnamed
+    /// ones.
+    fn compile_parameters(&mut self) {
+        if self.function.variadic {
+            for (i, register) in ARGUMENTS.iter().enumerate() {
+                let slot = rbp(self.save_area + 8 * i as i32).qword();
+                self.gen(Instruction::Mov(slot.into(), (*register).into()));
+            }
+        }
+        let in_registers = self.function.parameters_in_registers;
+        for (i, parameter) in self.function.parameters.iter().enumerate() {
+            let dest = virtual_register(*parameter);
+            let src: Address = match i < in_registers {
+                true => ARGUMENTS[i].resize(parameter.ty.size()).into(),
+                // the rest are where the caller pushed them, above the
+                // saved rbp and the return address
+                false => {
+                    let offset = 16 + 8 * (i - in_registers) as i32;
+                    sized(rbp(offset), parameter.ty).into()
+                }
+            };
+            self.gen(Instruction::Mov(dest.into(), src));
+        }
+    }
+}
+
+struct Codegen<'a> {
+    options: &'a Options,
+    defined: HashSet<&'a str>,
+    instructions: Vec<Instruction>,
+    /// how many local labels have been generated, used to name them
+    labels: usize,
+}
+
+impl<'a> Codegen<'a> {
+    /// a fresh label for a block. Labels starting with a dot are local to
+    /// the function in NASM.
+    fn new_label(&mut self) -> String {
+        let label = format!(".L{}", self.labels);
+        self.labels += 1;
+        label
+    }
+
+    fn compile_function(&mut self, function: &ir::Function) {
+        let mut frame = Frame::default();
+        let save_area = match function.variadic {
+            true => frame.alloc(GP_SAVE_AREA, 8),
+            false => 0,
+        };
+        let returns_struct = |instruction: &ir::Instruction| {
+            matches!(
+                instruction,
+                ir::Instruction::Call {
+                    returned_struct: Some(_),
+                    ..
+                }
+            )
+        };
+        let return_area = match function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .any(returns_struct)
+        {
+            true => frame.alloc(16, 8),
+            false => 0,
+        };
+        let slots = function
+            .slots
+            .iter()
+            .map(|slot| frame.alloc(slot.size, slot.align))
+            .collect();
+        // only blocks something jumps to need a label
+        let mut labels = HashMap::new();
+        for block in &function.blocks {
+            for target in block.terminator.successors() {
+                labels.entry(target).or_insert_with(|| self.new_label());
+            }
+        }
+        let mut uses = HashMap::new();
+        for block in &function.blocks {
+            let registers = block.instructions.iter().flat_map(ir::Instruction::uses);
+            for reg in registers.chain(block.terminator.uses()) {
+                *uses.entry(reg.number).or_insert(0) += 1;
+            }
+        }
+        let mut codegen = FunctionCodegen {
+            function,
+            defined: &self.defined,
+            instructions: vec![],
+            frame,
+            slots,
+            save_area,
+            return_area,
+            labels,
+            return_label: format!("{}.return", function.name),
+            uses,
+            next_register: function.registers,
+        };
+        codegen.compile_parameters();
+        for (i, block) in function.blocks.iter().enumerate() {
+            if let Some(label) = codegen.labels.get(&block.id) {
+                let label = label.clone();
+                codegen.gen(Instruction::Label(label));
+            }
+            let next = function.blocks.get(i + 1).map(|block| block.id);
+            codegen.compile_block(block, next);
+        }
+
+        let live_out = match (function.return_ty, function.returned_struct) {
+            (Some(_), Some(size)) if size > 8 => vec![Rax, Rdx],
+            (Some(_), _) => vec![Rax],
+            (None, _) => vec![],
+        };
+        let mut frame = codegen.frame;
+        let allocation = allocator::allocate(
+            codegen.instructions,
+            &live_out,
+            &mut || rbp(frame.alloc(8, 8)),
+            self.options.allocation_comments,
+        );
+        // the callee saved registers the body uses are restored on the way out
+        let saved: Vec<_> = allocation
+            .callee_saved
+            .into_iter()
+            .map(|register| (register, Address::from(rbp(frame.alloc(8, 8)).qword())))
+            .collect();
+        self.instructions.extend(vec![
+            Instruction::Label(function.name.clone()),
+            Instruction::Push(Rbp),
+            Instruction::Mov(Rbp.into(), Rsp.into()),
+        ]);
+        if frame.size() > 0 {
+            self.instructions.push(Instruction::Sub(
+                Rsp.into(),
+                Address::Immediate(frame.size() as i64),
+            ));
+        }
+        for (register, slot) in &saved {
+            self.instructions
+                .push(Instruction::Mov(slot.clone(), (*register).into()));
+        }
+        // everything but the ret of the epilogue
+        let mut epilogue: Vec<Instruction> = saved
+            .into_iter()
+            .map(|(register, slot)| Instruction::Mov(register.into(), slot))
+            .collect();
+        epilogue.extend(vec![
+            Instruction::Mov(Rsp.into(), Rbp.into()),
+            Instruction::Pop(Rbp),
+        ]);
+        for instruction in allocation.instructions {
+            if let Instruction::TailCall(..) | Instruction::TailCallExternal(..) = instruction {
+                self.instructions.extend(epilogue.iter().cloned());
+            }
+            self.instructions.push(instruction);
+        }
+        self.instructions
+            .push(Instruction::Label(codegen.return_label));
+        self.instructions.extend(epilogue);
+        self.instructions.push(Instruction::Ret);
+    }
+}
+
+/// generates the assembly for a whole module
+pub fn generate(module: &ir::Module, options: &Options) -> Vec<asm::Instruction> {
+    let mut codegen = Codegen {
+        options,
+        defined: module
+            .functions
+            .iter()
+            .map(|function| function.name.as_str())
+            .collect(),
+        instructions: vec![Instruction::Section(Section::Text)],
+        labels: 0,
+    };
+    for function in &module.functions {
+        codegen.compile_function(function);
+    }
+
+    // symbols are exported or imported depending on their linkage
+    let defined: HashSet<&str> = module
+        .data
+        .values()
+        .flatten()
+        .filter_map(|instruction| match instruction {
+            Instruction::Label(label) => Some(label.as_str()),
+            _ => None,
+        })
+        .chain(codegen.defined.iter().copied())
+        .collect();
+    let mut instructions: Vec<_> = module
+        .exported
+        .iter()
+        .cloned()
+        .map(Instruction::Global)
+        .collect();
+    instructions.extend(
+        module
+            .referenced
+            .iter()
+            .filter(|name| !defined.contains(name.as_str()))
+            .cloned()
+            .map(Instruction::Extern),
+    );
+    instructions.extend(codegen.instructions);
+    for (section, data) in &module.data {
+        instructions.push(Instruction::Section(*section));
+        instructions.extend(data.iter().cloned());
+    }
+    if platform::stack_note() {
+        instructions.push(Instruction::Section(Section::NoteGnuStack));
+    }
+    instructions
+}
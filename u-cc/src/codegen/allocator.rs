@@ -0,0 +1,223 @@
+//! Linear scan register allocation. Virtual registers are visited in the
+//! order their live intervals start, and each gets a register that's free
+//! for the whole interval. Machine registers the code uses directly, like
+//! the argument registers or the ones a call clobbers, keep virtual
+//! registers out of them while they're live.
+//!
+//! When no register is free, whichever interval ends last is spilled to a
+//! slot in the frame. The spilled register is then loaded and stored
+//! around each instruction that uses it, through registers that only live
+//! for that instruction, and the body is allocated again.
+
+use super::liveness::{self, Interval};
+use super::registers::{CALLEE_SAVED, POOL};
+use crate::asm::{IndirectAddress, Instruction, Register, Register::*};
+use std::collections::{HashMap, HashSet};
+
+pub struct Allocation {
+    pub instructions: Vec<Instruction>,
+    /// the callee saved registers the code uses, which the function has to
+    /// save and restore
+    pub callee_saved: Vec<Register>,
+}
+
+/// maps the virtual registers of a function body onto machine registers.
+/// `live_out` are the registers holding results when the body ends, and
+/// `spill_slot` reserves a qword in the frame. With `comments` the code
+/// says where each virtual register went.
+pub fn allocate(
+    mut instructions: Vec<Instruction>,
+    live_out: &[Register],
+    spill_slot: &mut dyn FnMut() -> IndirectAddress,
+    comments: bool,
+) -> Allocation {
+    let mut next = instructions
+        .iter_mut()
+        .flat_map(Instruction::registers_mut)
+        .filter_map(|register| match *register {
+            Virtual(number, _) => Some(number + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+    // the registers spilled ones are reloaded into, which are never spilled
+    let mut reloads = HashSet::new();
+    loop {
+        let intervals = liveness::intervals(&instructions, live_out);
+        match scan(&intervals, &reloads) {
+            Ok(assignment) => return assign(instructions, &intervals, &assignment, comments),
+            Err(spilled) => {
+                instructions = spill(instructions, &spilled, spill_slot, comments, || {
+                    reloads.insert(next);
+                    next += 1;
+                    next - 1
+                })
+            }
+        }
+    }
+}
+
+/// assigns registers to every virtual register by number, or returns the
+/// ones that have to be spilled
+fn scan(
+    intervals: &HashMap<Register, Interval>,
+    reloads: &HashSet<usize>,
+) -> Result<HashMap<usize, Register>, Vec<usize>> {
+    let mut virtuals: Vec<(usize, usize, usize)> = intervals
+        .iter()
+        .filter_map(|(register, interval)| match register {
+            Virtual(number, _) => Some((interval.start(), interval.end(), *number)),
+            _ => None,
+        })
+        .collect();
+    virtuals.sort_unstable();
+    // whether the code uses a machine register directly in start..end
+    let fixed = |register: Register, start: usize, end: usize| {
+        intervals
+            .get(&register)
+            .is_some_and(|interval| interval.overlaps(start, end))
+    };
+    // the end, number and register of the intervals that are live
+    let mut active: Vec<(usize, usize, Register)> = vec![];
+    let mut assignment = HashMap::new();
+    let mut spilled = vec![];
+    for (start, end, number) in virtuals {
+        active.retain(|&(active_end, ..)| active_end > start);
+        let free = POOL.iter().copied().find(|&register| {
+            !active.iter().any(|&(.., taken)| taken == register) && !fixed(register, start, end)
+        });
+        let register = match free {
+            Some(register) => register,
+            None => {
+                // the interval that ends last, out of those whose register
+                // this one could take instead
+                let victim = active
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (_, other, register))| {
+                        !reloads.contains(other) && !fixed(*register, start, end)
+                    })
+                    .max_by_key(|(_, (other_end, ..))| *other_end)
+                    .map(|(index, _)| index);
+                match victim {
+                    Some(index) if active[index].0 > end || reloads.contains(&number) => {
+                        let (_, victim, register) = active.remove(index);
+                        assignment.remove(&victim);
+                        spilled.push(victim);
+                        register
+                    }
+                    _ if !reloads.contains(&number) => {
+                        spilled.push(number);
+                        continue;
+                    }
+                    _ => panic!("Ran out of registers for v{}", number),
+                }
+            }
+        };
+        assignment.insert(number, register);
+        active.push((end, number, register));
+    }
+    match spilled.is_empty() {
+        true => Ok(assignment),
+        false => Err(spilled),
+    }
+}
+
+/// gives each spilled register a slot in the frame, and replaces it in
+/// each instruction with a new register that's loaded from the slot before
+/// and stored to it after. `new_register` numbers the new registers.
+fn spill(
+    instructions: Vec<Instruction>,
+    spilled: &[usize],
+    spill_slot: &mut dyn FnMut() -> IndirectAddress,
+    comments: bool,
+    mut new_register: impl FnMut() -> usize,
+) -> Vec<Instruction> {
+    let mut slots = HashMap::new();
+    let mut rewritten = vec![];
+    for mut instruction in instructions {
+        let (uses, defs) = liveness::uses_and_defs(&instruction);
+        let mut stores = vec![];
+        for &number in spilled {
+            let register = Virtual(number, 8);
+            let (used, defined) = (uses.contains(&register), defs.contains(&register));
+            if !used && !defined {
+                continue;
+            }
+            let slot = slots.entry(number).or_insert_with(|| {
+                let slot = spill_slot().qword();
+                if comments {
+                    rewritten.push(Instruction::Comment(format!(
+                        "{} spilled to {}",
+                        register, slot
+                    )));
+                }
+                slot
+            });
+            let reload = new_register();
+            for operand in instruction.registers_mut() {
+                if let Virtual(other, size) = *operand {
+                    if other == number {
+                        *operand = Virtual(reload, size);
+                    }
+                }
+            }
+            let reload = Virtual(reload, 8);
+            if used {
+                rewritten.push(Instruction::Mov(reload.into(), slot.clone().into()));
+            }
+            if defined {
+                stores.push(Instruction::Mov(slot.clone().into(), reload.into()));
+            }
+        }
+        rewritten.push(instruction);
+        rewritten.extend(stores);
+    }
+    rewritten
+}
+
+/// replaces the virtual registers with the ones they were assigned
+fn assign(
+    instructions: Vec<Instruction>,
+    intervals: &HashMap<Register, Interval>,
+    assignment: &HashMap<usize, Register>,
+    comments: bool,
+) -> Allocation {
+    // each comment goes before the instruction the interval starts at
+    let mut notes: Vec<(usize, usize, Register)> = match comments {
+        true => assignment
+            .iter()
+            .map(|(&number, &register)| {
+                (intervals[&Virtual(number, 8)].start() / 2, number, register)
+            })
+            .collect(),
+        false => vec![],
+    };
+    notes.sort_unstable_by_key(|&(index, number, _)| (index, number));
+    let mut notes = notes.into_iter().peekable();
+    let mut allocated = vec![];
+    for (index, mut instruction) in instructions.into_iter().enumerate() {
+        while let Some((_, number, register)) = notes.next_if(|note| note.0 == index) {
+            allocated.push(Instruction::Comment(format!(
+                "{} in {}",
+                Virtual(number, 8),
+                register
+            )));
+        }
+        for operand in instruction.registers_mut() {
+            if let Virtual(number, size) = *operand {
+                *operand = assignment[&number].resize(size);
+            }
+        }
+        allocated.push(instruction);
+    }
+    let callee_saved = CALLEE_SAVED
+        .iter()
+        .copied()
+        .filter(|register| assignment.values().any(|assigned| assigned == register))
+        .collect();
+    Allocation {
+        instructions: allocated,
+        callee_saved,
+    }
+}
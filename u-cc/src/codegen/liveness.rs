@@ -0,0 +1,252 @@
+//! Where in a function body each register holds a value that's still
+//! needed. The body is split into basic blocks at labels and jumps, and
+//! liveness flows backwards through them until nothing changes.
+//!
+//! Instruction i reads its operands at position 2i and writes its results
+//! at 2i + 1, so the register an instruction last reads can hold what the
+//! same instruction writes.
+
+use super::registers::{ARGUMENTS, CALLER_SAVED};
+use crate::asm::{Address, Instruction, Register, Register::*};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// the register an operand reads, as a qword. For memory that's the base
+/// of the address, and the frame registers aren't tracked.
+fn read(addr: &Address) -> Option<Register> {
+    let register = match addr {
+        Address::Register(register) => *register,
+        Address::Indirect(addr) => match addr.base() {
+            Address::Register(register) => *register,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    match register.resize(8) {
+        Rsp | Rbp => None,
+        register => Some(register),
+    }
+}
+
+/// the registers an instruction reads and writes, as qwords. Writing part
+/// of a register counts as writing all of it, since the code never reads
+/// the rest afterwards.
+pub fn uses_and_defs(instruction: &Instruction) -> (Vec<Register>, Vec<Register>) {
+    let (mut uses, mut defs) = (vec![], vec![]);
+    // a destination in memory reads the register it's addressed through
+    let mut write = |dest: &Address, uses: &mut Vec<Register>| match dest {
+        Address::Register(_) => defs.extend(read(dest)),
+        _ => uses.extend(read(dest)),
+    };
+    match instruction {
+        Instruction::Mov(dest, src)
+        | Instruction::Movsx(dest, src)
+        | Instruction::Movzx(dest, src)
+        | Instruction::Movsxd(dest, src)
+        | Instruction::Lea(dest, src) => {
+            uses.extend(read(src));
+            write(dest, &mut uses);
+        }
+        // xor of a register with itself zeroes it, whatever it held
+        Instruction::Xor(dest @ Address::Register(_), src) if dest == src => {
+            write(dest, &mut uses);
+        }
+        Instruction::Add(dest, src)
+        | Instruction::Sub(dest, src)
+        | Instruction::Imul(dest, src)
+        | Instruction::And(dest, src)
+        | Instruction::Or(dest, src)
+        | Instruction::Xor(dest, src)
+        | Instruction::Shl(dest, src)
+        | Instruction::Sar(dest, src)
+        | Instruction::Shr(dest, src) => {
+            uses.extend(read(dest));
+            uses.extend(read(src));
+            write(dest, &mut uses);
+        }
+        Instruction::Neg(dest) | Instruction::Not(dest) => {
+            uses.extend(read(dest));
+            write(dest, &mut uses);
+        }
+        Instruction::Cmp(lhs, rhs) => {
+            uses.extend(read(lhs));
+            uses.extend(read(rhs));
+        }
+        Instruction::Cdq | Instruction::Cqo => {
+            uses.push(Rax);
+            write(&Rdx.into(), &mut uses);
+        }
+        Instruction::Idiv(divisor) | Instruction::Div(divisor) => {
+            uses.extend(read(divisor));
+            uses.extend(&[Rax, Rdx]);
+            write(&Rax.into(), &mut uses);
+            write(&Rdx.into(), &mut uses);
+        }
+        Instruction::Setcc(_, register) | Instruction::Pop(register) => {
+            write(&(*register).into(), &mut uses);
+        }
+        Instruction::Push(register) => uses.extend(read(&(*register).into())),
+        Instruction::Call(_, arguments) | Instruction::CallExternal(_, arguments) => {
+            uses.extend(&ARGUMENTS[..*arguments]);
+            for &register in CALLER_SAVED.iter() {
+                write(&register.into(), &mut uses);
+            }
+        }
+        Instruction::TailCall(_, arguments) | Instruction::TailCallExternal(_, arguments) => {
+            uses.extend(&ARGUMENTS[..*arguments]);
+        }
+        _ => {}
+    }
+    (uses, defs)
+}
+
+/// the positions a register is live at, as sorted, disjoint half open
+/// ranges
+#[derive(Debug, Default)]
+pub struct Interval {
+    ranges: Vec<(usize, usize)>,
+}
+
+impl Interval {
+    pub fn start(&self) -> usize {
+        self.ranges[0].0
+    }
+    pub fn end(&self) -> usize {
+        self.ranges[self.ranges.len() - 1].1
+    }
+    /// whether the register is live anywhere in start..end
+    pub fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.ranges
+            .iter()
+            .any(|&(from, to)| from < end && start < to)
+    }
+    /// adds a position, going backwards through the body
+    fn add(&mut self, position: usize) {
+        match self.ranges.last_mut() {
+            Some(range) if range.0 == position + 1 => range.0 = position,
+            Some(range) if range.0 == position => {}
+            _ => self.ranges.push((position, position + 1)),
+        }
+    }
+}
+
+/// the instructions of each basic block
+fn blocks(instructions: &[Instruction]) -> Vec<Range<usize>> {
+    let mut starts = vec![0];
+    for (i, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::Label(_) => starts.push(i),
+            Instruction::Jmp(_)
+            | Instruction::Jcc(..)
+            | Instruction::Ret
+            | Instruction::TailCall(..)
+            | Instruction::TailCallExternal(..) => starts.push(i + 1),
+            _ => {}
+        }
+    }
+    starts.push(instructions.len());
+    starts.dedup();
+    starts.windows(2).map(|pair| pair[0]..pair[1]).collect()
+}
+
+/// the live interval of every register the body uses. `live_out` are the
+/// registers that hold results where the body ends, or jumps to a label
+/// outside of it.
+pub fn intervals(
+    instructions: &[Instruction],
+    live_out: &[Register],
+) -> HashMap<Register, Interval> {
+    let blocks = blocks(instructions);
+    let labels: HashMap<&str, usize> = blocks
+        .iter()
+        .enumerate()
+        .filter_map(|(index, block)| match &instructions[block.start] {
+            Instruction::Label(label) => Some((label.as_str(), index)),
+            _ => None,
+        })
+        .collect();
+    // the blocks control can go to after each block, and whether it can
+    // leave the body
+    let successors: Vec<(Vec<usize>, bool)> = blocks
+        .iter()
+        .enumerate()
+        .map(|(index, block)| {
+            let next = Some(index + 1).filter(|&next| next < blocks.len());
+            let (target, falls_through) = match &instructions[block.end - 1] {
+                Instruction::Jmp(label) => (Some(label), false),
+                Instruction::Jcc(_, label) => (Some(label), true),
+                Instruction::Ret
+                | Instruction::TailCall(..)
+                | Instruction::TailCallExternal(..) => (None, false),
+                _ => (None, true),
+            };
+            let target = target.map(|label| labels.get(label.as_str()).copied());
+            let mut successors: Vec<usize> = target.flatten().into_iter().collect();
+            successors.extend(next.filter(|_| falls_through));
+            let exits = target == Some(None) || (falls_through && next.is_none());
+            (successors, exits)
+        })
+        .collect();
+
+    // what each block reads before writing it, and what it writes
+    let summaries: Vec<(HashSet<Register>, HashSet<Register>)> = blocks
+        .iter()
+        .map(|block| {
+            let (mut gen, mut kill) = (HashSet::new(), HashSet::new());
+            for instruction in instructions[block.clone()].iter().rev() {
+                let (uses, defs) = uses_and_defs(instruction);
+                for register in defs {
+                    gen.remove(&register);
+                    kill.insert(register);
+                }
+                gen.extend(uses);
+            }
+            (gen, kill)
+        })
+        .collect();
+    let mut live_in = vec![HashSet::new(); blocks.len()];
+    let mut live_at_end = vec![HashSet::new(); blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for index in (0..blocks.len()).rev() {
+            let (successors, exits) = &successors[index];
+            let mut live: HashSet<Register> = successors
+                .iter()
+                .flat_map(|&successor| live_in[successor].iter().copied())
+                .collect();
+            if *exits {
+                live.extend(live_out.iter().map(|register| register.resize(8)));
+            }
+            let (gen, kill) = &summaries[index];
+            let entry: HashSet<Register> = live.difference(kill).chain(gen).copied().collect();
+            if entry != live_in[index] {
+                live_in[index] = entry;
+                changed = true;
+            }
+            live_at_end[index] = live;
+        }
+    }
+
+    let mut intervals: HashMap<Register, Interval> = HashMap::new();
+    for (index, block) in blocks.iter().enumerate().rev() {
+        let mut live = live_at_end[index].clone();
+        for i in block.clone().rev() {
+            let (uses, defs) = uses_and_defs(&instructions[i]);
+            for &register in live.iter().chain(&defs) {
+                intervals.entry(register).or_default().add(2 * i + 1);
+            }
+            for register in defs {
+                live.remove(&register);
+            }
+            live.extend(uses);
+            for &register in &live {
+                intervals.entry(register).or_default().add(2 * i);
+            }
+        }
+    }
+    for interval in intervals.values_mut() {
+        interval.ranges.reverse();
+    }
+    intervals
+}
@@ -0,0 +1,21 @@
+//! The registers intermediate values are computed in. Code is generated
+//! with as many virtual registers as it needs, and the allocator maps them
+//! onto the machine's registers once the whole function is compiled.
+
+use crate::asm::{Register, Register::*};
+
+/// the registers the allocator hands out. Caller saved registers come
+/// first, so leaf functions rarely need to save anything, and rsp and rbp
+/// hold the frame.
+pub const POOL: [Register; 14] = [
+    Rax, Rcx, Rdx, Rsi, Rdi, R8, R9, R10, R11, Rbx, R12, R13, R14, R15,
+];
+
+/// the registers a call can clobber
+pub const CALLER_SAVED: [Register; 9] = [Rax, Rcx, Rdx, Rsi, Rdi, R8, R9, R10, R11];
+
+/// the registers a function has to restore before it returns
+pub const CALLEE_SAVED: [Register; 5] = [Rbx, R12, R13, R14, R15];
+
+/// the registers the first six integer arguments are passed in
+pub const ARGUMENTS: [Register; 6] = [Rdi, Rsi, Rdx, Rcx, R8, R9];
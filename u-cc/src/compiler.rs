@@ -1,135 +1,926 @@
-use crate::asm::Address::Indirect;
-use crate::asm::{Address, IndirectAddress, Instruction, Register::*};
-use crate::ast::Expr::FunctionCall;
-use crate::ast::{self, Expr, FunctionDefinition, Program, Statement, Type};
+//! Lowers the AST into the IR. Scalar locals whose address is never taken
+//! live in virtual registers, and everything else is in a slot of the stack
+//! frame or in a data section.
+
+use crate::asm::{Data, DataValue, Instruction, Section};
+use crate::ast::{
+    self, Declaration, Expr, ExternalDeclaration, FunctionCall, FunctionDefinition, InitDeclarator,
+    Initializer, Program, Statement, StorageClass, StructType, Type,
+};
+use crate::codegen::registers::ARGUMENTS;
+use crate::compiler::initializer::InitEntry;
+use crate::compiler::statics::{Linkage, StaticObject};
 use crate::compiler::symbol_table::Symbol;
+use crate::ir::{self, Base, BlockId, Operand, Reg, Terminator, Ty};
 use crate::platform;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
+mod control_flow;
+mod initializer;
+mod operators;
+mod statics;
+mod stdarg;
 mod symbol_table;
+mod types;
 
 struct Compiler<'src> {
-    instructions: Vec<Instruction>,
+    /// the contents of .data, .bss and .rodata, emitted after .text
+    data_sections: BTreeMap<Section, Vec<Instruction>>,
     symbol_table: symbol_table::SymbolTable<'src>,
+    /// complete struct definitions, by tag
+    struct_tags: HashMap<String, StructType>,
+    /// how many string literals have been emitted, used to name them
+    string_literals: usize,
+    /// the functions this file defines
+    defined_functions: HashSet<&'src str>,
+    /// the linkage of each file scope name, decided by its first declaration
+    linkage: HashMap<&'src str, Linkage>,
+    /// labels defined with external linkage, which are made `global`
+    exported: BTreeSet<String>,
+    /// symbols the code uses, which are `extern` unless defined here
+    referenced: BTreeSet<String>,
+    /// globals and static locals, emitted after the code
+    objects: Vec<StaticObject>,
+    functions: Vec<ir::Function>,
+    /// semantic errors found so far; compiling carries on to report the rest
+    errors: Vec<String>,
+}
+
+/// where the result of an expression is
+#[derive(Debug, Clone)]
+enum Place {
+    /// a register or a constant. Locals in registers are lvalues too.
+    Operand(Operand),
+    /// an lvalue in memory
+    Memory(ir::Address),
+}
+
+/// where the result of an expression is, and what type it has
+#[derive(Debug, Clone)]
+struct Value {
+    place: Place,
+    ty: Type,
+}
+
+impl Value {
+    fn new(operand: impl Into<Operand>, ty: Type) -> Value {
+        Value {
+            place: Place::Operand(operand.into()),
+            ty,
+        }
+    }
+    fn memory(address: ir::Address, ty: Type) -> Value {
+        Value {
+            place: Place::Memory(address),
+            ty,
+        }
+    }
+    fn constant(val: i64, ty: Type) -> Value {
+        let width = Ty::of(&ty);
+        Value::new(Operand::Const(width.truncate(val), width), ty)
+    }
+}
+
+/// the result of an expression that doesn't have one
+fn void() -> Value {
+    Value::new(Operand::Const(0, Ty::I32), Type::Void)
+}
+
+/// the part of an object at `offset`, like a member or an array element
+fn subobject(value: &Value, offset: usize, ty: &Type) -> Value {
+    match &value.place {
+        Place::Memory(address) => {
+            Value::memory(address.clone().add_offset(offset as i32), ty.clone())
+        }
+        other => panic!("{:?} is not an object in memory", other),
+    }
+}
+
+/// where a local variable lives
+enum Storage {
+    Register(Reg),
+    /// in the stack frame
+    Slot(usize),
+    /// at a label, for static locals and block scope extern declarations
+    Static(String),
+}
+
+/// where break and continue go in the innermost loop
+struct Loop {
+    break_to: BlockId,
+    continue_to: BlockId,
 }
 
 struct FunctionCtx<'src> {
-    // the name of the variable as well as the offset of that var into
-    // the stack frame
-    local_variables: HashMap<&'src str, (i32, Symbol<'src>)>,
-    stack_ptr_offset: i32,
+    /// the locals of each block scope, innermost last
+    local_variables: Vec<HashMap<&'src str, (Storage, Symbol<'src>)>>,
+    return_type: Type,
+    /// locals whose address is taken somewhere, which have to be in memory
+    address_taken: HashSet<String>,
+    function: ir::Function,
+    /// the block instructions are added to and what it holds so far. It's
+    /// None after a jump or a return, until another block starts.
+    current: Option<(BlockId, Vec<ir::Instruction>)>,
+    /// the loops the current statement is in, innermost last
+    loops: Vec<Loop>,
+    /// the block of each label that's been defined or jumped to
+    labels: HashMap<&'src str, BlockId>,
+    defined_labels: HashSet<&'src str>,
+    /// how many static locals there are so far, which tells apart the
+    /// labels of ones with the same name in different blocks
+    statics: usize,
+    /// where the caller wants a struct too big for registers returned
+    result: Option<Reg>,
 }
 
 impl<'src> FunctionCtx<'src> {
-    fn new() -> Self {
+    fn new(function: ir::Function, return_type: Type, body: &[Statement]) -> Self {
+        let mut address_taken = HashSet::new();
+        for stmt in body {
+            stmt.visit_exprs(&mut |expr| {
+                if let Expr::AddressOf(name) = expr {
+                    address_taken.insert(name.clone());
+                }
+            });
+        }
         FunctionCtx {
-            local_variables: Default::default(),
-            stack_ptr_offset: 0,
-        }
-    }
-    fn lookup(&self, name: &str) -> Address {
-        assert!(self.local_variables.get(name).is_some());
-        let (offset, ref symbol) = *self.local_variables.get(name).unwrap();
-        let addr = IndirectAddress::offset(Box::new(Rbp.into()), offset);
-        let addr = match symbol.type_of().stack_size() {
-            4 => addr.dword(),
-            8 => addr.qword(),
-            n => panic!("Unknown stack size: {}", n),
+            local_variables: vec![HashMap::new()],
+            return_type,
+            address_taken,
+            function,
+            current: None,
+            loops: vec![],
+            labels: HashMap::new(),
+            defined_labels: HashSet::new(),
+            statics: 0,
+            result: None,
+        }
+    }
+    fn name(&self) -> &str {
+        &self.function.name
+    }
+    /// a label for a static local that no other object has, like `f.x.1`
+    fn static_label(&mut self, name: &str) -> String {
+        self.statics += 1;
+        format!("{}.{}.{}", self.name(), name, self.statics)
+    }
+    fn lookup(&self, name: &str) -> Option<Value> {
+        let (storage, symbol) = self
+            .local_variables
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))?;
+        let ty = symbol.type_of().clone();
+        Some(match storage {
+            Storage::Register(reg) => Value::new(*reg, ty),
+            Storage::Slot(slot) => Value::memory(ir::Address::new(Base::Slot(*slot)), ty),
+            Storage::Static(label) => {
+                Value::memory(ir::Address::new(Base::Global(label.clone())), ty)
+            }
+        })
+    }
+    /// gives a local a register, or a slot if it has to be in memory
+    fn register_local(&mut self, symbol: Symbol<'src>) -> Result<(), String> {
+        let ty = symbol.type_of();
+        let storage = match ty.is_scalar() && !self.address_taken.contains(symbol.name()) {
+            true => Storage::Register(self.function.new_reg(Ty::of(ty))),
+            false => Storage::Slot(self.function.new_slot(ty)),
         };
-        addr.into()
+        self.define_local(symbol, storage)
     }
-    fn register_local(&mut self, symbol: Symbol<'src>) {
-        debug_assert!(self.local_variables.get(symbol.name()).is_none());
-        self.stack_ptr_offset -= symbol.type_of().stack_size() as i32;
-        self.local_variables
-            .insert(symbol.name(), (self.stack_ptr_offset, symbol));
+    fn register_static(&mut self, symbol: Symbol<'src>, label: String) -> Result<(), String> {
+        self.define_local(symbol, Storage::Static(label))
+    }
+    /// the new definition replaces the old one even when it is an error,
+    /// so that the rest of the function can still be compiled
+    fn define_local(&mut self, symbol: Symbol<'src>, storage: Storage) -> Result<(), String> {
+        let scope = self.local_variables.last_mut().unwrap();
+        let name = symbol.name();
+        match scope.insert(name, (storage, symbol)) {
+            Some(_) => Err(format!("redefinition of {}", name)),
+            None => Ok(()),
+        }
     }
-    fn register_temp(&mut self) -> Address {
-        self.stack_ptr_offset -= Type::Int.stack_size() as i32;
-        IndirectAddress::offset(Box::new(Rbp.into()), self.stack_ptr_offset).into()
+    fn push_scope(&mut self) {
+        self.local_variables.push(HashMap::new());
+    }
+    fn pop_scope(&mut self) {
+        self.local_variables.pop();
+    }
+
+    fn new_reg(&mut self, ty: Ty) -> Reg {
+        self.function.new_reg(ty)
+    }
+    fn new_block(&mut self) -> BlockId {
+        self.function.new_block_id()
+    }
+    /// the instructions of the current block. Code after a jump or return
+    /// goes in a new block nothing jumps to.
+    fn block(&mut self) -> &mut Vec<ir::Instruction> {
+        if self.current.is_none() {
+            let id = self.new_block();
+            self.current = Some((id, vec![]));
+        }
+        &mut self.current.as_mut().unwrap().1
+    }
+    fn emit(&mut self, instruction: ir::Instruction) {
+        self.block().push(instruction);
+    }
+    /// ends the current block
+    fn terminate(&mut self, terminator: Terminator) {
+        self.block();
+        let (id, instructions) = self.current.take().unwrap();
+        self.function.blocks.push(ir::Block {
+            id,
+            instructions,
+            terminator,
+        });
+    }
+    /// starts adding to a new block, which the current one falls into
+    fn start_block(&mut self, id: BlockId) {
+        if let Some((current, _)) = self.current {
+            if current != id {
+                self.terminate(Terminator::Jump(id));
+            }
+        }
+        self.current = Some((id, vec![]));
+    }
+    /// ends the current block with a jump, unless there isn't one because
+    /// nothing can get here
+    fn jump(&mut self, target: BlockId) {
+        if self.current.is_some() {
+            self.terminate(Terminator::Jump(target));
+        }
+    }
+    /// the block a label starts
+    fn label(&mut self, name: &'src str) -> BlockId {
+        if let Some(block) = self.labels.get(name) {
+            return *block;
+        }
+        let block = self.new_block();
+        self.labels.insert(name, block);
+        block
+    }
+    /// notes which statement the current block starts with, for warnings
+    /// about code that can't be reached
+    fn start_statement(&mut self, kind: &'static str) {
+        self.block();
+        let (id, _) = self.current.as_ref().unwrap();
+        self.function.statements.entry(*id).or_insert(kind);
+    }
+
+    /// the value of an expression, as an operand. Arrays decay into a
+    /// pointer to their first element.
+    fn load(&mut self, value: Value) -> Operand {
+        match value.place {
+            Place::Operand(operand) => operand,
+            Place::Memory(address) if matches!(value.ty, Type::Array(..)) => {
+                let dest = self.new_reg(Ty::I64);
+                self.emit(ir::Instruction::AddressOf { dest, address });
+                dest.into()
+            }
+            Place::Memory(address) => {
+                if !value.ty.is_scalar() {
+                    unimplemented!("Using a {:?} as a value", value.ty);
+                }
+                let dest = self.new_reg(Ty::of(&value.ty));
+                self.emit(ir::Instruction::Load { dest, address });
+                dest.into()
+            }
+        }
+    }
+    /// converts an operand of type `from` to `to`, extending or truncating
+    /// as needed. Constants are converted right away.
+    fn convert(&mut self, operand: Operand, from: &Type, to: &Type) -> Operand {
+        let width = Ty::of(to);
+        if width == operand.ty() {
+            return operand;
+        }
+        match operand {
+            Operand::Const(val, ty) => {
+                let val = match from.is_signed() || ty == Ty::I64 {
+                    true => val,
+                    false => val & ((1 << (8 * ty.size())) - 1),
+                };
+                Operand::Const(width.truncate(val), width)
+            }
+            Operand::Reg(_) => {
+                let dest = self.new_reg(width);
+                self.emit(ir::Instruction::Convert {
+                    signed: from.is_signed(),
+                    dest,
+                    src: operand,
+                });
+                dest.into()
+            }
+        }
+    }
+    /// the value of an expression converted to `ty`
+    fn load_as(&mut self, value: Value, ty: &Type) -> Operand {
+        let from = decay(&value.ty);
+        let operand = self.load(value);
+        self.convert(operand, &from, ty)
+    }
+    /// an operand in a register, copying constants into one
+    fn in_register(&mut self, operand: Operand) -> Reg {
+        match operand {
+            Operand::Reg(reg) => reg,
+            Operand::Const(..) => {
+                let dest = self.new_reg(operand.ty());
+                self.emit(ir::Instruction::Copy { dest, src: operand });
+                dest
+            }
+        }
+    }
+    /// stores an operand of the target's type to an lvalue
+    fn store(&mut self, target: &Value, src: Operand) {
+        match &target.place {
+            Place::Operand(Operand::Reg(dest)) => {
+                self.emit(ir::Instruction::Copy { dest: *dest, src })
+            }
+            Place::Memory(address) => self.emit(ir::Instruction::Store {
+                address: address.clone(),
+                src,
+            }),
+            Place::Operand(constant) => panic!("{} is not an lvalue", constant),
+        }
     }
 }
 
 impl<'src> Compiler<'src> {
     pub fn new() -> Self {
         Compiler {
-            instructions: vec![],
+            data_sections: Default::default(),
             symbol_table: Default::default(),
+            struct_tags: Default::default(),
+            string_literals: 0,
+            defined_functions: Default::default(),
+            linkage: Default::default(),
+            exported: Default::default(),
+            referenced: Default::default(),
+            objects: vec![],
+            functions: vec![],
+            errors: vec![],
+        }
+    }
+
+    /// remembers the error, if there is one
+    fn report(&mut self, result: Result<(), String>) {
+        if let Err(error) = result {
+            self.errors.push(error);
         }
     }
 
-    pub fn gen(&mut self, instruction: Instruction) -> &mut Self {
-        self.instructions.push(instruction);
+    pub fn gen_data(&mut self, section: Section, instruction: Instruction) -> &mut Self {
+        self.data_sections
+            .entry(section)
+            .or_default()
+            .push(instruction);
         self
     }
-    pub fn gen_label(&mut self, label: String) -> &mut Self {
-        self.gen(Instruction::Label(label))
+    /// emits a nul terminated string into .rodata, and returns its label
+    fn string_literal(&mut self, bytes: &[u8]) -> String {
+        let label = format!("__str_{}", self.string_literals);
+        self.string_literals += 1;
+        let mut bytes = bytes.to_vec();
+        bytes.push(0);
+        self.gen_data(Section::Rodata, Instruction::Label(label.clone()))
+            .gen_data(Section::Rodata, Instruction::Data(Data::Byte(bytes)));
+        label
+    }
+    /// remembers the definitions of any structs declared along with `ty`
+    fn declare_structs(&mut self, ty: &Type) {
+        if let Type::Struct(StructType {
+            tag,
+            members: Some(members),
+        }) = ty
+        {
+            for member in members {
+                self.declare_structs(&member.ty);
+            }
+            if let (Some(tag), Type::Struct(complete)) = (tag, self.complete(ty)) {
+                self.struct_tags.insert(tag.to_string(), complete);
+            }
+        }
+    }
+    /// replaces references to structs by tag with their definitions, so the
+    /// type has a size. Pointers are left alone, since they can be to
+    /// incomplete types.
+    fn complete(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Struct(StructType {
+                tag: Some(tag),
+                members: None,
+            }) => match self.struct_tags.get(tag) {
+                Some(definition) => Type::Struct(definition.clone()),
+                None => ty.clone(),
+            },
+            Type::Struct(StructType {
+                tag,
+                members: Some(members),
+            }) => Type::Struct(StructType {
+                tag: tag.clone(),
+                members: Some(
+                    members
+                        .iter()
+                        .map(|member| ast::StructMember {
+                            name: member.name.clone(),
+                            ty: self.complete(&member.ty),
+                        })
+                        .collect(),
+                ),
+            }),
+            Type::Array(elem, len) => Type::Array(Box::new(self.complete(elem)), *len),
+            ty => ty.clone(),
+        }
+    }
+    /// locals shadow globals
+    fn lookup(&mut self, func_ctx: &FunctionCtx, name: &str) -> Value {
+        if let Some(value) = func_ctx.lookup(name) {
+            return value;
+        }
+        self.referenced.insert(name.to_string());
+        match self.symbol_table.lookup_symbol(name) {
+            Some(symbol) => Value::memory(
+                ir::Address::new(Base::Global(name.to_string())),
+                symbol.type_of().clone(),
+            ),
+            None => panic!("Use of undeclared identifier {}", name),
+        }
     }
 }
 
-fn func_parameter_register(number: usize) -> Address {
-    match number {
-        0 => Edi.into(),
-        1 => Esi.into(),
-        _ => unimplemented!(),
+fn pointer_to(ty: &Type) -> Type {
+    Type::Pointer(Box::new(ty.clone()))
+}
+
+/// the type a value of type `ty` has when it's used, since arrays decay
+/// into a pointer to their first element
+fn decay(ty: &Type) -> Type {
+    match ty {
+        Type::Array(elem, _) => pointer_to(elem),
+        ty => ty.clone(),
     }
 }
 
-fn compile_expr(compiler: &mut Compiler, func_ctx: &mut FunctionCtx, expr: &Expr) -> Address {
-    match expr {
-        Expr::Number(val) => Address::Immediate(*val),
-        Expr::Ident(ident) => func_ctx.lookup(ident),
-        Expr::AddressOf(ident) => {
-            let (offset, _) = func_ctx.local_variables.get(ident.as_str()).unwrap();
-            compiler.gen(Instruction::Lea(
-                Rax.into(),
-                IndirectAddress::offset(Box::new(Rbp.into()), *offset).into(),
-            ));
-            Rax.into()
-        }
-        Expr::FunctionCall(call) => {
-            for (i, arg) in call.arguments.iter().enumerate() {
-                let addr = compile_expr(compiler, func_ctx, arg);
-                let register = func_parameter_register(i);
-                compiler.gen(Instruction::Mov(register, addr));
-            }
-            compiler.gen(Instruction::Call(call.name.to_string()));
-            Eax.into()
+/// the object a pointer (or array) points at
+fn dereference(compiler: &mut Compiler, func_ctx: &mut FunctionCtx, pointer: Value) -> Value {
+    let pointee = match &pointer.ty {
+        Type::Pointer(pointee) | Type::Array(pointee, _) => compiler.complete(pointee),
+        other => panic!("Cannot dereference a value of type {:?}", other),
+    };
+    // the first element of an array is where the array is
+    if let Type::Array(..) = pointer.ty {
+        return subobject(&pointer, 0, &pointee);
+    }
+    let operand = func_ctx.load(pointer);
+    let reg = func_ctx.in_register(operand);
+    Value::memory(ir::Address::new(Base::Reg(reg)), pointee)
+}
+
+fn member(value: &Value, name: &str) -> Value {
+    let (offset, ty) = match &value.ty {
+        Type::Struct(struct_type) => match struct_type.member(name) {
+            Some((offset, ty)) => (offset, ty.clone()),
+            None => panic!("{:?} has no member named {}", value.ty, name),
+        },
+        other => panic!(
+            "Request for member {} in something not a struct: {:?}",
+            name, other
+        ),
+    };
+    subobject(value, offset, &ty)
+}
+
+/// converts an argument to its parameter type. Arguments narrower than int
+/// are then extended to int, which clang relies on.
+fn load_argument(func_ctx: &mut FunctionCtx, value: Value, ty: &Type) -> Operand {
+    let operand = func_ctx.load_as(value, ty);
+    func_ctx.convert(operand, ty, &ty.promote())
+}
+
+/// what's wrong with assigning `expr`, of type `from`, to a `to`, if
+/// anything. Integers and pointers only mix through a cast, except that 0
+/// is a null pointer, and pointers have to point at the same type unless
+/// one of them is void *.
+fn assignment_error(to: &Type, from: &Type, expr: &Expr) -> Option<&'static str> {
+    match (to, &decay(from)) {
+        (Type::Pointer(to), Type::Pointer(from)) => {
+            match **to == Type::Void || **from == Type::Void || compatible(to, from) {
+                true => None,
+                false => Some("from incompatible pointer type"),
+            }
         }
-        Expr::Op(lhs, op, rhs) => {
-            use ast::BinaryOp;
-            match op {
-                BinaryOp::Add => {
-                    let lhs = compile_expr(compiler, func_ctx, lhs);
-                    let rhs = compile_expr(compiler, func_ctx, rhs);
-                    let temp = func_ctx.register_temp();
-                    compiler
-                        .gen(Instruction::Mov(Eax.into(), lhs))
-                        .gen(Instruction::Add(Eax.into(), rhs))
-                        .gen(Instruction::Mov(temp.clone(), Eax.into()));
-                    temp
+        (Type::Pointer(_), from) if from.is_integer() => match expr.integer_constant() {
+            Some(0) => None,
+            _ => Some("makes pointer from integer without a cast"),
+        },
+        (to, Type::Pointer(_)) if to.is_integer() => {
+            Some("makes integer from pointer without a cast")
+        }
+        _ => None,
+    }
+}
+
+/// whether two types are the same, counting a struct declared by its tag
+/// as the one defined with that tag
+fn compatible(a: &Type, b: &Type) -> bool {
+    match (a, b) {
+        (Type::Pointer(a), Type::Pointer(b)) => compatible(a, b),
+        (Type::Array(a, n), Type::Array(b, m)) => {
+            compatible(a, b) && (n == m || n.is_none() || m.is_none())
+        }
+        (Type::Struct(a), Type::Struct(b)) if a.tag.is_some() => a.tag == b.tag,
+        (a, b) => a == b,
+    }
+}
+
+/// whether an argument of type `ty` is passed in registers, when `used`
+/// of them already hold arguments. It takes one for each eightbyte, and
+/// structs bigger than 16 bytes always go on the stack, as the SysV ABI
+/// says for a struct of integers.
+fn passed_in_registers(ty: &Type, used: usize) -> bool {
+    let size = ty.stack_size();
+    size <= 16 && used + size.div_ceil(8) <= ARGUMENTS.len()
+}
+
+/// a slot with room for a struct rounded up to whole eightbytes, so each
+/// of them can be loaded or stored as a long
+fn eightbyte_slot(func_ctx: &mut FunctionCtx, ty: &Type) -> usize {
+    let eightbytes = Type::Array(Box::new(Type::Long), Some(ty.stack_size().div_ceil(8)));
+    func_ctx.function.new_slot(&eightbytes)
+}
+
+/// the eightbytes a struct is passed as
+fn struct_argument(func_ctx: &mut FunctionCtx, value: Value) -> Vec<Operand> {
+    let size = value.ty.stack_size();
+    // loading the last eightbyte whole could read past the struct
+    let value = match size % 8 {
+        0 => value,
+        _ => {
+            let slot = eightbyte_slot(func_ctx, &value.ty);
+            let copied = Value::memory(ir::Address::new(Base::Slot(slot)), value.ty.clone());
+            copy(func_ctx, &copied, &value);
+            copied
+        }
+    };
+    (0..size.div_ceil(8))
+        .map(|i| func_ctx.load(subobject(&value, 8 * i, &Type::Long)))
+        .collect()
+}
+
+fn compile_call(compiler: &mut Compiler, func_ctx: &mut FunctionCtx, call: &FunctionCall) -> Value {
+    let (return_type, parameters, variadic) = match compiler.symbol_table.lookup_symbol(&call.name)
+    {
+        Some(symbol) => match symbol.type_of() {
+            Type::Function {
+                return_type,
+                arguments,
+                variadic,
+            } => ((**return_type).clone(), arguments.clone(), *variadic),
+            other => panic!("Called object {} has type {:?}", call.name, other),
+        },
+        None => {
+            eprintln!("warning: implicit declaration of function {}", call.name);
+            (Type::Int, None, false)
+        }
+    };
+    if let Some(parameters) = &parameters {
+        let count = call.arguments.len();
+        if count < parameters.len() {
+            compiler.report(Err(format!("too few arguments to function {}", call.name)));
+        }
+        if count > parameters.len() && !variadic {
+            compiler.report(Err(format!("too many arguments to function {}", call.name)));
+        }
+    }
+    let prototyped = parameters.is_some();
+    let parameters = parameters.unwrap_or_default();
+    let return_type = compiler.complete(&return_type);
+    // a struct comes back in registers, or else to memory whose address is
+    // passed before the arguments
+    let result = match return_type {
+        Type::Struct(_) => Some(temporary_object(func_ctx, &return_type)),
+        _ => None,
+    };
+    let returned_struct = returned_struct(&return_type);
+    let mut arguments = vec![];
+    let mut on_stack = vec![];
+    if let (Some(result), None) = (&result, returned_struct) {
+        arguments.push(address_of(func_ctx, result).into());
+    }
+    for (i, arg) in call.arguments.iter().enumerate() {
+        let value = compile_expr(compiler, func_ctx, arg);
+        // arguments are converted to the parameter type as if by assignment,
+        // unless there's no parameter to say what that is
+        let ty = match (parameters.get(i), &value.ty) {
+            (Some(parameter), _) => {
+                let ty = compiler.complete(parameter);
+                if let Some(problem) = assignment_error(&ty, &value.ty, arg) {
+                    let error = format!("passing argument {} of {} {}", i + 1, call.name, problem);
+                    compiler.report(Err(error));
                 }
-                _ => unimplemented!(),
+                ty
             }
+            (None, ty) => decay(ty).promote(),
+        };
+        let operands = match &ty {
+            Type::Struct(_) if value.ty == ty => struct_argument(func_ctx, value),
+            Type::Struct(_) => panic!("Cannot pass a {:?} as a {:?}", value.ty, ty),
+            _ if ty.is_scalar() => vec![load_argument(func_ctx, value, &ty)],
+            _ => unimplemented!("Passing a {:?} by value", ty),
+        };
+        match passed_in_registers(&ty, arguments.len()) {
+            true => arguments.extend(operands),
+            false => on_stack.extend(operands),
+        }
+    }
+    let in_registers = arguments.len();
+    arguments.extend(on_stack);
+    compiler.referenced.insert(call.name.to_string());
+    let dest = match return_type {
+        Type::Void => None,
+        Type::Struct(_) => Some(func_ctx.new_reg(Ty::I64)),
+        _ => Some(func_ctx.new_reg(Ty::of(&return_type))),
+    };
+    func_ctx.emit(ir::Instruction::Call {
+        dest,
+        function: call.name.to_string(),
+        arguments,
+        in_registers,
+        returned_struct,
+        // calls without a prototype could be to a variadic function
+        variadic: variadic || !prototyped,
+        tail: false,
+    });
+    match (dest, result) {
+        // where the registers were stored only lasts until the next call
+        (Some(dest), Some(result)) if returned_struct.is_some() => {
+            let returned = Value::memory(ir::Address::new(Base::Reg(dest)), return_type);
+            copy(func_ctx, &result, &returned);
+            result
+        }
+        (_, Some(result)) => result,
+        (Some(dest), None) => Value::new(dest, return_type),
+        (None, None) => void(),
+    }
+}
+
+/// the size of a struct that's returned in rax and rdx, which it is if it
+/// has at most two eightbytes
+fn returned_struct(ty: &Type) -> Option<usize> {
+    match ty {
+        Type::Struct(_) if ty.stack_size() <= 16 => Some(ty.stack_size()),
+        _ => None,
+    }
+}
+
+/// the address of an object in memory
+fn address_of(func_ctx: &mut FunctionCtx, object: &Value) -> Reg {
+    let address = match &object.place {
+        Place::Memory(address) => address.clone(),
+        other => panic!("{:?} is not an object in memory", other),
+    };
+    let dest = func_ctx.new_reg(Ty::I64);
+    func_ctx.emit(ir::Instruction::AddressOf { dest, address });
+    dest
+}
+
+/// the operand a struct is returned as: the address of the memory the
+/// caller passed, after copying the struct there, or of a copy with room
+/// to load the registers from
+fn return_struct(func_ctx: &mut FunctionCtx, value: Value) -> Operand {
+    if value.ty != func_ctx.return_type {
+        panic!(
+            "Cannot return a {:?} from a function returning {:?}",
+            value.ty, func_ctx.return_type
+        );
+    }
+    let (object, address) = match func_ctx.result {
+        Some(result) => (
+            Value::memory(ir::Address::new(Base::Reg(result)), value.ty.clone()),
+            result,
+        ),
+        None => {
+            let slot = eightbyte_slot(func_ctx, &value.ty);
+            let object = Value::memory(ir::Address::new(Base::Slot(slot)), value.ty.clone());
+            let address = address_of(func_ctx, &object);
+            (object, address)
+        }
+    };
+    copy(func_ctx, &object, &value);
+    address.into()
+}
+
+/// how many registers evaluating `expr` can hold at once, by Sethi-Ullman
+/// numbering. When both operands of a binary operator need the same
+/// number, it takes one more to hold the result of the first while the
+/// second is evaluated. A call clobbers registers, so it's counted as
+/// needing all of them.
+fn registers_needed(expr: &Expr) -> usize {
+    use ast::BinaryOp;
+    let pool = crate::codegen::registers::POOL.len();
+    // the register count of two subexpressions evaluated greediest first
+    let pair = |first: &Expr, second: &Expr| {
+        let (first, second) = (registers_needed(first), registers_needed(second));
+        if first == second {
+            first + 1
+        } else {
+            first.max(second)
+        }
+    };
+    let at_least_one = |expr: &Expr| registers_needed(expr).max(1);
+    let needed = match expr {
+        Expr::Number(_) | Expr::SizeofExpr(_) | Expr::SizeofType(_) => 0,
+        Expr::StringLiteral(_) | Expr::Ident(_) | Expr::AddressOf(_) => 1,
+        Expr::FunctionCall(_) => pool,
+        // a shift might have to move its value out of rcx
+        Expr::Op(lhs, BinaryOp::LeftShift, rhs) | Expr::Op(lhs, BinaryOp::RightShift, rhs) => {
+            pair(lhs, rhs) + 1
+        }
+        Expr::Op(lhs, _, rhs) | Expr::Index(lhs, rhs) => pair(lhs, rhs),
+        // the value is held while the lvalue is evaluated, and the lvalue
+        // is held while its old value is combined with the new
+        Expr::Assignment { lhs, op, value } => {
+            registers_needed(value).max(lvalue_registers_needed(lhs, op) + 1)
+        }
+        Expr::PostIncrement(expr) | Expr::PostDecrement(expr) => at_least_one(expr) + 1,
+        Expr::PrefixIncrement(expr)
+        | Expr::PrefixDecrement(expr)
+        | Expr::Dereference(expr)
+        | Expr::DotProperty(expr, _)
+        | Expr::ArrowProperty(expr, _)
+        | Expr::Not(expr)
+        | Expr::BitNot(expr)
+        | Expr::Plus(expr)
+        | Expr::Neg(expr)
+        | Expr::Cast(_, expr)
+        | Expr::VaEnd(expr) => at_least_one(expr),
+        Expr::Ternary {
+            cond,
+            truthy,
+            falsey,
+        } => at_least_one(cond)
+            .max(at_least_one(truthy))
+            .max(at_least_one(falsey)),
+        Expr::VaStart(ap) | Expr::VaArg(ap, _) => at_least_one(ap) + 1,
+        Expr::VaCopy(dest, src) => pair(dest, src) + 1,
+    };
+    needed.min(pool)
+}
+
+/// the registers the target of an assignment needs, counting the old value
+/// of a compound assignment and the register to store the new value from
+fn lvalue_registers_needed(lhs: &Expr, op: &ast::AssignmentOp) -> usize {
+    match op {
+        ast::AssignmentOp::Assign => registers_needed(lhs) + 1,
+        _ => registers_needed(lhs) + 2,
+    }
+}
+
+/// evaluates both operands of a binary operator, the one that needs more
+/// registers first, so fewer values are live at once and the allocator
+/// spills less
+fn compile_operands(
+    compiler: &mut Compiler,
+    func_ctx: &mut FunctionCtx,
+    lhs: &Expr,
+    rhs: &Expr,
+) -> (Value, Value) {
+    let lhs_first = registers_needed(lhs) >= registers_needed(rhs);
+    let (first, second) = if lhs_first { (lhs, rhs) } else { (rhs, lhs) };
+    let value = compile_expr(compiler, func_ctx, first);
+    let other = compile_expr(compiler, func_ctx, second);
+    if lhs_first {
+        (value, other)
+    } else {
+        (other, value)
+    }
+}
+
+/// evaluates an expression, leaving lvalues where they are
+fn compile_expr(compiler: &mut Compiler, func_ctx: &mut FunctionCtx, expr: &Expr) -> Value {
+    match expr {
+        Expr::Number(constant) => Value::constant(constant.value, constant.ty.clone()),
+        Expr::StringLiteral(bytes) => {
+            let label = compiler.string_literal(bytes);
+            let dest = func_ctx.new_reg(Ty::I64);
+            func_ctx.emit(ir::Instruction::AddressOf {
+                dest,
+                address: ir::Address::new(Base::Global(label)),
+            });
+            Value::new(dest, pointer_to(&Type::Char))
+        }
+        Expr::Ident(ident) => compiler.lookup(func_ctx, ident),
+        Expr::AddressOf(ident) => {
+            let value = compiler.lookup(func_ctx, ident);
+            let address = match value.place {
+                Place::Memory(address) => address,
+                other => panic!("Cannot take the address of {:?}", other),
+            };
+            let dest = func_ctx.new_reg(Ty::I64);
+            func_ctx.emit(ir::Instruction::AddressOf { dest, address });
+            Value::new(dest, pointer_to(&value.ty))
         }
+        Expr::FunctionCall(call) => compile_call(compiler, func_ctx, call),
+        Expr::Op(lhs, op, rhs) => match op {
+            ast::BinaryOp::And | ast::BinaryOp::Or => {
+                operators::logical(compiler, func_ctx, lhs, op, rhs)
+            }
+            _ => {
+                let (lhs, rhs) = compile_operands(compiler, func_ctx, lhs, rhs);
+                operators::binary(compiler, func_ctx, op, lhs, rhs)
+            }
+        },
         Expr::Dereference(expr) => {
-            let addr = compile_expr(compiler, func_ctx, expr);
-            compiler.gen(Instruction::Mov(Rax.into(), addr));
-            IndirectAddress::indirect(Box::new(Rax.into()))
-                .dword()
-                .into()
+            let pointer = compile_expr(compiler, func_ctx, expr);
+            dereference(compiler, func_ctx, pointer)
+        }
+        // a[b] is *(a + b)
+        Expr::Index(array, index) => compile_expr(
+            compiler,
+            func_ctx,
+            &Expr::Dereference(Box::new(Expr::Op(
+                array.clone(),
+                ast::BinaryOp::Add,
+                index.clone(),
+            ))),
+        ),
+        Expr::DotProperty(expr, name) => {
+            let value = compile_expr(compiler, func_ctx, expr);
+            member(&value, name)
+        }
+        Expr::ArrowProperty(expr, name) => {
+            let pointer = compile_expr(compiler, func_ctx, expr);
+            let value = dereference(compiler, func_ctx, pointer);
+            member(&value, name)
         }
+        Expr::SizeofExpr(expr) => {
+            let size = compiler.type_of(func_ctx, expr).stack_size();
+            Value::constant(size as i64, Type::UnsignedLong)
+        }
+        Expr::SizeofType(ty) => {
+            let size = compiler.complete(ty).stack_size();
+            Value::constant(size as i64, Type::UnsignedLong)
+        }
+        Expr::Not(expr) => {
+            let value = compile_expr(compiler, func_ctx, expr);
+            operators::not(func_ctx, value)
+        }
+        Expr::Plus(operand) | Expr::Neg(operand) | Expr::BitNot(operand) => {
+            let op = match expr {
+                Expr::Plus(_) => operators::UnaryOp::Plus,
+                Expr::Neg(_) => operators::UnaryOp::Neg,
+                _ => operators::UnaryOp::BitNot,
+            };
+            let value = compile_expr(compiler, func_ctx, operand);
+            operators::unary(func_ctx, op, value)
+        }
+        Expr::PrefixIncrement(expr) => operators::increment(compiler, func_ctx, expr, 1, false),
+        Expr::PrefixDecrement(expr) => operators::increment(compiler, func_ctx, expr, -1, false),
+        Expr::PostIncrement(expr) => operators::increment(compiler, func_ctx, expr, 1, true),
+        Expr::PostDecrement(expr) => operators::increment(compiler, func_ctx, expr, -1, true),
+        Expr::Cast(ty, expr) => {
+            let ty = compiler.complete(ty);
+            let value = compile_expr(compiler, func_ctx, expr);
+            match ty {
+                // (void) discards the value
+                Type::Void => void(),
+                _ => Value::new(func_ctx.load_as(value, &ty), ty),
+            }
+        }
+        Expr::Ternary { .. } => operators::ternary(compiler, func_ctx, expr),
         Expr::Assignment { lhs, op, value } => {
-            assert_eq!(op.clone(), ast::AssignmentOp::Assign);
+            // the value goes first, so the lvalue's address is live for as
+            // short a time as it can be
             let value = compile_expr(compiler, func_ctx, value);
-            let lhs = compile_expr(compiler, func_ctx, lhs);
-            compiler.gen(Instruction::Mov(lhs.clone(), value));
-            lhs
-        }
-        other => {
-            eprintln!("Not implemented: {:?}", other);
-            unimplemented!()
+            let target = compile_expr(compiler, func_ctx, lhs);
+            if let Type::Struct(_) = target.ty {
+                if op.binary_op().is_some() || value.ty != target.ty {
+                    panic!("Cannot assign a {:?} to a {:?}", value.ty, target.ty);
+                }
+                copy(func_ctx, &target, &value);
+                return target;
+            }
+            if !target.ty.is_scalar() {
+                unimplemented!("Assigning a {:?}", target.ty);
+            }
+            let value = match op.binary_op() {
+                Some(op) => {
+                    let current = func_ctx.load(target.clone());
+                    let current = Value::new(current, target.ty.clone());
+                    operators::binary(compiler, func_ctx, &op, current, value)
+                }
+                None => value,
+            };
+            let src = func_ctx.load_as(value, &target.ty);
+            func_ctx.store(&target, src);
+            target
         }
+        Expr::VaStart(ap) => stdarg::va_start(compiler, func_ctx, ap),
+        Expr::VaArg(ap, ty) => stdarg::va_arg(compiler, func_ctx, ap, ty),
+        Expr::VaEnd(ap) => stdarg::va_end(compiler, func_ctx, ap),
+        Expr::VaCopy(dest, src) => stdarg::va_copy(compiler, func_ctx, dest, src),
     }
 }
 
@@ -138,20 +929,213 @@ fn compile_statement<'src>(
     func_ctx: &mut FunctionCtx<'src>,
     stmt: &'src Statement,
 ) {
+    if let Some(kind) = stmt.kind() {
+        func_ctx.start_statement(kind);
+    }
     match stmt {
         Statement::Return(expr) => {
-            let ret_address = compile_expr(compiler, func_ctx, expr);
-            compiler.gen(Instruction::Mov(Eax.into(), ret_address));
-        }
-        Statement::VariableDefinition { ty, name, value } => {
-            let value = compile_expr(compiler, func_ctx, value);
-            let symbol = Symbol::new(name, ty.clone());
-            func_ctx.register_local(symbol);
-            compiler.gen(Instruction::Mov(func_ctx.lookup(name), value));
+            let return_type = func_ctx.return_type.clone();
+            let value = match expr {
+                Some(expr) => {
+                    let value = compile_expr(compiler, func_ctx, expr);
+                    match return_type {
+                        Type::Void => None,
+                        Type::Struct(_) => Some(return_struct(func_ctx, value)),
+                        _ => Some(func_ctx.load_as(value, &return_type)),
+                    }
+                }
+                // the caller can't use a value that was never returned
+                None => None,
+            };
+            func_ctx.terminate(Terminator::Return(value));
         }
+        Statement::Declaration(decl) => compile_declaration(compiler, func_ctx, decl),
         Statement::Expr(expr) => {
             compile_expr(compiler, func_ctx, expr);
         }
+        Statement::Compound(body) => {
+            compiler.symbol_table.push_scope();
+            func_ctx.push_scope();
+            for stmt in body {
+                compile_statement(compiler, func_ctx, stmt);
+            }
+            func_ctx.pop_scope();
+            compiler.symbol_table.pop_scope();
+        }
+        Statement::Empty => {}
+        Statement::If {
+            cond,
+            then,
+            otherwise,
+        } => control_flow::if_statement(compiler, func_ctx, cond, then, otherwise.as_deref()),
+        Statement::While { cond, body } => control_flow::while_loop(compiler, func_ctx, cond, body),
+        Statement::DoWhile { body, cond } => control_flow::do_while(compiler, func_ctx, body, cond),
+        Statement::For {
+            init,
+            cond,
+            step,
+            body,
+        } => control_flow::for_loop(
+            compiler,
+            func_ctx,
+            init,
+            cond.as_deref(),
+            step.as_deref(),
+            body,
+        ),
+        Statement::Break => {
+            let target = match func_ctx.loops.last() {
+                Some(innermost) => innermost.break_to,
+                None => panic!("break statement not within a loop"),
+            };
+            func_ctx.terminate(Terminator::Jump(target));
+        }
+        Statement::Continue => {
+            let target = match func_ctx.loops.last() {
+                Some(innermost) => innermost.continue_to,
+                None => panic!("continue statement not within a loop"),
+            };
+            func_ctx.terminate(Terminator::Jump(target));
+        }
+        Statement::Goto(name) => {
+            let target = func_ctx.label(name);
+            func_ctx.terminate(Terminator::Jump(target));
+        }
+        Statement::Labeled(name, stmt) => {
+            if !func_ctx.defined_labels.insert(name) {
+                panic!("Duplicate label {}", name);
+            }
+            let block = func_ctx.label(name);
+            func_ctx.start_block(block);
+            compile_statement(compiler, func_ctx, stmt);
+        }
+    }
+}
+
+fn compile_declaration<'src>(
+    compiler: &mut Compiler<'src>,
+    func_ctx: &mut FunctionCtx<'src>,
+    decl: &'src Declaration,
+) {
+    compiler.declare_structs(&decl.specifiers.ty);
+    for init_declarator in decl.declarators.iter() {
+        let declarator = &init_declarator.declarator;
+        let ty = compiler.complete(&declarator.type_of(&decl.specifiers.ty));
+        if let Type::Function { .. } = ty {
+            declare_function(compiler, &declarator.name, ty, init_declarator);
+            continue;
+        }
+        match decl.specifiers.storage_class {
+            // lives in a data section, under a label only this function uses
+            Some(StorageClass::Static) => {
+                let label = func_ctx.static_label(&declarator.name);
+                let object = StaticObject::new(
+                    compiler,
+                    label.clone(),
+                    ty,
+                    init_declarator.initializer.as_ref(),
+                    declarator.is_const(&decl.specifiers),
+                );
+                let symbol = Symbol::new(&declarator.name, object.ty.clone());
+                compiler.report(func_ctx.register_static(symbol, label));
+                compiler.define_object(object);
+                continue;
+            }
+            // refers to an object defined at file scope or in another file
+            Some(StorageClass::Extern) => {
+                if init_declarator.initializer.is_some() {
+                    panic!("{} has both extern and an initializer", declarator.name);
+                }
+                compiler.referenced.insert(declarator.name.to_string());
+                let symbol = Symbol::new(&declarator.name, ty);
+                compiler.report(func_ctx.register_static(symbol, declarator.name.clone()));
+                continue;
+            }
+            None => {}
+        }
+        // struct point q = p; copies p
+        if let (Type::Struct(_), Some(Initializer::Expr(expr))) =
+            (&ty, &init_declarator.initializer)
+        {
+            let value = compile_expr(compiler, func_ctx, expr);
+            if value.ty != ty {
+                panic!("Cannot initialize a {:?} with a {:?}", ty, value.ty);
+            }
+            compiler.report(func_ctx.register_local(Symbol::new(&declarator.name, ty)));
+            let local = compiler.lookup(func_ctx, &declarator.name);
+            copy(func_ctx, &local, &value);
+            continue;
+        }
+        // uninitialized locals only need somewhere to be
+        let (ty, entries) = match &init_declarator.initializer {
+            Some(init) => initializer::flatten(&ty, init),
+            None => (ty, vec![]),
+        };
+        compiler.report(func_ctx.register_local(Symbol::new(&declarator.name, ty)));
+        let local = compiler.lookup(func_ctx, &declarator.name);
+        if init_declarator.initializer.is_some() && !local.ty.is_scalar() {
+            zero_fill(func_ctx, &local);
+        }
+        for InitEntry { offset, ty, expr } in entries {
+            let value = compile_expr(compiler, func_ctx, &expr);
+            let dest = match local.ty.is_scalar() {
+                true => local.clone(),
+                false => subobject(&local, offset, &ty),
+            };
+            let src = func_ctx.load_as(value, &ty);
+            func_ctx.store(&dest, src);
+        }
+    }
+}
+
+/// a prototype, which only tells calls the function's type
+fn declare_function<'src>(
+    compiler: &mut Compiler<'src>,
+    name: &'src str,
+    ty: Type,
+    init_declarator: &InitDeclarator,
+) {
+    if init_declarator.initializer.is_some() {
+        panic!("Function {} is initialized like a variable", name);
+    }
+    declare(compiler, Symbol::new(name, ty));
+}
+
+fn declare<'src>(compiler: &mut Compiler<'src>, symbol: Symbol<'src>) {
+    let result = compiler.symbol_table.declare(symbol);
+    compiler.report(result);
+}
+
+/// zeroes every byte of an object, before the initialized members are stored
+fn zero_fill(func_ctx: &mut FunctionCtx, object: &Value) {
+    let size = object.ty.stack_size();
+    let mut offset = 0;
+    for (chunk, ty) in &[(8, Type::Long), (4, Type::Int), (1, Type::Char)] {
+        while size - offset >= *chunk {
+            let dest = subobject(object, offset, ty);
+            func_ctx.store(&dest, Operand::Const(0, Ty::of(ty)));
+            offset += chunk;
+        }
+    }
+}
+
+/// an object in the stack frame for a struct value that isn't stored
+/// anywhere else, like the result of a conditional expression
+fn temporary_object(func_ctx: &mut FunctionCtx, ty: &Type) -> Value {
+    let slot = func_ctx.function.new_slot(ty);
+    Value::memory(ir::Address::new(Base::Slot(slot)), ty.clone())
+}
+
+/// copies every byte of an object to another of the same type
+fn copy(func_ctx: &mut FunctionCtx, dest: &Value, src: &Value) {
+    let size = dest.ty.stack_size();
+    let mut offset = 0;
+    for (chunk, ty) in &[(8, Type::Long), (4, Type::Int), (1, Type::Char)] {
+        while size - offset >= *chunk {
+            let value = func_ctx.load(subobject(src, offset, ty));
+            func_ctx.store(&subobject(dest, offset, ty), value);
+            offset += chunk;
+        }
     }
 }
 
@@ -160,39 +1144,194 @@ fn compile_func<'src>(compiler: &mut Compiler<'src>, func: &'src FunctionDefinit
         "main" => platform::main_symbol().to_string(),
         name => name.to_string(),
     };
-    let symbol = Symbol::new(func.name.as_str(), func.type_of());
-    compiler.symbol_table.insert_symbol(symbol);
+    declare(compiler, Symbol::new(func.name.as_str(), func.type_of()));
+    if compiler.declare_linkage(&func.name, func.storage_class, true) == Linkage::External {
+        compiler.exported.insert(name.clone());
+    }
     compiler.symbol_table.push_scope();
-    let mut func_ctx = FunctionCtx::new();
-
-    compiler
-        // name the function
-        .gen_label(name)
-        // save stack pointer
-        .gen(Instruction::Push(Rbp))
-        // set frame pointer to stack pointer (so we can alloc stack space)
-        .gen(Instruction::Mov(Rbp.into(), Rsp.into()));
-
-    for (i, param) in func.parameters.iter().enumerate() {
-        let symbol = Symbol::new(param.name.as_ref(), param.ty.clone());
-        func_ctx.register_local(symbol.clone());
-        let register = func_parameter_register(i);
-        compiler.gen(Instruction::Mov(func_ctx.lookup(symbol.name()), register));
+    let return_type = compiler.complete(&func.return_type);
+    let return_ty = match return_type {
+        Type::Void => None,
+        // a struct is returned by its address
+        Type::Struct(_) => Some(Ty::I64),
+        _ => Some(Ty::of(&return_type)),
+    };
+    let mut function = ir::Function::new(name, return_ty, func.variadic);
+    function.inline = func.is_inline;
+    function.returned_struct = returned_struct(&return_type);
+    let mut func_ctx = FunctionCtx::new(function, return_type, &func.body);
+    let entry = func_ctx.new_block();
+    func_ctx.start_block(entry);
+
+    let mut in_registers = vec![];
+    let mut on_stack = vec![];
+    // a struct too big for registers is returned to where the first
+    // argument points
+    if let (Type::Struct(_), None) = (&func_ctx.return_type, func_ctx.function.returned_struct) {
+        let result = func_ctx.new_reg(Ty::I64);
+        in_registers.push(result);
+        func_ctx.result = Some(result);
+    }
+    for param in func.parameters.iter() {
+        // definitions always name their parameters
+        let name = param.name.as_deref().unwrap();
+        let ty = compiler.complete(&param.ty);
+        let arguments = match ty {
+            // a struct is put back together from its eightbytes
+            Type::Struct(_) => {
+                let slot = eightbyte_slot(&mut func_ctx, &ty);
+                let symbol = Symbol::new(name, ty.clone());
+                compiler.report(func_ctx.define_local(symbol, Storage::Slot(slot)));
+                let local = compiler.lookup(&func_ctx, name);
+                let eightbytes: Vec<Reg> = (0..ty.stack_size().div_ceil(8))
+                    .map(|_| func_ctx.new_reg(Ty::I64))
+                    .collect();
+                for (i, eightbyte) in eightbytes.iter().enumerate() {
+                    func_ctx.store(&subobject(&local, 8 * i, &Type::Long), (*eightbyte).into());
+                }
+                eightbytes
+            }
+            _ => {
+                let argument = func_ctx.new_reg(Ty::of(&ty));
+                if func_ctx.address_taken.contains(name) {
+                    compiler.report(func_ctx.register_local(Symbol::new(name, ty.clone())));
+                    let local = compiler.lookup(&func_ctx, name);
+                    func_ctx.store(&local, argument.into());
+                } else {
+                    let symbol = Symbol::new(name, ty.clone());
+                    compiler.report(func_ctx.define_local(symbol, Storage::Register(argument)));
+                }
+                vec![argument]
+            }
+        };
+        match passed_in_registers(&ty, in_registers.len()) {
+            true => in_registers.extend(arguments),
+            false => on_stack.extend(arguments),
+        }
     }
+    func_ctx.function.parameters_in_registers = in_registers.len();
+    func_ctx.function.parameters = in_registers;
+    func_ctx.function.parameters.extend(on_stack);
     for stmt in func.body.iter() {
-        compile_statement(compiler, &mut func_ctx, &stmt);
+        compile_statement(compiler, &mut func_ctx, stmt);
+    }
+    for name in func_ctx.labels.keys() {
+        if !func_ctx.defined_labels.contains(name) {
+            panic!("Label {} used but not defined", name);
+        }
+    }
+    if func_ctx.current.is_some() {
+        // reaching the end of main returns 0
+        let value = match func.name.as_str() {
+            "main" => Some(Operand::Const(0, Ty::I32)),
+            _ => None,
+        };
+        func_ctx.terminate(Terminator::Return(value));
     }
-    compiler.gen(Instruction::Pop(Rbp)).gen(Instruction::Ret);
+    compiler.functions.push(func_ctx.function);
     compiler.symbol_table.pop_scope();
 }
 
-pub fn compile(program: &Program) -> Vec<Instruction> {
+fn constant_value(compiler: &mut Compiler, expr: &Expr) -> DataValue {
+    use ast::BinaryOp;
+    let mut int = |expr| match constant_value(compiler, expr) {
+        DataValue::Int(val) => val,
+        DataValue::Label(_) => {
+            panic!("Pointer arithmetic in constant expressions is not supported")
+        }
+    };
+    match expr {
+        Expr::Number(constant) => DataValue::Int(constant.value),
+        Expr::StringLiteral(bytes) => DataValue::Label(compiler.string_literal(bytes)),
+        Expr::AddressOf(ident) => {
+            compiler.referenced.insert(ident.to_string());
+            DataValue::Label(ident.to_string())
+        }
+        // an array is the address of its first element
+        Expr::Ident(ident) => match compiler.symbol_table.lookup_symbol(ident) {
+            Some(symbol) if matches!(symbol.type_of(), Type::Array(..)) => {
+                compiler.referenced.insert(ident.to_string());
+                DataValue::Label(ident.to_string())
+            }
+            _ => panic!("Initializer element is not constant: {:?}", expr),
+        },
+        Expr::Plus(expr) => DataValue::Int(int(expr)),
+        Expr::Neg(expr) => DataValue::Int(int(expr).wrapping_neg()),
+        Expr::BitNot(expr) => DataValue::Int(!int(expr)),
+        Expr::Op(lhs, op, rhs) => {
+            let (lhs, rhs) = (int(lhs), int(rhs));
+            DataValue::Int(match op {
+                BinaryOp::Add => lhs.wrapping_add(rhs),
+                BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                _ => panic!("Unsupported operator in constant expression: {:?}", op),
+            })
+        }
+        other => panic!("Initializer element is not constant: {:?}", other),
+    }
+}
+
+fn compile_global<'src>(compiler: &mut Compiler<'src>, decl: &'src Declaration) {
+    compiler.declare_structs(&decl.specifiers.ty);
+    let storage_class = decl.specifiers.storage_class;
+    for init_declarator in decl.declarators.iter() {
+        let declarator = &init_declarator.declarator;
+        let name = declarator.name.as_str();
+        let ty = compiler.complete(&declarator.type_of(&decl.specifiers.ty));
+        let is_function = matches!(ty, Type::Function { .. });
+        let linkage = compiler.declare_linkage(name, storage_class, is_function);
+        if is_function {
+            declare_function(compiler, name, ty, init_declarator);
+            continue;
+        }
+        // `extern int a;` only says a is defined somewhere
+        if storage_class == Some(StorageClass::Extern) && init_declarator.initializer.is_none() {
+            declare(compiler, Symbol::new(name, ty));
+            continue;
+        }
+        let object = StaticObject::new(
+            compiler,
+            name.to_string(),
+            ty,
+            init_declarator.initializer.as_ref(),
+            declarator.is_const(&decl.specifiers),
+        );
+        if linkage == Linkage::External {
+            compiler.exported.insert(name.to_string());
+        }
+        declare(compiler, Symbol::new(name, object.ty.clone()));
+        compiler.define_object(object);
+    }
+}
+
+/// the module for a program, or every semantic error in it
+pub fn compile(program: &Program) -> Result<ir::Module, Vec<String>> {
     let mut compiler = Compiler::new();
     compiler.symbol_table.push_scope();
-    for func in program.functions.iter() {
-        compile_func(&mut compiler, func);
+    for item in program.items.iter() {
+        if let ExternalDeclaration::FunctionDefinition(func) = item {
+            if !compiler.defined_functions.insert(&func.name) {
+                compiler.report(Err(format!("redefinition of {}", func.name)));
+            }
+        }
+    }
+    for item in program.items.iter() {
+        match item {
+            ExternalDeclaration::FunctionDefinition(func) => compile_func(&mut compiler, func),
+            ExternalDeclaration::Declaration(decl) => compile_global(&mut compiler, decl),
+        }
     }
     compiler.symbol_table.pop_scope();
-
-    compiler.instructions
+    if !compiler.errors.is_empty() {
+        return Err(compiler.errors);
+    }
+    for object in std::mem::take(&mut compiler.objects) {
+        compiler.emit_object(object);
+    }
+    Ok(ir::Module {
+        functions: compiler.functions,
+        data: compiler.data_sections,
+        exported: compiler.exported,
+        referenced: compiler.referenced,
+    })
 }
@@ -0,0 +1,136 @@
+//! Selection and iteration statements. Each one is laid out as blocks in
+//! the order they run the first time through, and a for loop without a
+//! condition jumps straight into its body.
+
+use super::{compile_expr, compile_statement, Compiler, FunctionCtx, Loop};
+use crate::ast::{Expr, Statement};
+use crate::ir::{BlockId, Terminator};
+
+/// goes to `then` if the expression is nonzero and to `otherwise` if not
+fn branch(
+    compiler: &mut Compiler,
+    func_ctx: &mut FunctionCtx,
+    cond: &Expr,
+    then: BlockId,
+    otherwise: BlockId,
+) {
+    let value = compile_expr(compiler, func_ctx, cond);
+    let cond = func_ctx.load(value);
+    func_ctx.terminate(Terminator::Branch {
+        cond,
+        then,
+        otherwise,
+    });
+}
+
+/// the body of a loop, where break and continue go to the given blocks
+fn loop_body<'src>(
+    compiler: &mut Compiler<'src>,
+    func_ctx: &mut FunctionCtx<'src>,
+    body: &'src Statement,
+    break_to: BlockId,
+    continue_to: BlockId,
+) {
+    func_ctx.loops.push(Loop {
+        break_to,
+        continue_to,
+    });
+    compile_statement(compiler, func_ctx, body);
+    func_ctx.loops.pop();
+}
+
+pub fn if_statement<'src>(
+    compiler: &mut Compiler<'src>,
+    func_ctx: &mut FunctionCtx<'src>,
+    cond: &'src Expr,
+    then: &'src Statement,
+    otherwise: Option<&'src Statement>,
+) {
+    let (then_block, end) = (func_ctx.new_block(), func_ctx.new_block());
+    let otherwise_block = match otherwise {
+        Some(_) => func_ctx.new_block(),
+        None => end,
+    };
+    branch(compiler, func_ctx, cond, then_block, otherwise_block);
+    func_ctx.start_block(then_block);
+    compile_statement(compiler, func_ctx, then);
+    if let Some(otherwise) = otherwise {
+        func_ctx.jump(end);
+        func_ctx.start_block(otherwise_block);
+        compile_statement(compiler, func_ctx, otherwise);
+    }
+    func_ctx.start_block(end);
+}
+
+pub fn while_loop<'src>(
+    compiler: &mut Compiler<'src>,
+    func_ctx: &mut FunctionCtx<'src>,
+    cond: &'src Expr,
+    body: &'src Statement,
+) {
+    let (test, body_block, end) = (
+        func_ctx.new_block(),
+        func_ctx.new_block(),
+        func_ctx.new_block(),
+    );
+    func_ctx.start_block(test);
+    branch(compiler, func_ctx, cond, body_block, end);
+    func_ctx.start_block(body_block);
+    loop_body(compiler, func_ctx, body, end, test);
+    func_ctx.jump(test);
+    func_ctx.start_block(end);
+}
+
+pub fn do_while<'src>(
+    compiler: &mut Compiler<'src>,
+    func_ctx: &mut FunctionCtx<'src>,
+    body: &'src Statement,
+    cond: &'src Expr,
+) {
+    let (body_block, test, end) = (
+        func_ctx.new_block(),
+        func_ctx.new_block(),
+        func_ctx.new_block(),
+    );
+    func_ctx.start_block(body_block);
+    loop_body(compiler, func_ctx, body, end, test);
+    func_ctx.start_block(test);
+    branch(compiler, func_ctx, cond, body_block, end);
+    func_ctx.start_block(end);
+}
+
+/// init runs once in a scope of its own, then the loop goes test, body,
+/// step, test and so on
+pub fn for_loop<'src>(
+    compiler: &mut Compiler<'src>,
+    func_ctx: &mut FunctionCtx<'src>,
+    init: &'src Statement,
+    cond: Option<&'src Expr>,
+    step: Option<&'src Expr>,
+    body: &'src Statement,
+) {
+    compiler.symbol_table.push_scope();
+    func_ctx.push_scope();
+    compile_statement(compiler, func_ctx, init);
+    let (test, body_block, step_block, end) = (
+        func_ctx.new_block(),
+        func_ctx.new_block(),
+        func_ctx.new_block(),
+        func_ctx.new_block(),
+    );
+    func_ctx.start_block(test);
+    match cond {
+        Some(cond) => branch(compiler, func_ctx, cond, body_block, end),
+        None => func_ctx.terminate(Terminator::Jump(body_block)),
+    }
+    func_ctx.start_block(body_block);
+    loop_body(compiler, func_ctx, body, end, step_block);
+    func_ctx.start_block(step_block);
+    if let Some(step) = step {
+        compile_expr(compiler, func_ctx, step);
+    }
+    func_ctx.terminate(Terminator::Jump(test));
+    func_ctx.start_block(end);
+    func_ctx.pop_scope();
+    compiler.symbol_table.pop_scope();
+}
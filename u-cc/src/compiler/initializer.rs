@@ -0,0 +1,219 @@
+//! Flattening of initializers into the scalar stores they imply, which
+//! become either generated stores for locals or static data for globals.
+//!
+//! Braces can be elided for nested aggregates, so `int a[2][2] = {1, 2, 3, 4}`
+//! fills both rows, and designators always refer to the object whose braces
+//! directly enclose them.
+
+use crate::ast::{Designator, Expr, Initializer, IntConstant, Type};
+use std::collections::BTreeMap;
+
+type InitializerList = [(Vec<Designator>, Initializer)];
+
+/// a scalar to store at an offset into the initialized object. Everything
+/// that isn't covered by an entry is zero.
+#[derive(Debug)]
+pub struct InitEntry {
+    pub offset: usize,
+    pub ty: Type,
+    pub expr: Expr,
+}
+
+/// flattens `init` for an object of type `ty`. Also returns the completed
+/// type, since `int a[] = {1, 2, 3}` takes its length from the initializer.
+pub fn flatten(ty: &Type, init: &Initializer) -> (Type, Vec<InitEntry>) {
+    let mut flattener = Flattener {
+        entries: BTreeMap::new(),
+    };
+    let len = flattener.object(ty, init, 0);
+    let ty = match ty {
+        Type::Array(elem, None) => Type::Array(elem.clone(), Some(len)),
+        ty => ty.clone(),
+    };
+    let entries = flattener
+        .entries
+        .into_iter()
+        .map(|(offset, (ty, expr))| InitEntry { offset, ty, expr })
+        .collect();
+    (ty, entries)
+}
+
+fn is_char(ty: &Type) -> bool {
+    matches!(ty, Type::Char | Type::UnsignedChar)
+}
+
+/// the string a char array is initialized from, if this is one
+fn string_initializer<'a>(ty: &Type, init: &'a Initializer) -> Option<&'a [u8]> {
+    match (ty, init) {
+        (Type::Array(elem, _), Initializer::Expr(expr)) if is_char(elem) => match &**expr {
+            Expr::StringLiteral(bytes) => Some(bytes),
+            _ => None,
+        },
+        // char s[] = { "abc" } is allowed too
+        (Type::Array(_, _), Initializer::List(items)) => match items.as_slice() {
+            [(designators, init)] if designators.is_empty() => string_initializer(ty, init),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// the type and offset of the nth element of an aggregate
+fn element(ty: &Type, index: usize) -> Option<(Type, usize)> {
+    match ty {
+        Type::Array(elem, len) if len.is_none_or(|len| index < len) => {
+            Some(((**elem).clone(), index * elem.stack_size()))
+        }
+        Type::Struct(struct_type) => {
+            let members = struct_type.members.as_ref()?;
+            let member = members.get(index)?;
+            Some((member.ty.clone(), struct_type.layout().offsets[index]))
+        }
+        _ => None,
+    }
+}
+
+struct Flattener {
+    /// keyed by offset, so a later designator overrides an earlier value
+    entries: BTreeMap<usize, (Type, Expr)>,
+}
+
+impl Flattener {
+    /// initializes a whole object, returning how many elements it had
+    fn object(&mut self, ty: &Type, init: &Initializer, offset: usize) -> usize {
+        if let Some(bytes) = string_initializer(ty, init) {
+            return self.string(ty, bytes, offset);
+        }
+        match init {
+            Initializer::Expr(expr) if ty.is_scalar() => {
+                self.entries.insert(offset, (ty.clone(), (**expr).clone()));
+                1
+            }
+            Initializer::Expr(expr) => {
+                panic!("Cannot initialize a {:?} with {:?}", ty, expr)
+            }
+            // int a = { 1 };
+            Initializer::List(items) if ty.is_scalar() => match items.first() {
+                Some((_, init)) => self.object(ty, init, offset),
+                None => 1,
+            },
+            Initializer::List(items) => self.aggregate(ty, items, &mut 0, offset, true, 0),
+        }
+    }
+
+    fn string(&mut self, ty: &Type, bytes: &[u8], offset: usize) -> usize {
+        let elem = match ty {
+            Type::Array(elem, _) => elem,
+            _ => unreachable!(),
+        };
+        // the nul terminator is dropped if the array is exactly long enough
+        let len = match ty {
+            Type::Array(_, Some(len)) => *len,
+            _ => bytes.len() + 1,
+        };
+        for (i, byte) in bytes
+            .iter()
+            .chain(std::iter::once(&0))
+            .take(len)
+            .enumerate()
+        {
+            let value = Expr::Number(IntConstant::int(i32::from(*byte as i8)));
+            self.entries.insert(offset + i, ((**elem).clone(), value));
+        }
+        bytes.len() + 1
+    }
+
+    /// initializes the elements of an aggregate from `items`, starting at
+    /// `pos` and element `index`. Without braces of its own, it only takes
+    /// the items it needs and leaves designators for the enclosing list.
+    fn aggregate(
+        &mut self,
+        ty: &Type,
+        items: &InitializerList,
+        pos: &mut usize,
+        offset: usize,
+        braced: bool,
+        mut index: usize,
+    ) -> usize {
+        let mut len = 0;
+        while let Some((designators, init)) = items.get(*pos) {
+            if !designators.is_empty() {
+                if !braced {
+                    break;
+                }
+                index = self.designated(ty, designators, items, pos, offset);
+            } else {
+                let (elem, elem_offset) = match element(ty, index) {
+                    Some(element) => element,
+                    None if braced => panic!("Excess elements in initializer for {:?}", ty),
+                    None => break,
+                };
+                match init {
+                    Initializer::Expr(_)
+                        if !elem.is_scalar() && string_initializer(&elem, init).is_none() =>
+                    {
+                        self.aggregate(&elem, items, pos, offset + elem_offset, false, 0);
+                    }
+                    _ => {
+                        self.object(&elem, init, offset + elem_offset);
+                        *pos += 1;
+                    }
+                }
+            }
+            index += 1;
+            len = len.max(index);
+        }
+        len
+    }
+
+    /// initializes the subobject named by `designators` from the item at
+    /// `pos`, and returns the index of the element the first designator
+    /// picked. Like gcc, items after `[1].x = 6` go on to initialize the
+    /// rest of `[1]`.
+    fn designated(
+        &mut self,
+        ty: &Type,
+        designators: &[Designator],
+        items: &InitializerList,
+        pos: &mut usize,
+        offset: usize,
+    ) -> usize {
+        let index = match (&designators[0], ty) {
+            (Designator::Index(index), Type::Array(_, _)) => match index.integer_constant() {
+                Some(index) if index >= 0 => index as usize,
+                _ => panic!("Array index in initializer is not a non-negative constant"),
+            },
+            (Designator::Member(name), Type::Struct(struct_type)) => struct_type
+                .members
+                .iter()
+                .flatten()
+                .position(|member| &member.name == name)
+                .unwrap_or_else(|| panic!("No member named {} in {:?}", name, ty)),
+            (designator, ty) => panic!("Designator {:?} used for {:?}", designator, ty),
+        };
+        let (elem, elem_offset) = element(ty, index)
+            .unwrap_or_else(|| panic!("Designator index {} is out of bounds for {:?}", index, ty));
+        let offset = offset + elem_offset;
+        let init = &items[*pos].1;
+        match &designators[1..] {
+            [] if matches!(init, Initializer::Expr(_))
+                && !elem.is_scalar()
+                && string_initializer(&elem, init).is_none() =>
+            {
+                // [1] = 5 for an aggregate element elides its braces
+                let items = [(vec![], init.clone())];
+                self.aggregate(&elem, &items, &mut 0, offset, false, 0);
+                *pos += 1;
+            }
+            [] => {
+                self.object(&elem, init, offset);
+                *pos += 1;
+            }
+            rest => {
+                let inner = self.designated(&elem, rest, items, pos, offset);
+                self.aggregate(&elem, items, pos, offset, false, inner + 1);
+            }
+        }
+        index
+    }
+}
@@ -0,0 +1,395 @@
+//! Unary and binary operators, on values that have already been evaluated.
+//! The operands are converted to the type the operator works in first, so
+//! both sides of an IR instruction have the same width. && and || and the
+//! conditional operator branch, since they decide what gets evaluated.
+
+use super::{
+    compile_expr, copy, decay, temporary_object, void, Compiler, FunctionCtx, Place, Value,
+};
+use crate::ast::{BinaryOp, Expr, Type};
+use crate::ir::{self, Comparison, Operand, Terminator, Ty};
+
+pub enum UnaryOp {
+    Plus,
+    Neg,
+    BitNot,
+}
+
+/// +a, -a and ~a, which promote their operand
+pub fn unary(func_ctx: &mut FunctionCtx, op: UnaryOp, value: Value) -> Value {
+    let ty = value.ty.promote();
+    let src = func_ctx.load_as(value, &ty);
+    // negative constants are written as negated literals
+    if let Operand::Const(val, width) = src {
+        let val = match op {
+            UnaryOp::Plus => val,
+            UnaryOp::Neg => val.wrapping_neg(),
+            UnaryOp::BitNot => !val,
+        };
+        return Value::new(Operand::Const(width.truncate(val), width), ty);
+    }
+    let op = match op {
+        UnaryOp::Plus => return Value::new(src, ty),
+        UnaryOp::Neg => ir::UnaryOp::Neg,
+        UnaryOp::BitNot => ir::UnaryOp::Not,
+    };
+    let dest = func_ctx.new_reg(src.ty());
+    func_ctx.emit(ir::Instruction::Unary { op, dest, src });
+    Value::new(dest, ty)
+}
+
+/// !a, which is 1 if a compares equal to zero
+pub fn not(func_ctx: &mut FunctionCtx, value: Value) -> Value {
+    let lhs = func_ctx.load(value);
+    let rhs = Operand::Const(0, lhs.ty());
+    compare_operands(func_ctx, Comparison::Eq, lhs, rhs)
+}
+
+/// every binary operator except && and ||, which decide whether their
+/// right operand is evaluated at all
+pub fn binary(
+    compiler: &mut Compiler,
+    func_ctx: &mut FunctionCtx,
+    op: &BinaryOp,
+    lhs: Value,
+    rhs: Value,
+) -> Value {
+    let is_pointer = |ty: &Type| matches!(ty, Type::Pointer(_) | Type::Array(..));
+    match op {
+        BinaryOp::Add | BinaryOp::Sub if is_pointer(&lhs.ty) || is_pointer(&rhs.ty) => {
+            pointer_arithmetic(compiler, func_ctx, op, lhs, rhs)
+        }
+        BinaryOp::Add
+        | BinaryOp::Sub
+        | BinaryOp::Mul
+        | BinaryOp::BitAnd
+        | BinaryOp::BitXor
+        | BinaryOp::BitOr
+        | BinaryOp::Div
+        | BinaryOp::Mod => arithmetic(func_ctx, op, lhs, rhs),
+        BinaryOp::LeftShift | BinaryOp::RightShift => shift(func_ctx, op, lhs, rhs),
+        BinaryOp::Equal
+        | BinaryOp::NotEqual
+        | BinaryOp::LessThan
+        | BinaryOp::GreaterThan
+        | BinaryOp::LessThanEqual
+        | BinaryOp::GreaterThanEqual => compare(func_ctx, op, lhs, rhs),
+        BinaryOp::And | BinaryOp::Or => unreachable!("{:?} is compiled by logical", op),
+    }
+}
+
+/// emits an instruction for lhs op rhs, with both in the width of `ty`
+fn emit_binary(
+    func_ctx: &mut FunctionCtx,
+    op: ir::BinaryOp,
+    lhs: Operand,
+    rhs: Operand,
+    ty: Type,
+) -> Value {
+    let dest = func_ctx.new_reg(Ty::of(&ty));
+    func_ctx.emit(ir::Instruction::Binary { op, dest, lhs, rhs });
+    Value::new(dest, ty)
+}
+
+/// operators done in the common type of their operands. Division and
+/// remainder depend on its signedness.
+fn arithmetic(func_ctx: &mut FunctionCtx, op: &BinaryOp, lhs: Value, rhs: Value) -> Value {
+    let ty = Type::common(&lhs.ty, &rhs.ty);
+    let signed = ty.is_signed();
+    let op = match op {
+        BinaryOp::Add => ir::BinaryOp::Add,
+        BinaryOp::Sub => ir::BinaryOp::Sub,
+        BinaryOp::Mul => ir::BinaryOp::Mul,
+        BinaryOp::BitAnd => ir::BinaryOp::And,
+        BinaryOp::BitXor => ir::BinaryOp::Xor,
+        BinaryOp::BitOr => ir::BinaryOp::Or,
+        BinaryOp::Div if signed => ir::BinaryOp::SDiv,
+        BinaryOp::Div => ir::BinaryOp::UDiv,
+        BinaryOp::Mod if signed => ir::BinaryOp::SRem,
+        BinaryOp::Mod => ir::BinaryOp::URem,
+        _ => unreachable!("{:?} is not arithmetic", op),
+    };
+    let lhs = func_ctx.load_as(lhs, &ty);
+    let rhs = func_ctx.load_as(rhs, &ty);
+    emit_binary(func_ctx, op, lhs, rhs, ty)
+}
+
+/// pointer + integer scales the integer by the size of the pointee, and
+/// pointer - pointer counts the elements between them
+fn pointer_arithmetic(
+    compiler: &mut Compiler,
+    func_ctx: &mut FunctionCtx,
+    op: &BinaryOp,
+    lhs: Value,
+    rhs: Value,
+) -> Value {
+    let pointee = |ty: &Type| match ty {
+        Type::Pointer(pointee) | Type::Array(pointee, _) => Some((**pointee).clone()),
+        _ => None,
+    };
+    let (pointer, index, pointee) = match (pointee(&lhs.ty), pointee(&rhs.ty), op) {
+        (Some(pointee), Some(_), BinaryOp::Sub) => {
+            let size = compiler.complete(&pointee).stack_size() as i64;
+            return pointer_difference(func_ctx, lhs, rhs, size);
+        }
+        (Some(pointee), None, _) => (lhs, rhs, pointee),
+        (None, Some(pointee), BinaryOp::Add) => (rhs, lhs, pointee),
+        _ => panic!(
+            "Invalid operands to {:?}: {:?} and {:?}",
+            op, lhs.ty, rhs.ty
+        ),
+    };
+    let pointee = compiler.complete(&pointee);
+    let ty = super::pointer_to(&pointee);
+    let scale = pointee.stack_size() as i64;
+    if !index.ty.is_integer() {
+        panic!("Invalid operands to {:?}: {:?} and {:?}", op, ty, index.ty);
+    }
+    let pointer = func_ctx.load_as(pointer, &ty);
+    let offset = match func_ctx.load_as(index, &Type::Long) {
+        Operand::Const(val, width) => Operand::Const(val.wrapping_mul(scale), width),
+        index if scale == 1 => index,
+        index => {
+            let scaled = Operand::Const(scale, Ty::I64);
+            let value = emit_binary(func_ctx, ir::BinaryOp::Mul, index, scaled, Type::Long);
+            func_ctx.load(value)
+        }
+    };
+    let op = match op {
+        BinaryOp::Add => ir::BinaryOp::Add,
+        _ => ir::BinaryOp::Sub,
+    };
+    emit_binary(func_ctx, op, pointer, offset, ty)
+}
+
+fn pointer_difference(func_ctx: &mut FunctionCtx, lhs: Value, rhs: Value, size: i64) -> Value {
+    let lhs = func_ctx.load_as(lhs, &Type::Long);
+    let rhs = func_ctx.load_as(rhs, &Type::Long);
+    let difference = emit_binary(func_ctx, ir::BinaryOp::Sub, lhs, rhs, Type::Long);
+    let (op, rhs) = match size {
+        1 => return difference,
+        _ if size.count_ones() == 1 => (ir::BinaryOp::Sar, i64::from(size.trailing_zeros())),
+        _ => (ir::BinaryOp::SDiv, size),
+    };
+    let difference = func_ctx.load(difference);
+    emit_binary(
+        func_ctx,
+        op,
+        difference,
+        Operand::Const(rhs, Ty::I64),
+        Type::Long,
+    )
+}
+
+/// the operands are promoted separately, and the result has the type of
+/// the left one
+fn shift(func_ctx: &mut FunctionCtx, op: &BinaryOp, lhs: Value, rhs: Value) -> Value {
+    let ty = lhs.ty.promote();
+    let count_ty = rhs.ty.promote();
+    let lhs = func_ctx.load_as(lhs, &ty);
+    let count = func_ctx.load_as(rhs, &count_ty);
+    let count = func_ctx.convert(count, &count_ty, &ty);
+    let op = match op {
+        BinaryOp::LeftShift => ir::BinaryOp::Shl,
+        _ if ty.is_signed() => ir::BinaryOp::Sar,
+        _ => ir::BinaryOp::Shr,
+    };
+    emit_binary(func_ctx, op, lhs, count, ty)
+}
+
+/// the comparison an operator makes. Pointers and unsigned values are
+/// compared unsigned.
+fn comparison(op: &BinaryOp, signed: bool) -> Comparison {
+    match (op, signed) {
+        (BinaryOp::Equal, _) => Comparison::Eq,
+        (BinaryOp::NotEqual, _) => Comparison::Ne,
+        (BinaryOp::LessThan, true) => Comparison::SLt,
+        (BinaryOp::LessThan, false) => Comparison::ULt,
+        (BinaryOp::GreaterThan, true) => Comparison::SGt,
+        (BinaryOp::GreaterThan, false) => Comparison::UGt,
+        (BinaryOp::LessThanEqual, true) => Comparison::SLe,
+        (BinaryOp::LessThanEqual, false) => Comparison::ULe,
+        (BinaryOp::GreaterThanEqual, true) => Comparison::SGe,
+        (BinaryOp::GreaterThanEqual, false) => Comparison::UGe,
+        _ => unreachable!("{:?} is not a comparison", op),
+    }
+}
+
+/// the int a comparison results in
+fn compare_operands(
+    func_ctx: &mut FunctionCtx,
+    comparison: Comparison,
+    lhs: Operand,
+    rhs: Operand,
+) -> Value {
+    let dest = func_ctx.new_reg(Ty::I32);
+    func_ctx.emit(ir::Instruction::Compare {
+        comparison,
+        dest,
+        lhs,
+        rhs,
+    });
+    Value::new(dest, Type::Int)
+}
+
+fn compare(func_ctx: &mut FunctionCtx, op: &BinaryOp, lhs: Value, rhs: Value) -> Value {
+    let ty = match (decay(&lhs.ty), decay(&rhs.ty)) {
+        (ty @ Type::Pointer(_), _) | (_, ty @ Type::Pointer(_)) => ty,
+        (lhs, rhs) => Type::common(&lhs, &rhs),
+    };
+    let lhs = func_ctx.load_as(lhs, &ty);
+    let rhs = func_ctx.load_as(rhs, &ty);
+    compare_operands(func_ctx, comparison(op, ty.is_signed()), lhs, rhs)
+}
+
+/// && and || only evaluate their right operand if the left one doesn't
+/// decide the result
+pub fn logical(
+    compiler: &mut Compiler,
+    func_ctx: &mut FunctionCtx,
+    lhs: &Expr,
+    op: &BinaryOp,
+    rhs: &Expr,
+) -> Value {
+    let (decided, end) = (func_ctx.new_block(), func_ctx.new_block());
+    // && is decided by a false operand and || by a true one
+    let result = match op {
+        BinaryOp::And => 0,
+        _ => 1,
+    };
+    for operand in &[lhs, rhs] {
+        let value = compile_expr(compiler, func_ctx, operand);
+        let cond = func_ctx.load(value);
+        let next = func_ctx.new_block();
+        let (then, otherwise) = match result {
+            0 => (next, decided),
+            _ => (decided, next),
+        };
+        func_ctx.terminate(Terminator::Branch {
+            cond,
+            then,
+            otherwise,
+        });
+        func_ctx.start_block(next);
+    }
+    let dest = func_ctx.new_reg(Ty::I32);
+    func_ctx.emit(ir::Instruction::Copy {
+        dest,
+        src: Operand::Const(1 - result, Ty::I32),
+    });
+    func_ctx.terminate(Terminator::Jump(end));
+    func_ctx.start_block(decided);
+    func_ctx.emit(ir::Instruction::Copy {
+        dest,
+        src: Operand::Const(result, Ty::I32),
+    });
+    func_ctx.start_block(end);
+    Value::new(dest, Type::Int)
+}
+
+/// cond ? truthy : falsey. Both branches leave their value in the same
+/// register, or copy a struct to the same object.
+pub fn ternary(compiler: &mut Compiler, func_ctx: &mut FunctionCtx, expr: &Expr) -> Value {
+    let (cond, truthy, falsey) = match expr {
+        Expr::Ternary {
+            cond,
+            truthy,
+            falsey,
+        } => (cond, truthy, falsey),
+        _ => unreachable!(),
+    };
+    let ty = compiler.type_of(func_ctx, expr);
+    let object = match ty {
+        Type::Struct(_) => Some(temporary_object(func_ctx, &ty)),
+        Type::Void => None,
+        _ if ty.is_scalar() => None,
+        _ => unimplemented!("A conditional expression of type {:?}", ty),
+    };
+    let dest = match ty {
+        Type::Void | Type::Struct(_) => None,
+        _ => Some(func_ctx.new_reg(Ty::of(&ty))),
+    };
+    let (then, otherwise, end) = (
+        func_ctx.new_block(),
+        func_ctx.new_block(),
+        func_ctx.new_block(),
+    );
+    let value = compile_expr(compiler, func_ctx, cond);
+    let cond = func_ctx.load(value);
+    func_ctx.terminate(Terminator::Branch {
+        cond,
+        then,
+        otherwise,
+    });
+    for (block, branch) in &[(then, truthy), (otherwise, falsey)] {
+        func_ctx.start_block(*block);
+        let value = compile_expr(compiler, func_ctx, branch);
+        if let Some(dest) = dest {
+            let src = func_ctx.load_as(value, &ty);
+            func_ctx.emit(ir::Instruction::Copy { dest, src });
+        } else if let Some(object) = &object {
+            if value.ty != ty {
+                panic!(
+                    "Type mismatch in conditional expression: {:?} and {:?}",
+                    value.ty, ty
+                );
+            }
+            copy(func_ctx, object, &value);
+        }
+        func_ctx.terminate(Terminator::Jump(end));
+    }
+    func_ctx.start_block(end);
+    match (dest, object) {
+        (Some(dest), _) => Value::new(dest, ty),
+        (None, Some(object)) => object,
+        (None, None) => void(),
+    }
+}
+
+/// ++a, --a, a++ and a--. Pointers step by the size of their pointee.
+pub fn increment(
+    compiler: &mut Compiler,
+    func_ctx: &mut FunctionCtx,
+    expr: &Expr,
+    delta: i64,
+    postfix: bool,
+) -> Value {
+    let target = compile_expr(compiler, func_ctx, expr);
+    let step = match &target.ty {
+        Type::Pointer(pointee) => delta * compiler.complete(pointee).stack_size() as i64,
+        ty if ty.is_integer() => delta,
+        other => panic!("Cannot increment a value of type {:?}", other),
+    };
+    let width = Ty::of(&target.ty);
+    let step = Operand::Const(width.truncate(step), width);
+    // a local in a register is updated in place
+    let (current, in_place) = match &target.place {
+        Place::Operand(Operand::Reg(reg)) => (Operand::Reg(*reg), Some(*reg)),
+        _ => (func_ctx.load(target.clone()), None),
+    };
+    let old = match (in_place, postfix) {
+        (Some(_), true) => {
+            let dest = func_ctx.new_reg(width);
+            func_ctx.emit(ir::Instruction::Copy { dest, src: current });
+            Operand::Reg(dest)
+        }
+        _ => current,
+    };
+    let dest = match in_place {
+        Some(reg) => reg,
+        None => func_ctx.new_reg(width),
+    };
+    func_ctx.emit(ir::Instruction::Binary {
+        op: ir::BinaryOp::Add,
+        dest,
+        lhs: current,
+        rhs: step,
+    });
+    if in_place.is_none() {
+        func_ctx.store(&target, dest.into());
+    }
+    match postfix {
+        true => Value::new(old, target.ty),
+        false => Value::new(dest, target.ty),
+    }
+}
@@ -0,0 +1,155 @@
+//! Objects with static storage duration, and the linkage that decides which
+//! symbols other objects can see.
+
+use super::{constant_value, initializer, Compiler};
+use crate::asm::{Data, DataValue, Instruction, Section};
+use crate::ast::{Initializer, StorageClass, Type};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Linkage {
+    /// visible to other objects, so the symbol is `global`
+    External,
+    /// declared static, so the symbol stays local to this object
+    Internal,
+}
+
+/// a global or static local, emitted into a data section once every
+/// declaration of it has been seen
+pub struct StaticObject {
+    pub label: String,
+    pub ty: Type,
+    /// the value at each offset, or None for a tentative definition like
+    /// `int a;` which is all zero unless another declaration initializes it
+    pub values: Option<Vec<(usize, Type, DataValue)>>,
+    pub is_const: bool,
+}
+
+impl StaticObject {
+    /// evaluates the initializer at compile time
+    pub fn new(
+        compiler: &mut Compiler,
+        label: String,
+        ty: Type,
+        initializer: Option<&Initializer>,
+        is_const: bool,
+    ) -> StaticObject {
+        let (ty, values) = match initializer {
+            Some(init) => {
+                let (ty, entries) = initializer::flatten(&ty, init);
+                let values = entries
+                    .into_iter()
+                    .map(|entry| {
+                        let value = constant_value(compiler, &entry.expr);
+                        (entry.offset, entry.ty, value)
+                    })
+                    .collect();
+                (ty, Some(values))
+            }
+            None => (ty, None),
+        };
+        StaticObject {
+            label,
+            ty,
+            values,
+            is_const,
+        }
+    }
+}
+
+impl<'src> Compiler<'src> {
+    /// works out the linkage of a file scope declaration. `extern`
+    /// declarations follow an earlier declaration of the name, and so do
+    /// functions declared without a storage class. A declaration that
+    /// disagrees with an earlier one is an error, and the earlier one wins.
+    pub fn declare_linkage(
+        &mut self,
+        name: &'src str,
+        storage_class: Option<StorageClass>,
+        is_function: bool,
+    ) -> Linkage {
+        let previous = self.linkage.get(name).copied();
+        let linkage = match (storage_class, previous) {
+            (Some(StorageClass::Static), Some(Linkage::External)) => {
+                self.report(Err(format!(
+                    "static declaration of {} follows non-static declaration",
+                    name
+                )));
+                Linkage::External
+            }
+            (Some(StorageClass::Static), _) => Linkage::Internal,
+            (Some(StorageClass::Extern), Some(previous)) => previous,
+            (None, Some(previous)) if is_function => previous,
+            (None, Some(Linkage::Internal)) => {
+                self.report(Err(format!(
+                    "non-static declaration of {} follows static declaration",
+                    name
+                )));
+                Linkage::Internal
+            }
+            _ => Linkage::External,
+        };
+        self.linkage.insert(name, linkage);
+        linkage
+    }
+
+    /// adds a definition of an object. A tentative definition doesn't
+    /// define anything new if the object already is, and an initialized one
+    /// replaces a tentative one.
+    pub fn define_object(&mut self, object: StaticObject) {
+        let defined = self
+            .objects
+            .iter_mut()
+            .find(|defined| defined.label == object.label);
+        match defined {
+            Some(defined) => match (&defined.values, &object.values) {
+                (Some(_), Some(_)) => self.report(Err(format!("redefinition of {}", object.label))),
+                (None, Some(_)) => *defined = object,
+                _ => {}
+            },
+            None => self.objects.push(object),
+        }
+    }
+
+    /// lays an object out in .rodata if it's const, .bss if it's all zero
+    /// and .data otherwise
+    pub fn emit_object(&mut self, object: StaticObject) {
+        let values = object.values.unwrap_or_default();
+        let size = object.ty.stack_size();
+        let is_zero = values
+            .iter()
+            .all(|(_, ty, value)| *value == DataValue::Int(0) || ty.stack_size() == 0);
+        let section = match () {
+            _ if object.is_const => Section::Rodata,
+            _ if is_zero => Section::Bss,
+            _ => Section::Data,
+        };
+        let align = match section {
+            Section::Bss => Instruction::AlignB(object.ty.align()),
+            _ => Instruction::Align(object.ty.align()),
+        };
+        self.gen_data(section, align)
+            .gen_data(section, Instruction::Label(object.label));
+        if section == Section::Bss {
+            self.gen_data(section, Instruction::Data(Data::Reserve(size)));
+            return;
+        }
+        // lay the values out in order, with zeroes in the gaps
+        let mut cursor = 0;
+        for (offset, ty, value) in values {
+            if offset > cursor {
+                self.gen_data(section, Instruction::Data(Data::Zero(offset - cursor)));
+            }
+            let data = match (ty.stack_size(), value) {
+                (1, DataValue::Int(val)) => Data::Byte(vec![val as u8]),
+                (4, DataValue::Int(val)) => Data::Dword(vec![val as i32]),
+                (8, value) => Data::Qword(vec![value]),
+                (size, value) => panic!("Cannot store {} in {} bytes", value, size),
+            };
+            self.gen_data(section, Instruction::Data(data));
+            cursor = offset + ty.stack_size();
+        }
+        if size > cursor {
+            self.gen_data(section, Instruction::Data(Data::Zero(size - cursor)));
+        }
+    }
+}
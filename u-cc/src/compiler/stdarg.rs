@@ -0,0 +1,174 @@
+//! The va_* builtins. A variadic function saves the argument registers in
+//! its prologue, and va_list walks through them before moving on to the
+//! arguments passed on the stack. va_start depends on the frame layout, so
+//! it's left to code generation.
+
+use super::{compile_expr, compile_operands, pointer_to, void, Compiler, FunctionCtx, Value};
+use crate::ast::{Expr, Type};
+use crate::codegen::GP_SAVE_AREA;
+use crate::ir::{self, Base, Comparison, Operand, Reg, Terminator, Ty};
+
+/// a member of the va_list struct `list` points at
+fn field(list: Reg, offset: i32) -> ir::Address {
+    ir::Address::new(Base::Reg(list)).add_offset(offset)
+}
+
+fn gp_offset(list: Reg) -> ir::Address {
+    field(list, 0)
+}
+
+fn overflow_arg_area(list: Reg) -> ir::Address {
+    field(list, 8)
+}
+
+fn reg_save_area(list: Reg) -> ir::Address {
+    field(list, 16)
+}
+
+/// a pointer to the struct of a va_list, which is what both a local
+/// va_list and a va_list parameter decay to
+fn list_pointer(func_ctx: &mut FunctionCtx, value: Value) -> Reg {
+    let tag = match &value.ty {
+        Type::Array(tag, _) | Type::Pointer(tag) if **tag == va_list_tag() => tag.clone(),
+        other => panic!("Expected a va_list, found {:?}", other),
+    };
+    let pointer = func_ctx.load_as(value, &pointer_to(&tag));
+    func_ctx.in_register(pointer)
+}
+
+fn va_list(compiler: &mut Compiler, func_ctx: &mut FunctionCtx, ap: &Expr) -> Reg {
+    let value = compile_expr(compiler, func_ctx, ap);
+    list_pointer(func_ctx, value)
+}
+
+fn va_list_tag() -> Type {
+    match Type::va_list() {
+        Type::Array(tag, _) => *tag,
+        _ => unreachable!(),
+    }
+}
+
+pub fn va_start(compiler: &mut Compiler, func_ctx: &mut FunctionCtx, ap: &Expr) -> Value {
+    if !func_ctx.function.variadic {
+        panic!("va_start used in a function with fixed arguments");
+    }
+    let list = va_list(compiler, func_ctx, ap);
+    func_ctx.emit(ir::Instruction::VaStart { list: list.into() });
+    void()
+}
+
+/// takes the next argument from the save area while there are registers
+/// left, and from the stack after that
+pub fn va_arg(compiler: &mut Compiler, func_ctx: &mut FunctionCtx, ap: &Expr, ty: &Type) -> Value {
+    let ty = compiler.complete(ty);
+    if !ty.is_scalar() {
+        unimplemented!("va_arg of a {:?}", ty);
+    }
+    let list = va_list(compiler, func_ctx, ap);
+    let (in_registers, on_stack, done) = (
+        func_ctx.new_block(),
+        func_ctx.new_block(),
+        func_ctx.new_block(),
+    );
+    let offset = func_ctx.new_reg(Ty::I32);
+    func_ctx.emit(ir::Instruction::Load {
+        dest: offset,
+        address: gp_offset(list),
+    });
+    let left = func_ctx.new_reg(Ty::I32);
+    func_ctx.emit(ir::Instruction::Compare {
+        comparison: Comparison::ULt,
+        dest: left,
+        lhs: offset.into(),
+        rhs: Operand::Const(GP_SAVE_AREA as i64, Ty::I32),
+    });
+    func_ctx.terminate(Terminator::Branch {
+        cond: left.into(),
+        then: in_registers,
+        otherwise: on_stack,
+    });
+    // the argument is at this address in either case
+    let argument = func_ctx.new_reg(Ty::I64);
+
+    func_ctx.start_block(in_registers);
+    let next = func_ctx.new_reg(Ty::I32);
+    func_ctx.emit(ir::Instruction::Binary {
+        op: ir::BinaryOp::Add,
+        dest: next,
+        lhs: offset.into(),
+        rhs: Operand::Const(8, Ty::I32),
+    });
+    func_ctx.emit(ir::Instruction::Store {
+        address: gp_offset(list),
+        src: next.into(),
+    });
+    let wide_offset = func_ctx.new_reg(Ty::I64);
+    func_ctx.emit(ir::Instruction::Convert {
+        signed: false,
+        dest: wide_offset,
+        src: offset.into(),
+    });
+    let save_area = func_ctx.new_reg(Ty::I64);
+    func_ctx.emit(ir::Instruction::Load {
+        dest: save_area,
+        address: reg_save_area(list),
+    });
+    func_ctx.emit(ir::Instruction::Binary {
+        op: ir::BinaryOp::Add,
+        dest: argument,
+        lhs: save_area.into(),
+        rhs: wide_offset.into(),
+    });
+    func_ctx.terminate(Terminator::Jump(done));
+
+    func_ctx.start_block(on_stack);
+    func_ctx.emit(ir::Instruction::Load {
+        dest: argument,
+        address: overflow_arg_area(list),
+    });
+    // every stack argument takes up 8 bytes, whatever its type
+    let next = func_ctx.new_reg(Ty::I64);
+    func_ctx.emit(ir::Instruction::Binary {
+        op: ir::BinaryOp::Add,
+        dest: next,
+        lhs: argument.into(),
+        rhs: Operand::Const(8, Ty::I64),
+    });
+    func_ctx.emit(ir::Instruction::Store {
+        address: overflow_arg_area(list),
+        src: next.into(),
+    });
+
+    func_ctx.start_block(done);
+    let value = Value::memory(ir::Address::new(Base::Reg(argument)), ty.clone());
+    Value::new(func_ctx.load(value), ty)
+}
+
+pub fn va_end(compiler: &mut Compiler, func_ctx: &mut FunctionCtx, ap: &Expr) -> Value {
+    // there's nothing to clean up, but ap still gets evaluated
+    va_list(compiler, func_ctx, ap);
+    void()
+}
+
+pub fn va_copy(
+    compiler: &mut Compiler,
+    func_ctx: &mut FunctionCtx,
+    dest: &Expr,
+    src: &Expr,
+) -> Value {
+    let (dest, src) = compile_operands(compiler, func_ctx, dest, src);
+    let dest = list_pointer(func_ctx, dest);
+    let src = list_pointer(func_ctx, src);
+    for offset in (0..va_list_tag().stack_size()).step_by(8) {
+        let qword = func_ctx.new_reg(Ty::I64);
+        func_ctx.emit(ir::Instruction::Load {
+            dest: qword,
+            address: field(src, offset as i32),
+        });
+        func_ctx.emit(ir::Instruction::Store {
+            address: field(dest, offset as i32),
+            src: qword.into(),
+        });
+    }
+    void()
+}
@@ -33,10 +33,22 @@ impl<'src> SymbolTable<'src> {
                 return Some(symbol);
             }
         }
-        return None;
+        None
     }
-    pub fn insert_symbol(&mut self, symbol: Symbol<'src>) {
-        self.inner.last_mut().unwrap().insert(symbol.name, symbol);
+    /// adds a declaration of something that may have been declared before
+    /// in the same scope, like a function prototype followed by its
+    /// definition. The symbol ends up with what both declarations know.
+    pub fn declare(&mut self, symbol: Symbol<'src>) -> Result<(), String> {
+        let scope = self.inner.last_mut().unwrap();
+        let type_of = match scope.get(symbol.name) {
+            Some(previous) => previous
+                .type_of
+                .composite(&symbol.type_of)
+                .ok_or_else(|| format!("conflicting types for {}", symbol.name))?,
+            None => symbol.type_of,
+        };
+        scope.insert(symbol.name, Symbol::new(symbol.name, type_of));
+        Ok(())
     }
     pub fn push_scope(&mut self) {
         self.inner.push(Default::default());
@@ -0,0 +1,110 @@
+//! The types of expressions, worked out without compiling them, for sizeof
+//! and for operators that need to know a type before evaluating anything.
+
+use super::{decay, pointer_to, Compiler, FunctionCtx};
+use crate::ast::{BinaryOp, Expr, Type};
+
+impl<'src> Compiler<'src> {
+    pub fn type_of(&self, func_ctx: &FunctionCtx, expr: &Expr) -> Type {
+        let pointee = |ty: &Type| match ty {
+            Type::Pointer(pointee) | Type::Array(pointee, _) => Some(self.complete(pointee)),
+            _ => None,
+        };
+        match expr {
+            Expr::Number(constant) => constant.ty.clone(),
+            // the terminating nul counts towards the size
+            Expr::StringLiteral(bytes) => Type::Array(Box::new(Type::Char), Some(bytes.len() + 1)),
+            Expr::Ident(name) => self.type_of_name(func_ctx, name),
+            Expr::AddressOf(name) => pointer_to(&self.type_of_name(func_ctx, name)),
+            Expr::Dereference(expr) => match pointee(&self.type_of(func_ctx, expr)) {
+                Some(pointee) => pointee,
+                None => panic!("Cannot dereference {:?}", expr),
+            },
+            Expr::Index(lhs, rhs) => {
+                let (lhs, rhs) = (self.type_of(func_ctx, lhs), self.type_of(func_ctx, rhs));
+                match pointee(&lhs).or_else(|| pointee(&rhs)) {
+                    Some(elem) => elem,
+                    None => panic!("Subscripted value is neither array nor pointer"),
+                }
+            }
+            Expr::FunctionCall(call) => match self.symbol_table.lookup_symbol(&call.name) {
+                Some(symbol) => match symbol.type_of() {
+                    Type::Function { return_type, .. } => self.complete(return_type),
+                    other => panic!("Called object {} has type {:?}", call.name, other),
+                },
+                // implicitly declared functions return int
+                None => Type::Int,
+            },
+            Expr::Op(lhs, op, rhs) => {
+                let (lhs, rhs) = (self.type_of(func_ctx, lhs), self.type_of(func_ctx, rhs));
+                match op {
+                    BinaryOp::Add | BinaryOp::Sub => match (pointee(&lhs), pointee(&rhs)) {
+                        (Some(_), Some(_)) => Type::Long,
+                        (Some(pointee), None) | (None, Some(pointee)) => pointer_to(&pointee),
+                        (None, None) => Type::common(&lhs, &rhs),
+                    },
+                    BinaryOp::Mul
+                    | BinaryOp::Div
+                    | BinaryOp::Mod
+                    | BinaryOp::BitAnd
+                    | BinaryOp::BitXor
+                    | BinaryOp::BitOr => Type::common(&lhs, &rhs),
+                    BinaryOp::LeftShift | BinaryOp::RightShift => lhs.promote(),
+                    // comparisons and logical operators
+                    _ => Type::Int,
+                }
+            }
+            Expr::PostIncrement(expr)
+            | Expr::PostDecrement(expr)
+            | Expr::PrefixIncrement(expr)
+            | Expr::PrefixDecrement(expr) => self.type_of(func_ctx, expr),
+            Expr::DotProperty(expr, name) => self.member_type(self.type_of(func_ctx, expr), name),
+            Expr::ArrowProperty(expr, name) => match pointee(&self.type_of(func_ctx, expr)) {
+                Some(pointee) => self.member_type(pointee, name),
+                None => panic!("Invalid type argument of ->"),
+            },
+            Expr::SizeofExpr(_) | Expr::SizeofType(_) => Type::UnsignedLong,
+            Expr::Not(_) => Type::Int,
+            Expr::Plus(expr) | Expr::Neg(expr) | Expr::BitNot(expr) => {
+                self.type_of(func_ctx, expr).promote()
+            }
+            Expr::Cast(ty, _) => self.complete(ty),
+            Expr::Ternary { truthy, falsey, .. } => {
+                let truthy = decay(&self.type_of(func_ctx, truthy));
+                let falsey = decay(&self.type_of(func_ctx, falsey));
+                match (&truthy, &falsey) {
+                    (Type::Pointer(_), _) => truthy,
+                    (_, Type::Pointer(_)) => falsey,
+                    _ if truthy.is_integer() && falsey.is_integer() => {
+                        Type::common(&truthy, &falsey)
+                    }
+                    _ => truthy,
+                }
+            }
+            Expr::Assignment { lhs, .. } => self.type_of(func_ctx, lhs),
+            Expr::VaStart(_) | Expr::VaEnd(_) | Expr::VaCopy(..) => Type::Void,
+            Expr::VaArg(_, ty) => self.complete(ty),
+        }
+    }
+    fn type_of_name(&self, func_ctx: &FunctionCtx, name: &str) -> Type {
+        if let Some(value) = func_ctx.lookup(name) {
+            return value.ty;
+        }
+        match self.symbol_table.lookup_symbol(name) {
+            Some(symbol) => symbol.type_of().clone(),
+            None => panic!("Use of undeclared identifier {}", name),
+        }
+    }
+    fn member_type(&self, ty: Type, name: &str) -> Type {
+        match &ty {
+            Type::Struct(struct_type) => match struct_type.member(name) {
+                Some((_, ty)) => self.complete(ty),
+                None => panic!("{:?} has no member named {}", ty, name),
+            },
+            other => panic!(
+                "Request for member {} in something not a struct: {:?}",
+                name, other
+            ),
+        }
+    }
+}
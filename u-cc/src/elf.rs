@@ -0,0 +1,593 @@
+//! ELF64 files for x86-64: the relocatable objects `u-cc -c` writes and
+//! the linker reads back, with the sections, a symbol table, and the
+//! relocations the linker fills in once it knows where everything goes,
+//! and the static executables the linker makes of them.
+
+/// the address of the symbol plus the addend, in 8 bytes
+pub const R_X86_64_64: u32 = 1;
+/// the address of the symbol plus the addend, relative to where it's
+/// written, in 4 bytes
+pub const R_X86_64_PC32: u32 = 2;
+/// like R_X86_64_PC32, but to the symbol's PLT entry if it's in a shared
+/// library
+pub const R_X86_64_PLT32: u32 = 4;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+
+const SHN_UNDEF: u16 = 0;
+
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 62;
+
+const PT_LOAD: u32 = 1;
+const PT_GNU_STACK: u32 = 0x6474_e551;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub contents: Vec<u8>,
+    /// how many bytes the section takes in memory, which is more than its
+    /// contents for .bss, which has none in the file
+    pub size: usize,
+    pub writable: bool,
+    pub executable: bool,
+    pub align: usize,
+    pub relocations: Vec<Relocation>,
+}
+
+impl Section {
+    fn nobits(&self) -> bool {
+        self.contents.len() < self.size
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    /// the index of the section it's in, or None if another object
+    /// defines it
+    pub section: Option<usize>,
+    pub value: u64,
+    pub global: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Relocation {
+    /// where in the section the value goes
+    pub offset: u64,
+    /// the index of the symbol in the object
+    pub symbol: usize,
+    pub kind: u32,
+    pub addend: i64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Object {
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+}
+
+/// a table of null terminated names, which the rest of the file refers to
+/// by offset
+#[derive(Default)]
+struct StringTable {
+    bytes: Vec<u8>,
+}
+
+impl StringTable {
+    fn add(&mut self, name: &str) -> u32 {
+        if self.bytes.is_empty() {
+            self.bytes.push(0);
+        }
+        if name.is_empty() {
+            return 0;
+        }
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(name.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+}
+
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entry_size: u64,
+}
+
+impl SectionHeader {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.name.to_le_bytes());
+        out.extend_from_slice(&self.kind.to_le_bytes());
+        out.extend_from_slice(&self.flags.to_le_bytes());
+        // the address, which objects don't have yet
+        out.extend_from_slice(&0u64.to_le_bytes());
+        out.extend_from_slice(&self.offset.to_le_bytes());
+        out.extend_from_slice(&self.size.to_le_bytes());
+        out.extend_from_slice(&self.link.to_le_bytes());
+        out.extend_from_slice(&self.info.to_le_bytes());
+        out.extend_from_slice(&self.align.to_le_bytes());
+        out.extend_from_slice(&self.entry_size.to_le_bytes());
+    }
+}
+
+fn pad_to(out: &mut Vec<u8>, align: usize) {
+    out.resize(out.len().next_multiple_of(align), 0);
+}
+
+impl Object {
+    /// the object as the bytes of an ELF file
+    pub fn write(&self) -> Vec<u8> {
+        let mut names = StringTable::default();
+        let mut strings = StringTable::default();
+        names.add("");
+        strings.add("");
+        // the header goes first, but where everything else went is only
+        // known at the end
+        let mut out = vec![0; 64];
+        let mut headers = vec![SectionHeader {
+            name: 0,
+            kind: 0,
+            flags: 0,
+            offset: 0,
+            size: 0,
+            link: 0,
+            info: 0,
+            align: 0,
+            entry_size: 0,
+        }];
+        for section in &self.sections {
+            let align = section.align.max(1);
+            pad_to(&mut out, align);
+            let mut flags = SHF_ALLOC;
+            if section.writable {
+                flags |= SHF_WRITE;
+            }
+            if section.executable {
+                flags |= SHF_EXECINSTR;
+            }
+            headers.push(SectionHeader {
+                name: names.add(&section.name),
+                kind: match section.nobits() {
+                    true => SHT_NOBITS,
+                    false => SHT_PROGBITS,
+                },
+                flags,
+                offset: out.len() as u64,
+                size: section.size as u64,
+                link: 0,
+                info: 0,
+                align: align as u64,
+                entry_size: 0,
+            });
+            out.extend_from_slice(&section.contents);
+        }
+        // without it, the linker makes the stack executable
+        headers.push(SectionHeader {
+            name: names.add(".note.GNU-stack"),
+            kind: SHT_PROGBITS,
+            flags: 0,
+            offset: out.len() as u64,
+            size: 0,
+            link: 0,
+            info: 0,
+            align: 1,
+            entry_size: 0,
+        });
+
+        // local symbols have to come before global ones
+        let mut order: Vec<usize> = (0..self.symbols.len()).collect();
+        order.sort_by_key(|&i| self.symbols[i].global);
+        let mut index = vec![0; self.symbols.len()];
+        for (position, &i) in order.iter().enumerate() {
+            index[i] = position + 1;
+        }
+        let first_global = 1 + self.symbols.iter().filter(|symbol| !symbol.global).count();
+        let rela_sections = self
+            .sections
+            .iter()
+            .filter(|section| !section.relocations.is_empty())
+            .count();
+        let symtab_index = headers.len() + rela_sections;
+
+        for (i, section) in self.sections.iter().enumerate() {
+            if section.relocations.is_empty() {
+                continue;
+            }
+            pad_to(&mut out, 8);
+            let offset = out.len() as u64;
+            for relocation in &section.relocations {
+                let info = (index[relocation.symbol] as u64) << 32 | u64::from(relocation.kind);
+                out.extend_from_slice(&relocation.offset.to_le_bytes());
+                out.extend_from_slice(&info.to_le_bytes());
+                out.extend_from_slice(&relocation.addend.to_le_bytes());
+            }
+            headers.push(SectionHeader {
+                name: names.add(&format!(".rela{}", section.name)),
+                kind: SHT_RELA,
+                flags: SHF_INFO_LINK,
+                offset,
+                size: out.len() as u64 - offset,
+                link: symtab_index as u32,
+                info: i as u32 + 1,
+                align: 8,
+                entry_size: 24,
+            });
+        }
+
+        pad_to(&mut out, 8);
+        let offset = out.len() as u64;
+        out.extend_from_slice(&[0; 24]);
+        for &i in &order {
+            let symbol = &self.symbols[i];
+            let binding = match symbol.global {
+                true => STB_GLOBAL,
+                false => STB_LOCAL,
+            };
+            let section = match symbol.section {
+                Some(section) => section as u16 + 1,
+                None => SHN_UNDEF,
+            };
+            out.extend_from_slice(&strings.add(&symbol.name).to_le_bytes());
+            // no type, like NASM leaves them
+            out.push(binding << 4);
+            out.push(0);
+            out.extend_from_slice(&section.to_le_bytes());
+            out.extend_from_slice(&symbol.value.to_le_bytes());
+            out.extend_from_slice(&0u64.to_le_bytes());
+        }
+        headers.push(SectionHeader {
+            name: names.add(".symtab"),
+            kind: SHT_SYMTAB,
+            flags: 0,
+            offset,
+            size: out.len() as u64 - offset,
+            link: symtab_index as u32 + 1,
+            info: first_global as u32,
+            align: 8,
+            entry_size: 24,
+        });
+        let name = names.add(".strtab");
+        headers.push(SectionHeader {
+            name,
+            kind: SHT_STRTAB,
+            flags: 0,
+            offset: out.len() as u64,
+            size: strings.bytes.len() as u64,
+            link: 0,
+            info: 0,
+            align: 1,
+            entry_size: 0,
+        });
+        out.extend_from_slice(&strings.bytes);
+        let name = names.add(".shstrtab");
+        headers.push(SectionHeader {
+            name,
+            kind: SHT_STRTAB,
+            flags: 0,
+            offset: out.len() as u64,
+            size: names.bytes.len() as u64,
+            link: 0,
+            info: 0,
+            align: 1,
+            entry_size: 0,
+        });
+        out.extend_from_slice(&names.bytes);
+
+        pad_to(&mut out, 8);
+        let section_headers = out.len() as u64;
+        for header in &headers {
+            header.write(&mut out);
+        }
+
+        let mut header = identification();
+        header.extend_from_slice(&ET_REL.to_le_bytes());
+        header.extend_from_slice(&EM_X86_64.to_le_bytes());
+        header.extend_from_slice(&1u32.to_le_bytes());
+        // no entry point or program headers
+        header.extend_from_slice(&0u64.to_le_bytes());
+        header.extend_from_slice(&0u64.to_le_bytes());
+        header.extend_from_slice(&section_headers.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&64u16.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(&64u16.to_le_bytes());
+        header.extend_from_slice(&(headers.len() as u16).to_le_bytes());
+        // .shstrtab is last
+        header.extend_from_slice(&(headers.len() as u16 - 1).to_le_bytes());
+        out[..64].copy_from_slice(&header);
+        out
+    }
+}
+
+/// the start of every ELF header
+fn identification() -> Vec<u8> {
+    let mut header = vec![];
+    header.extend_from_slice(b"\x7fELF");
+    // 64 bit, little endian, version 1, System V ABI
+    header.extend_from_slice(&[2, 1, 1, 0]);
+    header.extend_from_slice(&[0; 8]);
+    header
+}
+
+/// little endian numbers at offsets in a file, which may be cut short
+struct Bytes<'a>(&'a [u8]);
+
+impl Bytes<'_> {
+    fn get(&self, offset: u64, size: u64) -> Result<&[u8], String> {
+        let start = offset as usize;
+        self.0
+            .get(start..start + size as usize)
+            .ok_or_else(|| "the file is cut short".to_string())
+    }
+    fn u8(&self, offset: u64) -> Result<u8, String> {
+        Ok(self.get(offset, 1)?[0])
+    }
+    fn u16(&self, offset: u64) -> Result<u16, String> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.get(offset, 2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+    fn u32(&self, offset: u64) -> Result<u32, String> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.get(offset, 4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+    fn u64(&self, offset: u64) -> Result<u64, String> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.get(offset, 8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+    /// the null terminated name at an offset in a string table
+    fn name(&self, table: &SectionHeader, offset: u32) -> Result<String, String> {
+        let start = table.offset + u64::from(offset);
+        let bytes = self.get(start, table.size - u64::from(offset))?;
+        let end = bytes
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(bytes.len());
+        Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+    }
+    fn section_header(&self, offset: u64) -> Result<SectionHeader, String> {
+        Ok(SectionHeader {
+            name: self.u32(offset)?,
+            kind: self.u32(offset + 4)?,
+            flags: self.u64(offset + 8)?,
+            offset: self.u64(offset + 24)?,
+            size: self.u64(offset + 32)?,
+            link: self.u32(offset + 40)?,
+            info: self.u32(offset + 44)?,
+            align: self.u64(offset + 48)?,
+            entry_size: self.u64(offset + 56)?,
+        })
+    }
+}
+
+impl Object {
+    /// the object in an ELF file, like the ones `write` makes. Only the
+    /// sections that get loaded are kept, along with their symbols and
+    /// relocations.
+    pub fn read(file: &[u8]) -> Result<Object, String> {
+        let bytes = Bytes(file);
+        if bytes.get(0, 8)? != &identification()[..8] {
+            return Err("not a 64 bit little endian ELF file".to_string());
+        }
+        if bytes.u16(16)? != ET_REL || bytes.u16(18)? != EM_X86_64 {
+            return Err("not an x86-64 relocatable object".to_string());
+        }
+        let section_headers = bytes.u64(40)?;
+        let headers = (0..u64::from(bytes.u16(60)?))
+            .map(|i| bytes.section_header(section_headers + i * 64))
+            .collect::<Result<Vec<_>, _>>()?;
+        let names = headers
+            .get(usize::from(bytes.u16(62)?))
+            .ok_or("there are no section names")?;
+
+        // which of the object's sections each of the file's is
+        let mut object = Object::default();
+        let mut sections = vec![None; headers.len()];
+        for (i, header) in headers.iter().enumerate() {
+            if header.flags & SHF_ALLOC == 0 {
+                continue;
+            }
+            let contents = match header.kind {
+                SHT_PROGBITS => bytes.get(header.offset, header.size)?.to_vec(),
+                SHT_NOBITS => vec![],
+                _ => continue,
+            };
+            sections[i] = Some(object.sections.len());
+            object.sections.push(Section {
+                name: bytes.name(names, header.name)?,
+                contents,
+                size: header.size as usize,
+                writable: header.flags & SHF_WRITE != 0,
+                executable: header.flags & SHF_EXECINSTR != 0,
+                align: header.align as usize,
+                relocations: vec![],
+            });
+        }
+
+        let symtab = match headers.iter().position(|header| header.kind == SHT_SYMTAB) {
+            Some(symtab) => symtab,
+            None => return Ok(object),
+        };
+        let strings = headers
+            .get(headers[symtab].link as usize)
+            .ok_or("the symbol table has no names")?;
+        // which of the object's symbols each of the file's is, leaving
+        // out the null one and any in sections that aren't kept
+        let mut symbols = vec![];
+        for i in 0..headers[symtab].size / 24 {
+            let offset = headers[symtab].offset + i * 24;
+            let index = bytes.u16(offset + 6)?;
+            let section = match index {
+                SHN_UNDEF => None,
+                index => match sections.get(usize::from(index)) {
+                    Some(&Some(section)) => Some(section),
+                    _ => {
+                        symbols.push(None);
+                        continue;
+                    }
+                },
+            };
+            let name = bytes.name(strings, bytes.u32(offset)?)?;
+            if i == 0 || (section.is_none() && name.is_empty()) {
+                symbols.push(None);
+                continue;
+            }
+            symbols.push(Some(object.symbols.len()));
+            object.symbols.push(Symbol {
+                name,
+                section,
+                value: bytes.u64(offset + 8)?,
+                global: bytes.u8(offset + 4)? >> 4 != STB_LOCAL,
+            });
+        }
+
+        for header in headers.iter().filter(|header| header.kind == SHT_RELA) {
+            let section = match sections.get(header.info as usize) {
+                Some(&Some(section)) => section,
+                _ => continue,
+            };
+            for i in 0..header.size / 24 {
+                let offset = header.offset + i * 24;
+                let info = bytes.u64(offset + 8)?;
+                let symbol = match symbols.get((info >> 32) as usize) {
+                    Some(&Some(symbol)) => symbol,
+                    _ => return Err(format!("relocation {} refers to no symbol", i)),
+                };
+                object.sections[section].relocations.push(Relocation {
+                    offset: bytes.u64(offset)?,
+                    symbol,
+                    kind: info as u32,
+                    addend: bytes.u64(offset + 16)? as i64,
+                });
+            }
+        }
+        Ok(object)
+    }
+}
+
+/// a part of an executable loaded at an address, with the same
+/// permissions throughout
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub address: u64,
+    pub contents: Vec<u8>,
+    /// how many bytes the segment takes in memory, which is more than its
+    /// contents if it ends in .bss
+    pub size: u64,
+    pub writable: bool,
+    pub executable: bool,
+}
+
+/// a statically linked executable, which needs nothing but the kernel to
+/// run
+#[derive(Debug, Clone, Default)]
+pub struct Executable {
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+}
+
+/// how big the headers of an executable with this many segments are
+pub fn headers_size(segments: usize) -> u64 {
+    // the stack gets a program header too
+    64 + 56 * (segments as u64 + 1)
+}
+
+/// executables load in pages, so each segment's offset in the file has
+/// to be its address modulo this
+pub const PAGE_SIZE: u64 = 0x1000;
+
+impl Executable {
+    /// the executable as the bytes of an ELF file. Each segment goes at
+    /// its address's offset in the pages from the first one, which the
+    /// headers are loaded at the start of. There are no section headers,
+    /// which only tools look at.
+    pub fn write(&self) -> Vec<u8> {
+        let base = self.segments[0].address & !(PAGE_SIZE - 1);
+        let mut out = identification();
+        out.extend_from_slice(&ET_EXEC.to_le_bytes());
+        out.extend_from_slice(&EM_X86_64.to_le_bytes());
+        out.extend_from_slice(&1u32.to_le_bytes());
+        out.extend_from_slice(&self.entry.to_le_bytes());
+        // the program headers come right after this one
+        out.extend_from_slice(&64u64.to_le_bytes());
+        out.extend_from_slice(&0u64.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&64u16.to_le_bytes());
+        out.extend_from_slice(&56u16.to_le_bytes());
+        out.extend_from_slice(&(self.segments.len() as u16 + 1).to_le_bytes());
+        out.extend_from_slice(&64u16.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+
+        let mut program_header = |kind: u32, flags: u32, segment: Option<&Segment>| {
+            let (offset, address, file_size, memory_size) = match segment {
+                // the first segment starts at the start of the file, so
+                // the headers get loaded too
+                Some(segment) if segment.address - base < PAGE_SIZE => (
+                    0,
+                    base,
+                    segment.address - base + segment.contents.len() as u64,
+                    segment.address - base + segment.size,
+                ),
+                Some(segment) => (
+                    segment.address - base,
+                    segment.address,
+                    segment.contents.len() as u64,
+                    segment.size,
+                ),
+                None => (0, 0, 0, 0),
+            };
+            out.extend_from_slice(&kind.to_le_bytes());
+            out.extend_from_slice(&flags.to_le_bytes());
+            out.extend_from_slice(&offset.to_le_bytes());
+            out.extend_from_slice(&address.to_le_bytes());
+            out.extend_from_slice(&address.to_le_bytes());
+            out.extend_from_slice(&file_size.to_le_bytes());
+            out.extend_from_slice(&memory_size.to_le_bytes());
+            out.extend_from_slice(&PAGE_SIZE.to_le_bytes());
+        };
+        for segment in &self.segments {
+            let mut flags = PF_R;
+            if segment.writable {
+                flags |= PF_W;
+            }
+            if segment.executable {
+                flags |= PF_X;
+            }
+            program_header(PT_LOAD, flags, Some(segment));
+        }
+        // without it, the stack is executable
+        program_header(PT_GNU_STACK, PF_R | PF_W, None);
+
+        for segment in &self.segments {
+            out.resize((segment.address - base) as usize, 0);
+            out.extend_from_slice(&segment.contents);
+        }
+        out
+    }
+}
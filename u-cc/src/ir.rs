@@ -0,0 +1,727 @@
+//! The intermediate representation functions are compiled to on the way
+//! from the AST to assembly. A function is a list of basic blocks of
+//! three-address instructions over typed virtual registers, and each block
+//! ends in an explicit jump, branch or return. Registers can be assigned
+//! more than once, so it isn't SSA.
+
+use crate::asm::{self, Section};
+use crate::ast::Type;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
+
+/// the width of a value. Signedness belongs to the operations instead, and
+/// pointers are i64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ty {
+    I8,
+    I32,
+    I64,
+}
+
+impl Ty {
+    /// the representation of a scalar type
+    pub fn of(ty: &Type) -> Ty {
+        match ty.stack_size() {
+            1 => Ty::I8,
+            4 => Ty::I32,
+            8 if ty.is_scalar() => Ty::I64,
+            _ => panic!("{:?} doesn't fit in a register", ty),
+        }
+    }
+    pub fn size(self) -> usize {
+        match self {
+            Ty::I8 => 1,
+            Ty::I32 => 4,
+            Ty::I64 => 8,
+        }
+    }
+    /// a constant truncated to this width, like storing it would
+    pub fn truncate(self, val: i64) -> i64 {
+        match self {
+            Ty::I8 => i64::from(val as i8),
+            Ty::I32 => i64::from(val as i32),
+            Ty::I64 => val,
+        }
+    }
+}
+
+impl Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ty::I8 => write!(f, "i8"),
+            Ty::I32 => write!(f, "i32"),
+            Ty::I64 => write!(f, "i64"),
+        }
+    }
+}
+
+/// a virtual register
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Reg {
+    pub number: usize,
+    pub ty: Ty,
+}
+
+impl Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "%{}", self.number)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operand {
+    Reg(Reg),
+    /// a constant, already truncated to its width
+    Const(i64, Ty),
+}
+
+impl Operand {
+    pub fn ty(&self) -> Ty {
+        match self {
+            Operand::Reg(reg) => reg.ty,
+            Operand::Const(_, ty) => *ty,
+        }
+    }
+}
+
+impl From<Reg> for Operand {
+    fn from(reg: Reg) -> Operand {
+        Operand::Reg(reg)
+    }
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Reg(reg) => Display::fmt(reg, f),
+            Operand::Const(val, _) => Display::fmt(val, f),
+        }
+    }
+}
+
+/// what an address is relative to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Base {
+    /// an object in the function's stack frame, by index
+    Slot(usize),
+    /// a symbol, like a global or a string literal
+    Global(String),
+    /// a pointer in a register
+    Reg(Reg),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Address {
+    pub base: Base,
+    pub offset: i32,
+}
+
+impl Address {
+    pub fn new(base: Base) -> Address {
+        Address { base, offset: 0 }
+    }
+    pub fn add_offset(mut self, offset: i32) -> Address {
+        self.offset += offset;
+        self
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.base {
+            Base::Slot(slot) => write!(f, "[${}", slot)?,
+            Base::Global(label) => write!(f, "[@{}", label)?,
+            Base::Reg(reg) => write!(f, "[{}", reg)?,
+        }
+        match self.offset {
+            0 => write!(f, "]"),
+            offset if offset < 0 => write!(f, " - {}]", -i64::from(offset)),
+            offset => write!(f, " + {}]", offset),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    SDiv,
+    UDiv,
+    SRem,
+    URem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    /// arithmetic shift right, which keeps the sign
+    Sar,
+    /// logical shift right, which shifts in zeroes
+    Shr,
+}
+
+/// comparisons, with separate signed and unsigned orderings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    SLt,
+    SLe,
+    SGt,
+    SGe,
+    ULt,
+    ULe,
+    UGt,
+    UGe,
+}
+
+impl Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnaryOp::Neg => write!(f, "neg"),
+            UnaryOp::Not => write!(f, "not"),
+        }
+    }
+}
+
+impl Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::SDiv => "sdiv",
+            BinaryOp::UDiv => "udiv",
+            BinaryOp::SRem => "srem",
+            BinaryOp::URem => "urem",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+            BinaryOp::Xor => "xor",
+            BinaryOp::Shl => "shl",
+            BinaryOp::Sar => "sar",
+            BinaryOp::Shr => "shr",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Comparison::Eq => "eq",
+            Comparison::Ne => "ne",
+            Comparison::SLt => "slt",
+            Comparison::SLe => "sle",
+            Comparison::SGt => "sgt",
+            Comparison::SGe => "sge",
+            Comparison::ULt => "ult",
+            Comparison::ULe => "ule",
+            Comparison::UGt => "ugt",
+            Comparison::UGe => "uge",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Copy {
+        dest: Reg,
+        src: Operand,
+    },
+    Unary {
+        op: UnaryOp,
+        dest: Reg,
+        src: Operand,
+    },
+    /// both operands have the type of dest, even the count of a shift
+    Binary {
+        op: BinaryOp,
+        dest: Reg,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    /// sets dest, an i32, to 1 if the comparison holds and 0 otherwise
+    Compare {
+        comparison: Comparison,
+        dest: Reg,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    /// extends src to a wider dest, treating it as signed or not, or
+    /// truncates it to a narrower one
+    Convert {
+        signed: bool,
+        dest: Reg,
+        src: Operand,
+    },
+    Load {
+        dest: Reg,
+        address: Address,
+    },
+    Store {
+        address: Address,
+        src: Operand,
+    },
+    /// sets dest to the address itself
+    AddressOf {
+        dest: Reg,
+        address: Address,
+    },
+    Call {
+        dest: Option<Reg>,
+        function: String,
+        /// the ones passed in registers come first, then the ones passed
+        /// on the stack
+        arguments: Vec<Operand>,
+        /// how many of the arguments are passed in registers
+        in_registers: usize,
+        /// the size of a struct of up to 16 bytes that the callee returns
+        /// in rax and rdx. dest is then set to the address of a copy of it.
+        returned_struct: Option<usize>,
+        /// whether the callee might be variadic, which tells it how many
+        /// vector registers hold arguments
+        variadic: bool,
+        /// whether the function returns what the call does straight after
+        /// it, so the callee can return to the caller's caller instead
+        tail: bool,
+    },
+    /// fills in the va_list `list` points at, in a variadic function
+    VaStart {
+        list: Operand,
+    },
+}
+
+impl Instruction {
+    /// sets a parameter to an argument, which calls pass as at least an int
+    pub fn pass(parameter: Reg, argument: Operand) -> Instruction {
+        match parameter.ty == argument.ty() {
+            true => Instruction::Copy {
+                dest: parameter,
+                src: argument,
+            },
+            false => Instruction::Convert {
+                signed: true,
+                dest: parameter,
+                src: argument,
+            },
+        }
+    }
+    /// the operands the instruction reads, not counting the bases of
+    /// addresses
+    pub fn operands(&self) -> Vec<Operand> {
+        match self {
+            Instruction::Copy { src, .. }
+            | Instruction::Unary { src, .. }
+            | Instruction::Convert { src, .. }
+            | Instruction::Store { src, .. } => vec![*src],
+            Instruction::Binary { lhs, rhs, .. } | Instruction::Compare { lhs, rhs, .. } => {
+                vec![*lhs, *rhs]
+            }
+            Instruction::Call { arguments, .. } => arguments.clone(),
+            Instruction::VaStart { list } => vec![*list],
+            Instruction::Load { .. } | Instruction::AddressOf { .. } => vec![],
+        }
+    }
+    /// the address the instruction accesses or computes, if any
+    pub fn address(&self) -> Option<&Address> {
+        match self {
+            Instruction::Load { address, .. }
+            | Instruction::Store { address, .. }
+            | Instruction::AddressOf { address, .. } => Some(address),
+            _ => None,
+        }
+    }
+    /// the operands the instruction reads, for rewriting them
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Instruction::Copy { src, .. }
+            | Instruction::Unary { src, .. }
+            | Instruction::Convert { src, .. }
+            | Instruction::Store { src, .. } => vec![src],
+            Instruction::Binary { lhs, rhs, .. } | Instruction::Compare { lhs, rhs, .. } => {
+                vec![lhs, rhs]
+            }
+            Instruction::Call { arguments, .. } => arguments.iter_mut().collect(),
+            Instruction::VaStart { list } => vec![list],
+            Instruction::Load { .. } | Instruction::AddressOf { .. } => vec![],
+        }
+    }
+    /// the register the instruction writes, if any
+    pub fn dest(&self) -> Option<Reg> {
+        match self {
+            Instruction::Copy { dest, .. }
+            | Instruction::Unary { dest, .. }
+            | Instruction::Binary { dest, .. }
+            | Instruction::Compare { dest, .. }
+            | Instruction::Convert { dest, .. }
+            | Instruction::Load { dest, .. }
+            | Instruction::AddressOf { dest, .. } => Some(*dest),
+            Instruction::Call { dest, .. } => *dest,
+            Instruction::Store { .. } | Instruction::VaStart { .. } => None,
+        }
+    }
+    /// the register the instruction writes, for renaming it
+    pub fn dest_mut(&mut self) -> Option<&mut Reg> {
+        match self {
+            Instruction::Copy { dest, .. }
+            | Instruction::Unary { dest, .. }
+            | Instruction::Binary { dest, .. }
+            | Instruction::Compare { dest, .. }
+            | Instruction::Convert { dest, .. }
+            | Instruction::Load { dest, .. }
+            | Instruction::AddressOf { dest, .. } => Some(dest),
+            Instruction::Call { dest, .. } => dest.as_mut(),
+            Instruction::Store { .. } | Instruction::VaStart { .. } => None,
+        }
+    }
+    /// the address the instruction accesses or computes, for rewriting it
+    pub fn address_mut(&mut self) -> Option<&mut Address> {
+        match self {
+            Instruction::Load { address, .. }
+            | Instruction::Store { address, .. }
+            | Instruction::AddressOf { address, .. } => Some(address),
+            _ => None,
+        }
+    }
+    /// every register the instruction reads
+    pub fn uses(&self) -> Vec<Reg> {
+        let mut uses: Vec<Reg> = self
+            .operands()
+            .into_iter()
+            .filter_map(|operand| match operand {
+                Operand::Reg(reg) => Some(reg),
+                Operand::Const(..) => None,
+            })
+            .collect();
+        if let Some(Address {
+            base: Base::Reg(reg),
+            ..
+        }) = self.address()
+        {
+            uses.push(*reg);
+        }
+        uses
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dest = |f: &mut fmt::Formatter, dest: &Reg| write!(f, "{}:{} = ", dest, dest.ty);
+        match self {
+            Instruction::Copy { dest: reg, src } => {
+                dest(f, reg)?;
+                write!(f, "copy {}", src)
+            }
+            Instruction::Unary { op, dest: reg, src } => {
+                dest(f, reg)?;
+                write!(f, "{} {}", op, src)
+            }
+            Instruction::Binary {
+                op,
+                dest: reg,
+                lhs,
+                rhs,
+            } => {
+                dest(f, reg)?;
+                write!(f, "{} {}, {}", op, lhs, rhs)
+            }
+            Instruction::Compare {
+                comparison,
+                dest: reg,
+                lhs,
+                rhs,
+            } => {
+                dest(f, reg)?;
+                write!(f, "cmp {} {} {}, {}", comparison, lhs.ty(), lhs, rhs)
+            }
+            Instruction::Convert {
+                signed,
+                dest: reg,
+                src,
+            } => {
+                dest(f, reg)?;
+                let conversion = match (reg.ty.size() > src.ty().size(), signed) {
+                    (true, true) => "sext",
+                    (true, false) => "zext",
+                    (false, _) => "trunc",
+                };
+                write!(f, "{} {} {}", conversion, src.ty(), src)
+            }
+            Instruction::Load { dest: reg, address } => {
+                dest(f, reg)?;
+                write!(f, "load {}", address)
+            }
+            Instruction::Store { address, src } => {
+                write!(f, "store {} {}, {}", src.ty(), address, src)
+            }
+            Instruction::AddressOf { dest: reg, address } => {
+                dest(f, reg)?;
+                write!(f, "addr {}", address)
+            }
+            Instruction::Call {
+                dest: reg,
+                function,
+                arguments,
+                in_registers,
+                returned_struct,
+                variadic,
+                tail,
+            } => {
+                if let Some(reg) = reg {
+                    dest(f, reg)?;
+                }
+                if *tail {
+                    write!(f, "tail ")?;
+                }
+                write!(f, "call @{}(", function)?;
+                for (i, argument) in arguments.iter().enumerate() {
+                    write!(f, "{}", separator(i, *in_registers))?;
+                    write!(f, "{} {}", argument.ty(), argument)?;
+                }
+                write!(f, ")")?;
+                if let Some(size) = returned_struct {
+                    write!(f, " struct {}", size)?;
+                }
+                if *variadic {
+                    write!(f, " variadic")?;
+                }
+                Ok(())
+            }
+            Instruction::VaStart { list } => write!(f, "va_start {}", list),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+impl Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "b{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    /// goes to `then` if cond is nonzero, and to `otherwise` if it's zero
+    Branch {
+        cond: Operand,
+        then: BlockId,
+        otherwise: BlockId,
+    },
+    Return(Option<Operand>),
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch {
+                then, otherwise, ..
+            } => vec![*then, *otherwise],
+            Terminator::Return(_) => vec![],
+        }
+    }
+    /// the blocks it can go to, for rewriting them
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch {
+                then, otherwise, ..
+            } => vec![then, otherwise],
+            Terminator::Return(_) => vec![],
+        }
+    }
+    /// the operand a branch or return reads, for rewriting it
+    pub fn operand_mut(&mut self) -> Option<&mut Operand> {
+        match self {
+            Terminator::Branch { cond, .. } => Some(cond),
+            Terminator::Return(value) => value.as_mut(),
+            Terminator::Jump(_) => None,
+        }
+    }
+    /// the register a branch or return reads, if any
+    pub fn uses(&self) -> Option<Reg> {
+        match self {
+            Terminator::Branch {
+                cond: Operand::Reg(reg),
+                ..
+            }
+            | Terminator::Return(Some(Operand::Reg(reg))) => Some(*reg),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Terminator::Jump(target) => write!(f, "jmp {}", target),
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => write!(f, "br {}, {}, {}", cond, then, otherwise),
+            Terminator::Return(Some(value)) => write!(f, "ret {}", value),
+            Terminator::Return(None) => write!(f, "ret"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub id: BlockId,
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+/// an object in the stack frame, for locals that have to be in memory
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slot {
+    pub size: usize,
+    pub align: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    /// the symbol the function is defined as
+    pub name: String,
+    /// the registers the arguments arrive in, in the order the caller
+    /// passes them
+    pub parameters: Vec<Reg>,
+    /// how many of the parameters arrive in registers, with the rest on
+    /// the stack
+    pub parameters_in_registers: usize,
+    /// the size of a struct of up to 16 bytes that the function returns in
+    /// rax and rdx. A return gives its address, with room after it to read
+    /// whole eightbytes.
+    pub returned_struct: Option<usize>,
+    /// None for functions returning void
+    pub return_ty: Option<Ty>,
+    pub variadic: bool,
+    /// whether the definition asked for calls to be inlined
+    pub inline: bool,
+    pub slots: Vec<Slot>,
+    /// the entry block comes first
+    pub blocks: Vec<Block>,
+    /// how many registers and blocks have been numbered
+    pub registers: usize,
+    pub block_ids: usize,
+    /// the kind of statement each block's code starts with, for warning
+    /// about statements that can't be reached
+    pub statements: BTreeMap<BlockId, &'static str>,
+}
+
+impl Function {
+    pub fn new(name: String, return_ty: Option<Ty>, variadic: bool) -> Function {
+        Function {
+            name,
+            parameters: vec![],
+            parameters_in_registers: 0,
+            returned_struct: None,
+            return_ty,
+            variadic,
+            inline: false,
+            slots: vec![],
+            blocks: vec![],
+            registers: 0,
+            block_ids: 0,
+            statements: BTreeMap::new(),
+        }
+    }
+    pub fn new_reg(&mut self, ty: Ty) -> Reg {
+        self.registers += 1;
+        Reg {
+            number: self.registers - 1,
+            ty,
+        }
+    }
+    pub fn new_block_id(&mut self) -> BlockId {
+        self.block_ids += 1;
+        BlockId(self.block_ids - 1)
+    }
+    pub fn new_slot(&mut self, ty: &Type) -> usize {
+        self.slots.push(Slot {
+            size: ty.stack_size(),
+            align: ty.align(),
+        });
+        self.slots.len() - 1
+    }
+}
+
+/// what goes before the `i`th argument or parameter, where a semicolon
+/// marks the first one on the stack
+fn separator(i: usize, in_registers: usize) -> &'static str {
+    match i {
+        0 => "",
+        i if i == in_registers => "; ",
+        _ => ", ",
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.return_ty {
+            Some(ty) => write!(f, "function {} @{}(", ty, self.name)?,
+            None => write!(f, "function void @{}(", self.name)?,
+        }
+        for (i, parameter) in self.parameters.iter().enumerate() {
+            write!(f, "{}", separator(i, self.parameters_in_registers))?;
+            write!(f, "{} {}", parameter.ty, parameter)?;
+        }
+        if self.variadic {
+            write!(
+                f,
+                "{}...",
+                if self.parameters.is_empty() { "" } else { ", " }
+            )?;
+        }
+        write!(f, ")")?;
+        if let Some(size) = self.returned_struct {
+            write!(f, " struct {}", size)?;
+        }
+        writeln!(f, " {{")?;
+        for (i, slot) in self.slots.iter().enumerate() {
+            writeln!(f, "  ${}: {} bytes, align {}", i, slot.size, slot.align)?;
+        }
+        for block in &self.blocks {
+            writeln!(f, "{}:", block.id)?;
+            for instruction in &block.instructions {
+                writeln!(f, "  {}", instruction)?;
+            }
+            writeln!(f, "  {}", block.terminator)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+/// everything compiled from one file
+pub struct Module {
+    pub functions: Vec<Function>,
+    /// the contents of .data, .bss and .rodata, which are laid out already
+    pub data: BTreeMap<Section, Vec<asm::Instruction>>,
+    /// symbols other objects can use
+    pub exported: BTreeSet<String>,
+    /// symbols the code uses, which are `extern` unless defined here
+    pub referenced: BTreeSet<String>,
+}
+
+impl Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", function)?;
+        }
+        Ok(())
+    }
+}
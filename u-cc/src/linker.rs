@@ -0,0 +1,267 @@
+//! A static linker for the objects u-cc makes, so a program can be built
+//! without any other tools. The sections with the same name are put
+//! together, those go into a segment each for code, read only data and
+//! writable data, and once everything has an address the relocations are
+//! filled in. There's no C library, so the program starts at a `_start`
+//! of our own that calls main and exits with what it returns.
+
+use crate::elf::{self, Executable, Object, Segment};
+use crate::platform;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// where the executable is loaded, like other linkers do without PIE
+const BASE: u64 = 0x40_0000;
+
+/// the code that runs first: it calls main with argc and argv from the
+/// stack, then makes the exit system call with what main returned. The
+/// stack is already aligned to 16 bytes, as main expects it to be before
+/// the call.
+fn start() -> Object {
+    let contents = vec![
+        0x31, 0xed, // xor ebp, ebp
+        0x48, 0x8b, 0x3c, 0x24, // mov rdi, [rsp]
+        0x48, 0x8d, 0x74, 0x24, 0x08, // lea rsi, [rsp + 8]
+        0xe8, 0, 0, 0, 0, // call main
+        0x89, 0xc7, // mov edi, eax
+        0xb8, 0x3c, 0, 0, 0, // mov eax, 60
+        0x0f, 0x05, // syscall
+    ];
+    Object {
+        sections: vec![elf::Section {
+            name: ".text".to_string(),
+            size: contents.len(),
+            contents,
+            writable: false,
+            executable: true,
+            align: 16,
+            relocations: vec![elf::Relocation {
+                offset: 12,
+                symbol: 1,
+                kind: elf::R_X86_64_PLT32,
+                addend: -4,
+            }],
+        }],
+        symbols: vec![
+            elf::Symbol {
+                name: "_start".to_string(),
+                section: Some(0),
+                value: 0,
+                global: true,
+            },
+            elf::Symbol {
+                name: platform::main_symbol().to_string(),
+                section: None,
+                value: 0,
+                global: true,
+            },
+        ],
+    }
+}
+
+/// the sections from all the objects with one name, together
+struct OutputSection {
+    name: String,
+    contents: Vec<u8>,
+    size: usize,
+    writable: bool,
+    executable: bool,
+    align: usize,
+    address: u64,
+}
+
+impl OutputSection {
+    /// the segment it goes in, in the order they're loaded
+    fn segment(&self) -> usize {
+        match (self.executable, self.writable) {
+            (true, _) => 0,
+            (false, false) => 1,
+            (false, true) => 2,
+        }
+    }
+    fn nobits(&self) -> bool {
+        self.contents.len() < self.size
+    }
+}
+
+/// links the objects into an executable, or says which symbols are
+/// missing or defined more than once
+pub fn link(objects: &[Object]) -> Result<Executable, String> {
+    let start = start();
+    let objects: Vec<&Object> = std::iter::once(&start).chain(objects).collect();
+
+    // which output section each section goes in, and where
+    let mut outputs: Vec<OutputSection> = vec![];
+    let mut placements = vec![];
+    for object in &objects {
+        let mut placed = vec![];
+        for section in &object.sections {
+            let index = match outputs
+                .iter()
+                .position(|output| output.name == section.name)
+            {
+                Some(index) => index,
+                None => {
+                    outputs.push(OutputSection {
+                        name: section.name.clone(),
+                        contents: vec![],
+                        size: 0,
+                        writable: section.writable,
+                        executable: section.executable,
+                        align: 1,
+                        address: 0,
+                    });
+                    outputs.len() - 1
+                }
+            };
+            let output = &mut outputs[index];
+            let align = section.align.max(1);
+            output.align = output.align.max(align);
+            output.size = output.size.next_multiple_of(align);
+            placed.push((index, output.size));
+            output.contents.resize(output.size, 0);
+            output.contents.extend_from_slice(&section.contents);
+            output.size += section.size;
+        }
+        placements.push(placed);
+    }
+
+    // .bss goes last in its segment, since it takes no room in the file
+    let mut order: Vec<usize> = (0..outputs.len()).collect();
+    order.sort_by_key(|&i| (outputs[i].segment(), outputs[i].nobits()));
+    let segments =
+        order
+            .iter()
+            .map(|&i| outputs[i].segment())
+            .fold(vec![], |mut segments, segment| {
+                if segments.last() != Some(&segment) {
+                    segments.push(segment);
+                }
+                segments
+            });
+    let mut address = BASE + elf::headers_size(segments.len());
+    let mut previous = None;
+    for &i in &order {
+        let output = &mut outputs[i];
+        // each segment starts on a new page, so it can have its own
+        // permissions
+        if previous.is_some_and(|segment| segment != output.segment()) {
+            address = address.next_multiple_of(elf::PAGE_SIZE);
+        }
+        previous = Some(output.segment());
+        address = address.next_multiple_of(output.align as u64);
+        output.address = address;
+        address += output.size as u64;
+    }
+
+    // every global symbol, then the address of each object's symbols
+    let address_of = |object: usize, symbol: &elf::Symbol| {
+        symbol.section.map(|section| {
+            let (output, offset) = placements[object][section];
+            outputs[output].address + offset as u64 + symbol.value
+        })
+    };
+    let mut globals = HashMap::new();
+    let mut errors = vec![];
+    for (i, object) in objects.iter().enumerate() {
+        for symbol in object.symbols.iter().filter(|symbol| symbol.global) {
+            if let Some(address) = address_of(i, symbol) {
+                if globals.insert(symbol.name.as_str(), address).is_some() {
+                    errors.push(format!("multiple definitions of `{}'", symbol.name));
+                }
+            }
+        }
+    }
+    let mut addresses = vec![];
+    for (i, object) in objects.iter().enumerate() {
+        let mut resolved = vec![];
+        for symbol in &object.symbols {
+            resolved.push(match address_of(i, symbol) {
+                Some(address) => Some(address),
+                None => match globals.get(symbol.name.as_str()) {
+                    Some(&address) => Some(address),
+                    None => {
+                        let error = format!("undefined reference to `{}'", symbol.name);
+                        if !errors.contains(&error) {
+                            errors.push(error);
+                        }
+                        None
+                    }
+                },
+            });
+        }
+        addresses.push(resolved);
+    }
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+
+    for (i, object) in objects.iter().enumerate() {
+        for (section, relocations) in object
+            .sections
+            .iter()
+            .map(|section| &section.relocations)
+            .enumerate()
+        {
+            let (index, offset) = placements[i][section];
+            let output = &mut outputs[index];
+            for relocation in relocations {
+                let position = offset + relocation.offset as usize;
+                let place = output.address + position as u64;
+                let target = addresses[i][relocation.symbol].unwrap() as i64 + relocation.addend;
+                let name = &object.symbols[relocation.symbol].name;
+                match relocation.kind {
+                    elf::R_X86_64_64 => output.contents[position..position + 8]
+                        .copy_from_slice(&target.to_le_bytes()),
+                    elf::R_X86_64_PC32 | elf::R_X86_64_PLT32 => {
+                        let value = target - place as i64;
+                        if i32::try_from(value).is_err() {
+                            return Err(format!("`{}' is too far away to reach", name));
+                        }
+                        output.contents[position..position + 4]
+                            .copy_from_slice(&(value as i32).to_le_bytes());
+                    }
+                    kind => {
+                        return Err(format!(
+                            "relocation type {} for `{}' isn't supported",
+                            kind, name
+                        ))
+                    }
+                }
+            }
+        }
+    }
+
+    let mut executable = Executable {
+        entry: globals["_start"],
+        segments: vec![],
+    };
+    for &i in &order {
+        let output = &outputs[i];
+        let end = output.address + output.size as u64;
+        match executable.segments.last_mut() {
+            Some(segment)
+                if segment.writable == output.writable
+                    && segment.executable == output.executable =>
+            {
+                let start = (output.address - segment.address) as usize;
+                if !output.nobits() {
+                    segment.contents.resize(start, 0);
+                    segment.contents.extend_from_slice(&output.contents);
+                }
+                segment.size = end - segment.address;
+            }
+            _ => executable.segments.push(Segment {
+                address: output.address,
+                contents: match output.nobits() {
+                    true => vec![],
+                    false => output.contents.clone(),
+                },
+                size: output.size as u64,
+                writable: output.writable,
+                executable: output.executable,
+            }),
+        }
+    }
+    Ok(executable)
+}
@@ -0,0 +1,138 @@
+//! Decoding of the contents of literal tokens. The grammar only matches
+//! their shape, the functions here turn the matched text into values.
+
+use crate::ast::{IntConstant, Type};
+
+/// decodes the body of a string or character literal, without its quotes
+pub fn unescape(literal: &str) -> Result<Vec<u8>, &'static str> {
+    let mut bytes = vec![];
+    let mut chars = literal.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buf).bytes());
+            continue;
+        }
+        let escaped = chars.next().ok_or("unterminated escape sequence")?;
+        let byte = match escaped {
+            'n' => b'\n',
+            't' => b'\t',
+            'r' => b'\r',
+            'a' => 0x07,
+            'b' => 0x08,
+            'f' => 0x0c,
+            'v' => 0x0b,
+            '\\' | '\'' | '"' | '?' => escaped as u8,
+            '0'..='7' => {
+                // up to three octal digits, so \0 is just the shortest case
+                let mut value = escaped.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            value = value * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                if value > 0xff {
+                    return Err("octal escape sequence out of range");
+                }
+                value as u8
+            }
+            'x' => {
+                let mut value: u32 = 0;
+                let mut digits = 0;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(16)) {
+                    value = value.saturating_mul(16).saturating_add(digit);
+                    digits += 1;
+                    chars.next();
+                }
+                if digits == 0 {
+                    return Err("\\x used with no following hex digits");
+                }
+                if value > 0xff {
+                    return Err("hex escape sequence out of range");
+                }
+                value as u8
+            }
+            _ => return Err("unknown escape sequence"),
+        };
+        bytes.push(byte);
+    }
+    Ok(bytes)
+}
+
+/// the value of a character constant like 'a' or '\n', which has type int
+pub fn char_constant(literal: &str) -> Result<IntConstant, &'static str> {
+    match unescape(literal)?.as_slice() {
+        // plain char is signed, so '\xff' is -1
+        [byte] => Ok(IntConstant::int(i32::from(*byte as i8))),
+        [] => Err("empty character constant"),
+        _ => Err("multi-character character constants are not supported"),
+    }
+}
+
+/// parses an integer constant like `42`, `0x7f`, `017`, `0b101` or `1UL`.
+/// The type is the first one in C's list for the radix and suffix that
+/// can represent the value.
+pub fn int_constant(literal: &str) -> Result<IntConstant, &'static str> {
+    let (radix, body) = if literal.starts_with("0x") || literal.starts_with("0X") {
+        (16, &literal[2..])
+    } else if literal.starts_with("0b") || literal.starts_with("0B") {
+        (2, &literal[2..])
+    } else if literal.starts_with('0') && literal.len() > 1 {
+        (8, &literal[1..])
+    } else {
+        (10, literal)
+    };
+    let digits_end = body
+        .find(|c: char| !c.is_ascii_hexdigit() || (radix != 16 && c.is_ascii_alphabetic()))
+        .unwrap_or(body.len());
+    let (digits, suffix) = body.split_at(digits_end);
+    if digits.is_empty() && radix != 8 {
+        return Err("integer constant has no digits");
+    }
+
+    let mut value: u64 = 0;
+    for c in digits.chars() {
+        let digit = c.to_digit(radix).ok_or(match radix {
+            8 => "invalid digit in octal constant",
+            2 => "invalid digit in binary constant",
+            _ => "invalid digit in integer constant",
+        })?;
+        value = value
+            .checked_mul(u64::from(radix))
+            .and_then(|value| value.checked_add(u64::from(digit)))
+            .ok_or("integer constant is too large for its type")?;
+    }
+
+    let (unsigned, long) = match suffix {
+        "" => (false, false),
+        "u" | "U" => (true, false),
+        "l" | "L" | "ll" | "LL" => (false, true),
+        "ul" | "uL" | "Ul" | "UL" | "lu" | "lU" | "Lu" | "LU" | "ull" | "uLL" | "Ull" | "ULL"
+        | "llu" | "llU" | "LLu" | "LLU" => (true, true),
+        _ => return Err("invalid suffix on integer constant"),
+    };
+    // decimal constants only become unsigned when asked to (or when nothing
+    // else fits, like gcc), other radixes try the unsigned type of each rank
+    let candidates: &[Type] = match (unsigned, long, radix) {
+        (false, false, 10) => &[Type::Int, Type::Long, Type::UnsignedLong],
+        (false, false, _) => &[Type::Int, Type::UnsignedInt, Type::Long, Type::UnsignedLong],
+        (true, false, _) => &[Type::UnsignedInt, Type::UnsignedLong],
+        (false, true, _) => &[Type::Long, Type::UnsignedLong],
+        (true, true, _) => &[Type::UnsignedLong],
+    };
+    let fits = |ty: &Type| match ty {
+        Type::Int => value <= i32::MAX as u64,
+        Type::UnsignedInt => value <= u64::from(u32::MAX),
+        Type::Long => value <= i64::MAX as u64,
+        _ => true,
+    };
+    let ty = candidates.iter().find(|ty| fits(ty)).unwrap().clone();
+    Ok(IntConstant {
+        value: value as i64,
+        ty,
+    })
+}
@@ -1,33 +1,259 @@
 #[macro_use]
 extern crate lalrpop_util;
 mod asm;
+mod assembler;
 mod ast;
+mod codegen;
 mod compiler;
+mod elf;
+mod ir;
+mod linker;
+mod literal;
+mod optimizer;
+mod peephole;
 mod platform;
-lalrpop_mod!(pub c);
+mod preprocessor;
+lalrpop_mod!(#[allow(clippy::all, unused_parens)] pub c);
 
-use clap::{App, Arg};
-use std::{fs, io};
+use clap::{value_t, App, Arg};
+use std::fmt::Display;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::{fs, io, process};
+
+/// what the command line asks of the compiler
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// comment the code with where each virtual register was allocated
+    pub allocation_comments: bool,
+    /// 0 for no optimization
+    pub opt_level: u32,
+    /// the most IR instructions a function can have and still be inlined,
+    /// unless it's declared `inline`
+    pub inline_threshold: usize,
+    /// reuse values that were already computed, at -O2
+    pub cse: bool,
+    /// move code that computes the same thing each time out of loops, at
+    /// -O2
+    pub licm: bool,
+    /// turn calls the function returns the result of into jumps, at -O2
+    pub tail_calls: bool,
+    /// the passes to print the IR after
+    pub print_after: Vec<String>,
+    /// print how long each pass took
+    pub time_passes: bool,
+}
 
 fn main() {
     let matches = App::new("u-cc")
-        .arg(Arg::with_name("input").takes_value(true).required(true))
+        .arg(
+            Arg::with_name("input")
+                .takes_value(true)
+                .required(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("emit")
+                .long("emit")
+                .takes_value(true)
+                .possible_values(&["asm", "ir"])
+                .default_value("asm")
+                .help("Prints the assembly, or the intermediate representation"),
+        )
+        .arg(
+            Arg::with_name("compile-only")
+                .short("c")
+                .help("Writes an ELF object file instead of printing the assembly"),
+        )
+        .arg(
+            Arg::with_name("link")
+                .long("link")
+                .conflicts_with("compile-only")
+                .help("Compiles the C files and links them with any object files u-cc wrote into a static executable, which has no C library"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .takes_value(true)
+                .help("Writes the object file here instead of to the input's name ending in .o, or the executable here instead of to a.out"),
+        )
+        .arg(
+            Arg::with_name("syntax")
+                .long("syntax")
+                .takes_value(true)
+                .possible_values(&["nasm", "att"])
+                .default_value("nasm")
+                .help("Writes the assembly in Intel syntax for NASM, or AT&T syntax for GNU as"),
+        )
+        .arg(
+            Arg::with_name("regalloc-comments")
+                .long("regalloc-comments")
+                .help("Comments the output with the register each virtual register got"),
+        )
+        .arg(
+            Arg::with_name("opt-level")
+                .short("O")
+                .takes_value(true)
+                .possible_values(&["0", "1", "2"])
+                .default_value("0")
+                .help("Optimizes the code, with -O1 folding constants, removing dead code and cleaning up the assembly, and -O2 also inlining calls, turning tail calls into jumps, reusing common subexpressions and moving invariant code out of loops"),
+        )
+        .arg(
+            Arg::with_name("inline-threshold")
+                .long("inline-threshold")
+                .takes_value(true)
+                .default_value("20")
+                .help("Inlines functions of up to this many IR instructions at -O2, as well as ones declared inline"),
+        )
+        .arg(
+            Arg::with_name("no-cse")
+                .long("no-cse")
+                .help("Turns off common subexpression elimination"),
+        )
+        .arg(
+            Arg::with_name("no-licm")
+                .long("no-licm")
+                .help("Turns off moving loop-invariant code out of loops"),
+        )
+        .arg(
+            Arg::with_name("no-tail-calls")
+                .long("no-tail-calls")
+                .help("Keeps calls in tail position as calls, so they show up in backtraces"),
+        )
+        .arg(
+            Arg::with_name("print-after")
+                .long("print-after")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(optimizer::PASSES)
+                .help("Prints the IR to stderr after each time the pass runs"),
+        )
+        .arg(
+            Arg::with_name("time-passes")
+                .long("time-passes")
+                .help("Prints how long each pass took to stderr"),
+        )
         .get_matches();
+    let options = Options {
+        allocation_comments: matches.is_present("regalloc-comments"),
+        opt_level: matches.value_of("opt-level").unwrap().parse().unwrap(),
+        inline_threshold: value_t!(matches, "inline-threshold", usize).unwrap_or_else(|e| e.exit()),
+        cse: !matches.is_present("no-cse"),
+        licm: !matches.is_present("no-licm"),
+        tail_calls: !matches.is_present("no-tail-calls"),
+        print_after: matches
+            .values_of("print-after")
+            .map(|passes| passes.map(str::to_string).collect())
+            .unwrap_or_default(),
+        time_passes: matches.is_present("time-passes"),
+    };
+
+    let inputs: Vec<&Path> = matches
+        .values_of_os("input")
+        .unwrap()
+        .map(Path::new)
+        .collect();
+    if matches.is_present("link") {
+        let objects = inputs.iter().map(|input| match input.extension() {
+            Some(extension) if extension == "o" => {
+                let file = fs::read(input).unwrap_or_else(|err| fail(input, err));
+                elf::Object::read(&file).unwrap_or_else(|err| fail(input, err))
+            }
+            _ => assembler::assemble(&instructions(input, &options)),
+        });
+        let executable = match linker::link(&objects.collect::<Vec<_>>()) {
+            Ok(executable) => executable,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                process::exit(1);
+            }
+        };
+        let output = Path::new(matches.value_of_os("output").unwrap_or("a.out".as_ref()));
+        write_executable(output, &executable.write()).unwrap_or_else(|err| fail(output, err));
+        return;
+    }
+    let input = match inputs[..] {
+        [input] => input,
+        _ => {
+            eprintln!("error: only --link takes more than one input");
+            process::exit(1);
+        }
+    };
+    if matches.value_of("emit") == Some("ir") {
+        print!("{}", module(input, &options));
+        return;
+    }
+    let instructions = instructions(input, &options);
+    if matches.is_present("compile-only") {
+        let output = match matches.value_of_os("output") {
+            Some(output) => PathBuf::from(output),
+            None => PathBuf::from(input.file_name().unwrap()).with_extension("o"),
+        };
+        let object = assembler::assemble(&instructions);
+        fs::write(&output, object.write()).unwrap_or_else(|err| fail(&output, err));
+        return;
+    }
+    let syntax = match matches.value_of("syntax") {
+        Some("att") => asm::Syntax::Att,
+        _ => asm::Syntax::Nasm,
+    };
+    for instruction in instructions.iter() {
+        println!("{}", instruction.display(syntax));
+    }
+}
 
-    let input_str = fs::read_to_string(matches.value_of_os("input").unwrap())
-        .expect("Failed to open input file");
+fn fail(path: &Path, err: impl Display) -> ! {
+    eprintln!("error: {}: {}", path.display(), err);
+    process::exit(1);
+}
+
+/// the optimized IR for a C file
+fn module(input: &Path, options: &Options) -> ir::Module {
+    let input_str = fs::read_to_string(input).unwrap_or_else(|err| fail(input, err));
 
-    let ast = match c::ProgramParser::new().parse(&input_str) {
+    let source = match preprocessor::preprocess(&input_str) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let ast = match c::ProgramParser::new().parse(&source) {
         Ok(program) => program,
         Err(err) => {
-            panic!("{}", err);
+            eprintln!("error: {}", err);
+            process::exit(1);
         }
     };
 
-    let instructions = compiler::compile(&ast);
-    println!("global {}", platform::main_symbol());
-    println!("section .text");
-    for instruction in instructions.iter() {
-        println!("{}", instruction);
+    let mut module = match compiler::compile(&ast) {
+        Ok(module) => module,
+        Err(errors) => {
+            for err in errors {
+                eprintln!("error: {}", err);
+            }
+            process::exit(1);
+        }
+    };
+    optimizer::optimize(&mut module, options);
+    module
+}
+
+/// the assembly for a C file
+fn instructions(input: &Path, options: &Options) -> Vec<asm::Instruction> {
+    let mut instructions = codegen::generate(&module(input, options), options);
+    if options.opt_level > 0 {
+        peephole::optimize(&mut instructions);
     }
+    instructions
+}
+
+/// writes the file so that it can be run
+fn write_executable(output: &Path, contents: &[u8]) -> io::Result<()> {
+    fs::write(output, contents)?;
+    let mut permissions = fs::metadata(output)?.permissions();
+    permissions.set_mode(0o755);
+    fs::set_permissions(output, permissions)
 }
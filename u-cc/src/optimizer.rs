@@ -0,0 +1,134 @@
+//! Passes over the IR that make the code faster without changing what it
+//! does. The optimization level picks which passes the pass manager runs:
+//! none at -O0, so the IR stays a direct translation of the source there,
+//! the cheap local ones at -O1, and the rest at -O2. Unreachable statements
+//! are warned about at every level.
+//!
+//! At -O2, functions are simplified before inlining, so their size is what
+//! it'll be once they're inlined, and again afterwards, since the arguments
+//! are often constants. Tail calls, common subexpressions and
+//! loop-invariant code go last, so the loops tail calls make get their
+//! invariants moved out too, and each can be turned off on its own to
+//! narrow down a miscompile.
+
+use crate::ir;
+use crate::Options;
+use std::time::{Duration, Instant};
+
+mod common_subexpressions;
+mod constants;
+mod dead_code;
+mod dominators;
+mod inline;
+mod loop_invariants;
+mod tail_calls;
+
+/// the name of every pass, for the command line
+pub const PASSES: &[&str] = &[
+    "constants",
+    "dead-code",
+    "inline",
+    "tail-calls",
+    "cse",
+    "licm",
+];
+
+struct Pass<'a> {
+    name: &'static str,
+    run: Box<dyn Fn(&mut ir::Module) + 'a>,
+}
+
+/// runs passes over the module in the order they were added, printing the
+/// IR after the ones `--print-after` names and timing each if asked
+pub struct PassManager<'a> {
+    passes: Vec<Pass<'a>>,
+    print_after: &'a [String],
+    time_passes: bool,
+}
+
+impl<'a> PassManager<'a> {
+    pub fn new(options: &'a Options) -> PassManager<'a> {
+        PassManager {
+            passes: vec![],
+            print_after: &options.print_after,
+            time_passes: options.time_passes,
+        }
+    }
+    pub fn add(&mut self, name: &'static str, run: impl Fn(&mut ir::Module) + 'a) {
+        debug_assert!(PASSES.contains(&name), "Unknown pass {}", name);
+        self.passes.push(Pass {
+            name,
+            run: Box::new(run),
+        });
+    }
+    /// adds a pass that works on one function at a time
+    pub fn add_function_pass<T: 'a>(
+        &mut self,
+        name: &'static str,
+        run: fn(&mut ir::Function) -> T,
+    ) {
+        self.add(name, move |module| {
+            for function in &mut module.functions {
+                run(function);
+            }
+        });
+    }
+    pub fn run(&self, module: &mut ir::Module) {
+        let mut total = Duration::default();
+        for pass in &self.passes {
+            let start = Instant::now();
+            (pass.run)(module);
+            let elapsed = start.elapsed();
+            total += elapsed;
+            if self.time_passes {
+                eprintln!("{:>10.3} ms  {}", millis(elapsed), pass.name);
+            }
+            if self.print_after.iter().any(|name| name == pass.name) {
+                eprintln!("; IR after {}", pass.name);
+                eprint!("{}", module);
+            }
+        }
+        if self.time_passes {
+            eprintln!("{:>10.3} ms  total", millis(total));
+        }
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// constant folding and dead code elimination, which the other passes
+/// leave work for
+fn simplify(manager: &mut PassManager) {
+    manager.add_function_pass("constants", constants::propagate);
+    manager.add_function_pass("dead-code", dead_code::eliminate);
+}
+
+pub fn optimize(module: &mut ir::Module, options: &Options) {
+    for function in &module.functions {
+        dead_code::warn_unreachable(function);
+    }
+    let mut manager = PassManager::new(options);
+    if options.opt_level >= 1 {
+        simplify(&mut manager);
+    }
+    if options.opt_level >= 2 {
+        let threshold = options.inline_threshold;
+        manager.add("inline", move |module| {
+            inline::inline_calls(module, threshold)
+        });
+        simplify(&mut manager);
+        if options.tail_calls {
+            manager.add_function_pass("tail-calls", tail_calls::optimize);
+        }
+        if options.cse {
+            manager.add_function_pass("cse", common_subexpressions::eliminate);
+        }
+        if options.licm {
+            manager.add_function_pass("licm", loop_invariants::hoist_invariants);
+        }
+        manager.add_function_pass("dead-code", dead_code::eliminate);
+    }
+    manager.run(module);
+}
@@ -0,0 +1,215 @@
+//! Common subexpression elimination. Walking down the dominator tree, an
+//! operation that was already computed on every path to it becomes a copy
+//! of the earlier result. Since registers can be assigned more than once,
+//! a result is only reused in other blocks when every register involved
+//! is assigned once, before the first computation; anything else is only
+//! reused later in the same block, until one of its registers changes.
+//! Loads are never reused across blocks, and a store or call in between
+//! means memory might have changed.
+
+use super::dominators::Dominators;
+use crate::ir::{
+    Address, BinaryOp, BlockId, Comparison, Function, Instruction, Operand, Reg, Ty, UnaryOp,
+};
+use std::collections::HashMap;
+
+/// what an instruction computes, for finding it again
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Expression {
+    Unary(UnaryOp, Operand),
+    Binary(BinaryOp, Ty, Operand, Operand),
+    Compare(Comparison, Operand, Operand),
+    Convert(bool, Ty, Operand),
+    AddressOf(Address),
+    Load(Ty, Address),
+}
+
+/// the operands of a commutative operation in a standard order, so `a + b`
+/// and `b + a` are the same expression
+fn sorted(lhs: Operand, rhs: Operand) -> (Operand, Operand) {
+    let rank = |operand: &Operand| match operand {
+        Operand::Reg(reg) => (false, reg.number as i64),
+        Operand::Const(val, _) => (true, *val),
+    };
+    match rank(&lhs) <= rank(&rhs) {
+        true => (lhs, rhs),
+        false => (rhs, lhs),
+    }
+}
+
+fn expression(instruction: &Instruction) -> Option<Expression> {
+    Some(match instruction.clone() {
+        Instruction::Unary { op, src, .. } => Expression::Unary(op, src),
+        Instruction::Binary { op, dest, lhs, rhs } => {
+            let (lhs, rhs) = match op {
+                BinaryOp::Add | BinaryOp::Mul | BinaryOp::And | BinaryOp::Or | BinaryOp::Xor => {
+                    sorted(lhs, rhs)
+                }
+                _ => (lhs, rhs),
+            };
+            Expression::Binary(op, dest.ty, lhs, rhs)
+        }
+        Instruction::Compare {
+            comparison,
+            lhs,
+            rhs,
+            ..
+        } => {
+            let (lhs, rhs) = match comparison {
+                Comparison::Eq | Comparison::Ne => sorted(lhs, rhs),
+                _ => (lhs, rhs),
+            };
+            Expression::Compare(comparison, lhs, rhs)
+        }
+        Instruction::Convert { signed, dest, src } => Expression::Convert(signed, dest.ty, src),
+        Instruction::AddressOf { address, .. } => Expression::AddressOf(address),
+        Instruction::Load { dest, address } => Expression::Load(dest.ty, address),
+        Instruction::Copy { .. }
+        | Instruction::Store { .. }
+        | Instruction::Call { .. }
+        | Instruction::VaStart { .. } => return None,
+    })
+}
+
+/// where each register is assigned. Parameters count as assigned before
+/// the entry block.
+struct Definitions {
+    positions: HashMap<Reg, Vec<(BlockId, usize)>>,
+    parameters: Vec<Reg>,
+}
+
+impl Definitions {
+    fn new(function: &Function) -> Definitions {
+        let mut positions: HashMap<Reg, Vec<(BlockId, usize)>> = HashMap::new();
+        for block in &function.blocks {
+            for (index, instruction) in block.instructions.iter().enumerate() {
+                if let Some(dest) = instruction.dest() {
+                    positions.entry(dest).or_default().push((block.id, index));
+                }
+            }
+        }
+        Definitions {
+            positions,
+            parameters: function.parameters.clone(),
+        }
+    }
+    fn count(&self, reg: Reg) -> usize {
+        let parameter = self.parameters.contains(&reg) as usize;
+        parameter + self.positions.get(&reg).map_or(0, Vec::len)
+    }
+    /// whether the register has its one and only value by the time the
+    /// instruction at `index` in `block` runs
+    fn fixed_at(&self, reg: Reg, block: BlockId, index: usize, dominators: &Dominators) -> bool {
+        if self.count(reg) != 1 {
+            return false;
+        }
+        match self.positions.get(&reg).map(|positions| positions[0]) {
+            None => true,
+            Some((def_block, def_index)) if def_block == block => def_index < index,
+            Some((def_block, _)) => dominators.dominates(def_block, block),
+        }
+    }
+}
+
+/// an expression that was computed into a register
+struct Available {
+    expression: Expression,
+    value: Reg,
+    /// the registers it was computed from
+    uses: Vec<Reg>,
+}
+
+/// the state of the walk down the dominator tree
+struct Walk<'a> {
+    dominators: &'a Dominators,
+    definitions: &'a Definitions,
+    /// the expressions whose registers never change, computed in the
+    /// blocks that dominate the current one
+    fixed: Vec<Available>,
+    changed: bool,
+}
+
+impl Walk<'_> {
+    fn block(&mut self, function: &mut Function, id: BlockId) {
+        let scope = self.fixed.len();
+        // everything else available in the block so far
+        let mut local: Vec<Available> = vec![];
+        let block = function
+            .blocks
+            .iter_mut()
+            .find(|block| block.id == id)
+            .unwrap();
+        for (index, instruction) in block.instructions.iter_mut().enumerate() {
+            let mut computed = None;
+            if let (Some(expression), Some(dest)) = (expression(instruction), instruction.dest()) {
+                let found = local
+                    .iter()
+                    .chain(self.fixed.iter())
+                    .find(|available| available.expression == expression);
+                match found {
+                    Some(available) => {
+                        *instruction = Instruction::Copy {
+                            dest,
+                            src: available.value.into(),
+                        };
+                        self.changed = true;
+                    }
+                    None => computed = Some(expression),
+                }
+            }
+            // memory might have changed
+            if matches!(
+                instruction,
+                Instruction::Store { .. } | Instruction::Call { .. } | Instruction::VaStart { .. }
+            ) {
+                local.retain(|available| !matches!(available.expression, Expression::Load(..)));
+            }
+            let dest = match instruction.dest() {
+                Some(dest) => dest,
+                None => continue,
+            };
+            // what was computed from or into the old value is out of date
+            local.retain(|available| available.value != dest && !available.uses.contains(&dest));
+            let uses = instruction.uses();
+            let expression = match computed {
+                // like `x = x + 1`, which is about the old x
+                Some(_) if uses.contains(&dest) => continue,
+                Some(expression) => expression,
+                None => continue,
+            };
+            let fixed = !matches!(expression, Expression::Load(..))
+                && self.definitions.count(dest) == 1
+                && uses
+                    .iter()
+                    .all(|reg| self.definitions.fixed_at(*reg, id, index, self.dominators));
+            let available = Available {
+                expression,
+                value: dest,
+                uses,
+            };
+            match fixed {
+                true => self.fixed.push(available),
+                false => local.push(available),
+            }
+        }
+        for child in self.dominators.children(id) {
+            self.block(function, child);
+        }
+        self.fixed.truncate(scope);
+    }
+}
+
+/// returns whether anything was replaced
+pub fn eliminate(function: &mut Function) -> bool {
+    let dominators = Dominators::new(function);
+    let definitions = Definitions::new(function);
+    let mut walk = Walk {
+        dominators: &dominators,
+        definitions: &definitions,
+        fixed: vec![],
+        changed: false,
+    };
+    let entry = function.blocks[0].id;
+    walk.block(function, entry);
+    walk.changed
+}
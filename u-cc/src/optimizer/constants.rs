@@ -0,0 +1,216 @@
+//! Constant folding and propagation. A forward dataflow analysis finds the
+//! registers that hold the same constant on every path to a point, which
+//! covers locals that live in registers, and then operations on constants
+//! are replaced by their result. Folding follows what the code would have
+//! done at run time: arithmetic wraps, shift counts are masked like x86
+//! masks them, and divisions that would trap are left to trap.
+
+use crate::ir::{
+    BinaryOp, BlockId, Comparison, Function, Instruction, Operand, Reg, Terminator, Ty, UnaryOp,
+};
+use std::collections::HashMap;
+
+/// the registers known to hold a constant
+type Known = HashMap<Reg, i64>;
+
+/// the bits of a constant, as an unsigned number of its width
+fn unsigned(val: i64, ty: Ty) -> u64 {
+    match ty {
+        Ty::I64 => val as u64,
+        _ => val as u64 & ((1 << (8 * ty.size())) - 1),
+    }
+}
+
+fn unary(op: UnaryOp, ty: Ty, val: i64) -> i64 {
+    ty.truncate(match op {
+        UnaryOp::Neg => val.wrapping_neg(),
+        UnaryOp::Not => !val,
+    })
+}
+
+/// None for the divisions that raise an exception at run time
+fn binary(op: BinaryOp, ty: Ty, lhs: i64, rhs: i64) -> Option<i64> {
+    let min = ty.truncate(1 << (8 * ty.size() - 1));
+    // the hardware only looks at the low 5 bits of the count, or 6 bits
+    // for 64 bit operands
+    let count = match ty {
+        Ty::I64 => rhs & 63,
+        _ => rhs & 31,
+    };
+    let val = match op {
+        BinaryOp::Add => lhs.wrapping_add(rhs),
+        BinaryOp::Sub => lhs.wrapping_sub(rhs),
+        BinaryOp::Mul => lhs.wrapping_mul(rhs),
+        BinaryOp::SDiv | BinaryOp::SRem if rhs == 0 || (lhs == min && rhs == -1) => return None,
+        BinaryOp::UDiv | BinaryOp::URem if rhs == 0 => return None,
+        BinaryOp::SDiv => lhs / rhs,
+        BinaryOp::SRem => lhs % rhs,
+        BinaryOp::UDiv => (unsigned(lhs, ty) / unsigned(rhs, ty)) as i64,
+        BinaryOp::URem => (unsigned(lhs, ty) % unsigned(rhs, ty)) as i64,
+        BinaryOp::And => lhs & rhs,
+        BinaryOp::Or => lhs | rhs,
+        BinaryOp::Xor => lhs ^ rhs,
+        BinaryOp::Shl => lhs << count,
+        BinaryOp::Sar => lhs >> count,
+        BinaryOp::Shr => (unsigned(lhs, ty) >> count) as i64,
+    };
+    Some(ty.truncate(val))
+}
+
+fn compare(comparison: Comparison, ty: Ty, lhs: i64, rhs: i64) -> bool {
+    let (left, right) = (unsigned(lhs, ty), unsigned(rhs, ty));
+    match comparison {
+        Comparison::Eq => lhs == rhs,
+        Comparison::Ne => lhs != rhs,
+        Comparison::SLt => lhs < rhs,
+        Comparison::SLe => lhs <= rhs,
+        Comparison::SGt => lhs > rhs,
+        Comparison::SGe => lhs >= rhs,
+        Comparison::ULt => left < right,
+        Comparison::ULe => left <= right,
+        Comparison::UGt => left > right,
+        Comparison::UGe => left >= right,
+    }
+}
+
+fn convert(signed: bool, dest: Ty, src: Ty, val: i64) -> i64 {
+    match signed || dest.size() <= src.size() {
+        true => dest.truncate(val),
+        false => dest.truncate(unsigned(val, src) as i64),
+    }
+}
+
+/// the value an instruction computes, when its operands are constants
+fn fold(instruction: &Instruction) -> Option<i64> {
+    use Operand::Const;
+    match *instruction {
+        Instruction::Copy {
+            src: Const(val, _), ..
+        } => Some(val),
+        Instruction::Unary {
+            op,
+            dest,
+            src: Const(val, _),
+        } => Some(unary(op, dest.ty, val)),
+        Instruction::Binary {
+            op,
+            dest,
+            lhs: Const(lhs, _),
+            rhs: Const(rhs, _),
+        } => binary(op, dest.ty, lhs, rhs),
+        Instruction::Compare {
+            comparison,
+            lhs: Const(lhs, ty),
+            rhs: Const(rhs, _),
+            ..
+        } => Some(compare(comparison, ty, lhs, rhs) as i64),
+        Instruction::Convert {
+            signed,
+            dest,
+            src: Const(val, ty),
+        } => Some(convert(signed, dest.ty, ty, val)),
+        _ => None,
+    }
+}
+
+fn substitute(operand: &mut Operand, known: &Known) {
+    if let Operand::Reg(reg) = operand {
+        if let Some(val) = known.get(reg) {
+            *operand = Operand::Const(*val, reg.ty);
+        }
+    }
+}
+
+/// replaces the registers the instruction reads with what's known of them,
+/// folds it if that makes it constant, and records what it writes
+fn simplify(instruction: &mut Instruction, known: &mut Known) {
+    for operand in instruction.operands_mut() {
+        substitute(operand, known);
+    }
+    let dest = match instruction.dest() {
+        Some(dest) => dest,
+        None => return,
+    };
+    match fold(instruction) {
+        Some(val) => {
+            *instruction = Instruction::Copy {
+                dest,
+                src: Operand::Const(val, dest.ty),
+            };
+            known.insert(dest, val);
+        }
+        None => {
+            known.remove(&dest);
+        }
+    }
+}
+
+/// a branch on a constant always goes the same way
+fn simplify_terminator(terminator: &mut Terminator, known: &Known) {
+    if let Some(operand) = terminator.operand_mut() {
+        substitute(operand, known);
+    }
+    if let Terminator::Branch {
+        cond: Operand::Const(val, _),
+        then,
+        otherwise,
+    } = *terminator
+    {
+        *terminator = Terminator::Jump(if val != 0 { then } else { otherwise });
+    }
+}
+
+/// what's known on entry to each block. Blocks that can't be reached, like
+/// the side of a branch on a constant that's never taken, stay None.
+fn analyze(function: &Function) -> Vec<Option<Known>> {
+    let index: HashMap<BlockId, usize> = function
+        .blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| (block.id, i))
+        .collect();
+    let mut entry = vec![None; function.blocks.len()];
+    // nothing is known about the parameters
+    entry[0] = Some(Known::new());
+    let mut worklist = vec![0];
+    while let Some(i) = worklist.pop() {
+        let block = &function.blocks[i];
+        let mut known = entry[i].clone().unwrap();
+        for instruction in &block.instructions {
+            simplify(&mut instruction.clone(), &mut known);
+        }
+        let mut terminator = block.terminator.clone();
+        simplify_terminator(&mut terminator, &known);
+        for successor in terminator.successors() {
+            let j = index[&successor];
+            // a register is only known if every way in agrees on it
+            let merged = match &entry[j] {
+                None => known.clone(),
+                Some(old) => old
+                    .iter()
+                    .filter(|(reg, val)| known.get(reg) == Some(val))
+                    .map(|(reg, val)| (*reg, *val))
+                    .collect(),
+            };
+            if entry[j].as_ref() != Some(&merged) {
+                entry[j] = Some(merged);
+                worklist.push(j);
+            }
+        }
+    }
+    entry
+}
+
+pub fn propagate(function: &mut Function) {
+    let entry = analyze(function);
+    for (block, known) in function.blocks.iter_mut().zip(entry) {
+        let mut known = match known {
+            Some(known) => known,
+            None => continue,
+        };
+        for instruction in &mut block.instructions {
+            simplify(instruction, &mut known);
+        }
+        simplify_terminator(&mut block.terminator, &known);
+    }
+}
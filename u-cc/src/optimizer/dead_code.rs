@@ -0,0 +1,376 @@
+//! Dead code elimination. Blocks that nothing reaches are dropped, and so
+//! are computations whose result is never used and stores to locals that
+//! are never read. Empty blocks are jumped over and a block that's the
+//! only way into the next one absorbs it, so jumps to the next instruction
+//! disappear. The reachability analysis also warns about statements that
+//! can never run.
+
+use crate::ir::{
+    Address, Base, BinaryOp, BlockId, Function, Instruction, Operand, Reg, Terminator,
+};
+use std::collections::{HashMap, HashSet};
+
+/// the blocks control can get to from the entry
+fn reachable(function: &Function) -> HashSet<BlockId> {
+    let blocks: HashMap<BlockId, &Terminator> = function
+        .blocks
+        .iter()
+        .map(|block| (block.id, &block.terminator))
+        .collect();
+    let mut reached = HashSet::new();
+    let mut worklist = vec![function.blocks[0].id];
+    while let Some(id) = worklist.pop() {
+        if reached.insert(id) {
+            worklist.extend(blocks[&id].successors());
+        }
+    }
+    reached
+}
+
+/// how many jumps and branches go to each block
+fn predecessors(function: &Function) -> HashMap<BlockId, usize> {
+    let mut predecessors = HashMap::new();
+    for block in &function.blocks {
+        for successor in block.terminator.successors() {
+            *predecessors.entry(successor).or_insert(0) += 1;
+        }
+    }
+    predecessors
+}
+
+/// warns about the first statement of each stretch of code that can't be
+/// reached, like code right after a return, break or goto
+pub fn warn_unreachable(function: &Function) {
+    let reached = reachable(function);
+    let predecessors = predecessors(function);
+    for block in &function.blocks {
+        // the blocks an unreachable block goes to are unreachable too, and
+        // only the first one gets a warning
+        if reached.contains(&block.id) || predecessors.contains_key(&block.id) {
+            continue;
+        }
+        if let Some(kind) = function.statements.get(&block.id) {
+            eprintln!(
+                "warning: {} statement in {} is unreachable",
+                kind, function.name
+            );
+        }
+    }
+}
+
+fn remove_unreachable(function: &mut Function) -> bool {
+    let reached = reachable(function);
+    let count = function.blocks.len();
+    function.blocks.retain(|block| reached.contains(&block.id));
+    function.blocks.len() != count
+}
+
+/// whether dropping the instruction changes nothing but its dest
+fn is_pure(instruction: &Instruction) -> bool {
+    !matches!(
+        instruction,
+        Instruction::Store { .. } | Instruction::Call { .. } | Instruction::VaStart { .. }
+    )
+}
+
+/// the registers each block needs from before it runs
+pub fn live_in(function: &Function) -> HashMap<BlockId, HashSet<Reg>> {
+    let mut live_in: HashMap<BlockId, HashSet<Reg>> = HashMap::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        // going backwards, since liveness flows from uses to definitions
+        for block in function.blocks.iter().rev() {
+            let mut live: HashSet<Reg> = block
+                .terminator
+                .successors()
+                .iter()
+                .flat_map(|successor| live_in.get(successor).into_iter().flatten())
+                .copied()
+                .chain(block.terminator.uses())
+                .collect();
+            for instruction in block.instructions.iter().rev() {
+                if let Some(dest) = instruction.dest() {
+                    live.remove(&dest);
+                }
+                live.extend(instruction.uses());
+            }
+            if live_in.get(&block.id) != Some(&live) {
+                live_in.insert(block.id, live);
+                changed = true;
+            }
+        }
+    }
+    live_in
+}
+
+/// removes pure instructions whose result isn't used before it's
+/// overwritten or the function returns
+fn remove_dead_instructions(function: &mut Function) -> bool {
+    let live_in = live_in(function);
+    let mut changed = false;
+    for block in &mut function.blocks {
+        let mut live: HashSet<Reg> = block
+            .terminator
+            .successors()
+            .iter()
+            .flat_map(|successor| &live_in[successor])
+            .copied()
+            .chain(block.terminator.uses())
+            .collect();
+        let mut kept = vec![];
+        for instruction in block.instructions.drain(..).rev() {
+            let dest = instruction.dest();
+            if is_pure(&instruction) && !dest.is_some_and(|dest| live.contains(&dest)) {
+                changed = true;
+                continue;
+            }
+            if let Some(dest) = dest {
+                live.remove(&dest);
+            }
+            live.extend(instruction.uses());
+            kept.push(instruction);
+        }
+        kept.reverse();
+        block.instructions = kept;
+    }
+    changed
+}
+
+/// the slot each register points into, if every value it's given is an
+/// address in the same slot. Pointers into a slot come from taking its
+/// address, and then adding offsets or copying.
+fn slot_pointers(function: &Function) -> HashMap<Reg, Option<usize>> {
+    let mut origins: HashMap<Reg, Option<usize>> = HashMap::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let instructions = function.blocks.iter().flat_map(|block| &block.instructions);
+        for instruction in instructions {
+            let dest = match instruction.dest() {
+                Some(dest) => dest,
+                None => continue,
+            };
+            let origin = match derived_from(instruction) {
+                Some(Base::Slot(slot)) => Some(slot),
+                Some(Base::Reg(reg)) => origins.get(&reg).copied().flatten(),
+                _ => None,
+            };
+            // registers are assigned more than once, so they have to agree
+            let merged = match origins.get(&dest) {
+                None => origin,
+                Some(previous) if *previous == origin => origin,
+                Some(_) => None,
+            };
+            if origins.get(&dest) != Some(&merged) {
+                origins.insert(dest, merged);
+                changed = true;
+            }
+        }
+    }
+    origins
+}
+
+/// what an instruction computes a pointer from, if it could be an address
+/// in a slot: the slot itself, or the register it offsets or copies
+fn derived_from(instruction: &Instruction) -> Option<Base> {
+    match instruction {
+        Instruction::AddressOf { address, .. } => Some(address.base.clone()),
+        Instruction::Copy {
+            src: Operand::Reg(reg),
+            ..
+        }
+        | Instruction::Binary {
+            op: BinaryOp::Add,
+            lhs: Operand::Reg(reg),
+            ..
+        }
+        | Instruction::Binary {
+            op: BinaryOp::Sub,
+            lhs: Operand::Reg(reg),
+            ..
+        } => Some(Base::Reg(*reg)),
+        _ => None,
+    }
+}
+
+/// the slot an address is in, if it's known
+fn slot_of(address: &Address, origins: &HashMap<Reg, Option<usize>>) -> Option<usize> {
+    match address.base {
+        Base::Slot(slot) => Some(slot),
+        Base::Reg(reg) => origins.get(&reg).copied().flatten(),
+        Base::Global(_) => None,
+    }
+}
+
+/// the slots whose address gets anywhere other than the address of a load
+/// or store, like into a call, so code elsewhere might use it
+pub fn escaping_slots(function: &Function) -> HashSet<usize> {
+    let origins = slot_pointers(function);
+    let mut escaping = HashSet::new();
+    for block in &function.blocks {
+        for instruction in &block.instructions {
+            if let Instruction::AddressOf { dest, address } = instruction {
+                if let Base::Slot(slot) = address.base {
+                    if origins[dest] != Some(slot) {
+                        escaping.insert(slot);
+                    }
+                }
+            }
+            let dest = instruction.dest();
+            for reg in instruction.uses() {
+                let slot = match origins.get(&reg) {
+                    Some(Some(slot)) => *slot,
+                    _ => continue,
+                };
+                let is_address = instruction
+                    .address()
+                    .is_some_and(|address| address.base == Base::Reg(reg));
+                let is_derivation = derived_from(instruction) == Some(Base::Reg(reg))
+                    && dest.map(|dest| origins[&dest]) == Some(Some(slot));
+                if !is_address && !is_derivation {
+                    escaping.insert(slot);
+                }
+            }
+        }
+        if let Some(reg) = block.terminator.uses() {
+            escaping.extend(origins.get(&reg).copied().flatten());
+        }
+    }
+    escaping
+}
+
+/// removes the stores to slots that are never loaded from. A slot whose
+/// address escapes might be read through it, so its stores stay.
+fn remove_dead_stores(function: &mut Function) -> bool {
+    let origins = slot_pointers(function);
+    let mut read = escaping_slots(function);
+    for block in &function.blocks {
+        for instruction in &block.instructions {
+            if let Instruction::Load { address, .. } = instruction {
+                read.extend(slot_of(address, &origins));
+            }
+        }
+    }
+    let mut changed = false;
+    for block in &mut function.blocks {
+        block.instructions.retain(|instruction| match instruction {
+            Instruction::Store { address, .. } => match slot_of(address, &origins) {
+                Some(slot) if !read.contains(&slot) => {
+                    changed = true;
+                    false
+                }
+                _ => true,
+            },
+            _ => true,
+        });
+    }
+    changed
+}
+
+fn redirect(terminator: &mut Terminator, from: BlockId, to: BlockId) {
+    match terminator {
+        Terminator::Jump(target) => {
+            if *target == from {
+                *target = to;
+            }
+        }
+        Terminator::Branch {
+            then, otherwise, ..
+        } => {
+            for target in [then, otherwise] {
+                if *target == from {
+                    *target = to;
+                }
+            }
+        }
+        Terminator::Return(_) => {}
+    }
+}
+
+/// sends jumps to an empty block straight to where it goes, and turns
+/// branches that go the same way either way into jumps
+fn bypass_empty_blocks(function: &mut Function) -> bool {
+    let mut changed = false;
+    for block in &mut function.blocks {
+        if let Terminator::Branch {
+            then, otherwise, ..
+        } = block.terminator
+        {
+            if then == otherwise {
+                block.terminator = Terminator::Jump(then);
+                changed = true;
+            }
+        }
+    }
+    let entry = function.blocks[0].id;
+    let empty = function
+        .blocks
+        .iter()
+        .find_map(|block| match block.terminator {
+            // a block that jumps to itself is an infinite loop, which stays
+            Terminator::Jump(target)
+                if block.instructions.is_empty() && block.id != entry && target != block.id =>
+            {
+                Some((block.id, target))
+            }
+            _ => None,
+        });
+    if let Some((from, to)) = empty {
+        for block in &mut function.blocks {
+            redirect(&mut block.terminator, from, to);
+        }
+        // nothing goes to the empty block anymore
+        function.blocks.retain(|block| block.id != from);
+        changed = true;
+    }
+    changed
+}
+
+/// appends a block to the one before it when that's the only way in
+fn merge_blocks(function: &mut Function) -> bool {
+    let predecessors = predecessors(function);
+    let entry = function.blocks[0].id;
+    let pair = function
+        .blocks
+        .iter()
+        .find_map(|block| match block.terminator {
+            Terminator::Jump(target)
+                if target != block.id && target != entry && predecessors[&target] == 1 =>
+            {
+                Some((block.id, target))
+            }
+            _ => None,
+        });
+    let (into, from) = match pair {
+        Some(pair) => pair,
+        None => return false,
+    };
+    let position = function
+        .blocks
+        .iter()
+        .position(|block| block.id == from)
+        .unwrap();
+    let absorbed = function.blocks.remove(position);
+    let block = function
+        .blocks
+        .iter_mut()
+        .find(|block| block.id == into)
+        .unwrap();
+    block.instructions.extend(absorbed.instructions);
+    block.terminator = absorbed.terminator;
+    true
+}
+
+pub fn eliminate(function: &mut Function) {
+    loop {
+        let changed = remove_unreachable(function)
+            | remove_dead_instructions(function)
+            | remove_dead_stores(function)
+            | bypass_empty_blocks(function)
+            | merge_blocks(function);
+        if !changed {
+            break;
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! The dominator tree of a function's blocks, by the iterative algorithm
+//! of Cooper, Harvey and Kennedy. A block dominates another when every
+//! path from the entry to the second goes through the first.
+
+use crate::ir::{BlockId, Function};
+use std::collections::HashMap;
+
+pub struct Dominators {
+    /// the immediate dominator of each reachable block, with the entry as
+    /// its own
+    idom: HashMap<BlockId, BlockId>,
+    /// the reachable blocks, each after every block that dominates it
+    order: Vec<BlockId>,
+}
+
+/// the blocks reachable from the entry in postorder
+fn postorder(function: &Function) -> Vec<BlockId> {
+    let successors: HashMap<BlockId, Vec<BlockId>> = function
+        .blocks
+        .iter()
+        .map(|block| (block.id, block.terminator.successors()))
+        .collect();
+    let entry = function.blocks[0].id;
+    let mut order = vec![];
+    let mut visited = vec![entry];
+    // each block with how many of its successors have been looked at
+    let mut stack = vec![(entry, 0)];
+    while let Some((id, next)) = stack.pop() {
+        match successors[&id].get(next) {
+            Some(&successor) => {
+                stack.push((id, next + 1));
+                if !visited.contains(&successor) {
+                    visited.push(successor);
+                    stack.push((successor, 0));
+                }
+            }
+            None => order.push(id),
+        }
+    }
+    order
+}
+
+impl Dominators {
+    pub fn new(function: &Function) -> Dominators {
+        let postorder = postorder(function);
+        let number: HashMap<BlockId, usize> = postorder
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (*id, i))
+            .collect();
+        let mut predecessors: HashMap<BlockId, Vec<BlockId>> = HashMap::new();
+        for block in &function.blocks {
+            for successor in block.terminator.successors() {
+                predecessors.entry(successor).or_default().push(block.id);
+            }
+        }
+        let entry = function.blocks[0].id;
+        let mut idom = HashMap::new();
+        idom.insert(entry, entry);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &id in postorder.iter().rev().filter(|id| **id != entry) {
+                // meets the predecessors processed so far, going up the tree
+                // from whichever is further from the entry
+                let mut new_idom: Option<BlockId> = None;
+                for &predecessor in predecessors.get(&id).into_iter().flatten() {
+                    if !idom.contains_key(&predecessor) {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => predecessor,
+                        Some(mut other) => {
+                            let mut finger = predecessor;
+                            while finger != other {
+                                while number[&finger] < number[&other] {
+                                    finger = idom[&finger];
+                                }
+                                while number[&other] < number[&finger] {
+                                    other = idom[&other];
+                                }
+                            }
+                            finger
+                        }
+                    });
+                }
+                let new_idom = new_idom.unwrap();
+                if idom.get(&id) != Some(&new_idom) {
+                    idom.insert(id, new_idom);
+                    changed = true;
+                }
+            }
+        }
+        Dominators {
+            idom,
+            order: postorder.into_iter().rev().collect(),
+        }
+    }
+    pub fn is_reachable(&self, id: BlockId) -> bool {
+        self.idom.contains_key(&id)
+    }
+    /// whether every path to `b` goes through `a`, which is true of `b`
+    /// itself
+    pub fn dominates(&self, a: BlockId, mut b: BlockId) -> bool {
+        if !self.is_reachable(b) {
+            return false;
+        }
+        loop {
+            if a == b {
+                return true;
+            }
+            let parent = self.idom[&b];
+            if parent == b {
+                return false;
+            }
+            b = parent;
+        }
+    }
+    /// the blocks `id` immediately dominates
+    pub fn children(&self, id: BlockId) -> Vec<BlockId> {
+        self.order
+            .iter()
+            .copied()
+            .filter(|child| *child != id && self.idom[child] == id)
+            .collect()
+    }
+}
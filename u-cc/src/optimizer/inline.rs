@@ -0,0 +1,285 @@
+//! Inlining. A call to a small function, or one declared `inline`, is
+//! replaced by a copy of the function's blocks, with its registers, slots
+//! and blocks renumbered after the caller's. Functions whose address is
+//! taken keep their calls, since they're what the pointer has to reach,
+//! and so do recursive functions, which would only be unrolled a few
+//! times. Static functions nothing calls anymore are dropped.
+
+use crate::asm::{self, Data, DataValue};
+use crate::ir::{Base, Block, BlockId, Function, Instruction, Module, Operand, Reg, Terminator};
+use std::collections::{HashMap, HashSet};
+
+/// how many calls deep inlining goes, so functions that call each other
+/// don't grow without end
+const MAX_DEPTH: usize = 4;
+
+/// the symbols the module uses as addresses, rather than calling them
+fn address_taken(module: &Module) -> HashSet<&str> {
+    let mut taken = HashSet::new();
+    let instructions = module
+        .functions
+        .iter()
+        .flat_map(|function| &function.blocks)
+        .flat_map(|block| &block.instructions);
+    for instruction in instructions {
+        if let Some(address) = instruction.address() {
+            if let Base::Global(label) = &address.base {
+                taken.insert(label.as_str());
+            }
+        }
+    }
+    // initializers like `int (*f)(void) = &g;`
+    for data in module.data.values().flatten() {
+        if let asm::Instruction::Data(Data::Qword(values)) = data {
+            for value in values {
+                if let DataValue::Label(label) = value {
+                    taken.insert(label.as_str());
+                }
+            }
+        }
+    }
+    taken
+}
+
+fn calls_itself(function: &Function) -> bool {
+    function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .any(|instruction| match instruction {
+            Instruction::Call { function: name, .. } => *name == function.name,
+            _ => false,
+        })
+}
+
+fn size(function: &Function) -> usize {
+    function
+        .blocks
+        .iter()
+        .map(|block| block.instructions.len())
+        .sum()
+}
+
+/// the callee a call can be replaced with, if any
+fn callee_of<'a>(
+    instruction: &Instruction,
+    callees: &'a HashMap<String, Function>,
+) -> Option<&'a Function> {
+    match instruction {
+        Instruction::Call {
+            function,
+            arguments,
+            returned_struct,
+            ..
+        } => callees
+            .get(function)
+            // a call without a prototype can pass the wrong number, or
+            // expect something else back
+            .filter(|callee| {
+                callee.parameters.len() == arguments.len()
+                    && callee.returned_struct == *returned_struct
+            }),
+        _ => None,
+    }
+}
+
+/// renumbers everything a copy of the callee refers to so it doesn't clash
+/// with the caller's
+struct Renaming {
+    registers: usize,
+    slots: usize,
+    blocks: usize,
+}
+
+impl Renaming {
+    fn reg(&self, reg: Reg) -> Reg {
+        Reg {
+            number: reg.number + self.registers,
+            ty: reg.ty,
+        }
+    }
+    fn block(&self, id: BlockId) -> BlockId {
+        BlockId(id.0 + self.blocks)
+    }
+    fn operand(&self, operand: &mut Operand) {
+        if let Operand::Reg(reg) = operand {
+            *reg = self.reg(*reg);
+        }
+    }
+    fn instruction(&self, instruction: &mut Instruction) {
+        for operand in instruction.operands_mut() {
+            self.operand(operand);
+        }
+        if let Some(dest) = instruction.dest_mut() {
+            *dest = self.reg(*dest);
+        }
+        if let Some(address) = instruction.address_mut() {
+            match &mut address.base {
+                Base::Reg(reg) => *reg = self.reg(*reg),
+                Base::Slot(slot) => *slot += self.slots,
+                Base::Global(_) => {}
+            }
+        }
+    }
+    fn terminator(&self, terminator: &mut Terminator) {
+        if let Some(operand) = terminator.operand_mut() {
+            self.operand(operand);
+        }
+        for target in terminator.successors_mut() {
+            *target = self.block(*target);
+        }
+    }
+}
+
+/// replaces the call at `index` in the block at `position` with the
+/// callee's blocks. The code after the call moves to a block of its own,
+/// which the callee's returns jump to.
+fn inline_call(caller: &mut Function, position: usize, index: usize, callee: &Function) {
+    let renaming = Renaming {
+        registers: caller.registers,
+        slots: caller.slots.len(),
+        blocks: caller.block_ids,
+    };
+    caller.registers += callee.registers;
+    caller.slots.extend(callee.slots.iter().cloned());
+    caller.block_ids += callee.block_ids;
+    let rest = caller.new_block_id();
+
+    let block = &mut caller.blocks[position];
+    let mut after = block.instructions.split_off(index);
+    let (dest, arguments) = match after.remove(0) {
+        Instruction::Call {
+            dest, arguments, ..
+        } => (dest, arguments),
+        other => panic!("Inlining {} into something other than a call", other),
+    };
+    // the arguments go where the callee expects its parameters
+    for (parameter, argument) in callee.parameters.iter().zip(arguments) {
+        let parameter = renaming.reg(*parameter);
+        block
+            .instructions
+            .push(Instruction::pass(parameter, argument));
+    }
+    let terminator = std::mem::replace(
+        &mut block.terminator,
+        Terminator::Jump(renaming.block(callee.blocks[0].id)),
+    );
+
+    let mut blocks = vec![];
+    for block in &callee.blocks {
+        let mut block = block.clone();
+        block.id = renaming.block(block.id);
+        for instruction in &mut block.instructions {
+            renaming.instruction(instruction);
+        }
+        if let Terminator::Return(value) = &block.terminator {
+            if let (Some(dest), Some(mut value)) = (dest, *value) {
+                renaming.operand(&mut value);
+                block
+                    .instructions
+                    .push(Instruction::Copy { dest, src: value });
+            }
+            block.terminator = Terminator::Jump(rest);
+        } else {
+            renaming.terminator(&mut block.terminator);
+        }
+        blocks.push(block);
+    }
+    blocks.push(Block {
+        id: rest,
+        instructions: after,
+        terminator,
+    });
+    caller.blocks.splice(position + 1..position + 1, blocks);
+}
+
+/// inlines every call in the function that's there before it starts,
+/// returning whether there were any. The calls in the inlined code are one
+/// level deeper, so they're left for the next round.
+fn inline_round(function: &mut Function, callees: &HashMap<String, Function>) -> bool {
+    let mut changed = false;
+    let mut position = 0;
+    while position < function.blocks.len() {
+        let found = function.blocks[position]
+            .instructions
+            .iter()
+            .enumerate()
+            .find_map(|(index, instruction)| {
+                callee_of(instruction, callees).map(|callee| (index, callee))
+            });
+        match found {
+            Some((index, callee)) => {
+                inline_call(function, position, index, callee);
+                // carry on from the code after the call
+                position += callee.blocks.len() + 1;
+                changed = true;
+            }
+            None => position += 1,
+        }
+    }
+    changed
+}
+
+/// the functions that are called or have their address taken
+fn used(module: &Module) -> HashSet<String> {
+    let mut used: HashSet<String> = address_taken(module)
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    let instructions = module
+        .functions
+        .iter()
+        .flat_map(|function| &function.blocks)
+        .flat_map(|block| &block.instructions);
+    for instruction in instructions {
+        if let Instruction::Call { function, .. } = instruction {
+            used.insert(function.clone());
+        }
+    }
+    used
+}
+
+/// drops static functions nothing refers to anymore
+fn remove_unused(module: &mut Module) {
+    loop {
+        let used = used(module);
+        let exported = &module.exported;
+        let unused: Vec<String> = module
+            .functions
+            .iter()
+            .map(|function| function.name.clone())
+            .filter(|name| !exported.contains(name) && !used.contains(name))
+            .collect();
+        if unused.is_empty() {
+            break;
+        }
+        for name in &unused {
+            module.referenced.remove(name);
+        }
+        module
+            .functions
+            .retain(|function| !unused.contains(&function.name));
+    }
+}
+
+/// inlines calls to functions declared `inline`, and to ones with at most
+/// `threshold` instructions
+pub fn inline_calls(module: &mut Module, threshold: usize) {
+    let address_taken = address_taken(module);
+    let callees: HashMap<String, Function> = module
+        .functions
+        .iter()
+        .filter(|function| !function.variadic && !address_taken.contains(function.name.as_str()))
+        .filter(|function| !calls_itself(function))
+        .filter(|function| function.inline || size(function) <= threshold)
+        .map(|function| (function.name.clone(), function.clone()))
+        .collect();
+    for function in &mut module.functions {
+        for _ in 0..MAX_DEPTH {
+            if !inline_round(function, &callees) {
+                break;
+            }
+        }
+    }
+    remove_unused(module);
+}
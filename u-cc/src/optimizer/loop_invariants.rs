@@ -0,0 +1,234 @@
+//! Loop-invariant code motion. An operation in a loop whose operands are
+//! the same every time around moves to a block of its own just before the
+//! loop, so it's computed once. It then runs even when the loop doesn't,
+//! so only operations that can't trap move: no divisions, and only loads
+//! from locals and globals, from a loop with no stores or calls that might
+//! change them.
+
+use super::dead_code::live_in;
+use super::dominators::Dominators;
+use crate::ir::{Base, BinaryOp, Block, BlockId, Function, Instruction, Reg, Terminator};
+use std::collections::{HashMap, HashSet};
+
+/// a header, which dominates the rest of the loop, and every block that
+/// can get back to the header without going through it first
+struct Loop {
+    header: BlockId,
+    body: HashSet<BlockId>,
+}
+
+/// the natural loops of the function, one per header, innermost first
+fn loops(function: &Function) -> Vec<Loop> {
+    let dominators = Dominators::new(function);
+    let mut predecessors: HashMap<BlockId, Vec<BlockId>> = HashMap::new();
+    for block in &function.blocks {
+        for successor in block.terminator.successors() {
+            predecessors.entry(successor).or_default().push(block.id);
+        }
+    }
+    let mut bodies: HashMap<BlockId, HashSet<BlockId>> = HashMap::new();
+    for block in &function.blocks {
+        for header in block.terminator.successors() {
+            // a back edge, to a block that dominates this one
+            if !dominators.dominates(header, block.id) {
+                continue;
+            }
+            let body = bodies
+                .entry(header)
+                .or_insert_with(|| vec![header].into_iter().collect());
+            let mut worklist = vec![block.id];
+            while let Some(id) = worklist.pop() {
+                if body.insert(id) {
+                    worklist.extend(predecessors.get(&id).into_iter().flatten());
+                }
+            }
+        }
+    }
+    let mut loops: Vec<Loop> = bodies
+        .into_iter()
+        .map(|(header, body)| Loop { header, body })
+        .collect();
+    loops.sort_by_key(|found| (found.body.len(), found.header));
+    loops
+}
+
+/// whether running the instruction early can't go wrong or change anything
+/// but its dest
+fn can_move(instruction: &Instruction, memory_changes: bool) -> bool {
+    match instruction {
+        Instruction::Binary {
+            op: BinaryOp::SDiv | BinaryOp::UDiv | BinaryOp::SRem | BinaryOp::URem,
+            ..
+        } => false,
+        Instruction::Load { address, .. } => {
+            !memory_changes && !matches!(address.base, Base::Reg(_))
+        }
+        Instruction::Store { .. } | Instruction::Call { .. } | Instruction::VaStart { .. } => false,
+        _ => true,
+    }
+}
+
+/// the instructions in the loop that compute the same thing every time,
+/// by block and index, in an order where each comes after the ones it
+/// uses
+fn invariants(function: &Function, found: &Loop) -> Vec<(BlockId, usize)> {
+    let blocks: Vec<&Block> = function
+        .blocks
+        .iter()
+        .filter(|block| found.body.contains(&block.id))
+        .collect();
+    let instructions = || {
+        blocks.iter().flat_map(|block| {
+            block
+                .instructions
+                .iter()
+                .enumerate()
+                .map(move |(index, instruction)| (block.id, index, instruction))
+        })
+    };
+    let memory_changes = instructions().any(|(_, _, instruction)| {
+        matches!(
+            instruction,
+            Instruction::Store { .. } | Instruction::Call { .. } | Instruction::VaStart { .. }
+        )
+    });
+    let mut assignments: HashMap<Reg, usize> = HashMap::new();
+    for (_, _, instruction) in instructions() {
+        if let Some(dest) = instruction.dest() {
+            *assignments.entry(dest).or_insert(0) += 1;
+        }
+    }
+    let mut function_assignments: HashMap<Reg, usize> = HashMap::new();
+    for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+        if let Some(dest) = instruction.dest() {
+            *function_assignments.entry(dest).or_insert(0) += 1;
+        }
+    }
+    // a register the loop reads before assigning it needs its old value
+    let live = &live_in(function)[&found.header];
+    let mut moved: Vec<(BlockId, usize)> = vec![];
+    let mut invariant: HashSet<Reg> = HashSet::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (id, index, instruction) in instructions() {
+            let dest = match instruction.dest() {
+                Some(dest) => dest,
+                None => continue,
+            };
+            if moved.contains(&(id, index))
+                || !can_move(instruction, memory_changes)
+                || function_assignments[&dest] != 1
+                || function.parameters.contains(&dest)
+                || live.contains(&dest)
+            {
+                continue;
+            }
+            let operands_invariant = instruction
+                .uses()
+                .iter()
+                .all(|reg| !assignments.contains_key(reg) || invariant.contains(reg));
+            if operands_invariant {
+                moved.push((id, index));
+                invariant.insert(dest);
+                changed = true;
+            }
+        }
+    }
+    moved
+}
+
+/// the block that goes to the header from outside the loop, made if there
+/// isn't one already
+fn preheader(function: &mut Function, found: &Loop) -> BlockId {
+    let outside: Vec<BlockId> = function
+        .blocks
+        .iter()
+        .filter(|block| !found.body.contains(&block.id))
+        .filter(|block| block.terminator.successors().contains(&found.header))
+        .map(|block| block.id)
+        .collect();
+    if let [only] = outside[..] {
+        let block = function
+            .blocks
+            .iter()
+            .find(|block| block.id == only)
+            .unwrap();
+        if block.terminator == Terminator::Jump(found.header) {
+            return only;
+        }
+    }
+    let id = function.new_block_id();
+    for block in &mut function.blocks {
+        if outside.contains(&block.id) {
+            for target in block.terminator.successors_mut() {
+                if *target == found.header {
+                    *target = id;
+                }
+            }
+        }
+    }
+    let position = function
+        .blocks
+        .iter()
+        .position(|block| block.id == found.header)
+        .unwrap();
+    function.blocks.insert(
+        position,
+        Block {
+            id,
+            instructions: vec![],
+            terminator: Terminator::Jump(found.header),
+        },
+    );
+    id
+}
+
+fn hoist(function: &mut Function, found: &Loop) -> bool {
+    let moved = invariants(function, found);
+    if moved.is_empty() {
+        return false;
+    }
+    let preheader = preheader(function, found);
+    let mut hoisted = vec![];
+    for (id, index) in &moved {
+        let block = function
+            .blocks
+            .iter()
+            .find(|block| block.id == *id)
+            .unwrap();
+        hoisted.push(block.instructions[*index].clone());
+    }
+    for block in &mut function.blocks {
+        let id = block.id;
+        let mut index = 0;
+        block.instructions.retain(|_| {
+            index += 1;
+            !moved.contains(&(id, index - 1))
+        });
+    }
+    let block = function
+        .blocks
+        .iter_mut()
+        .find(|block| block.id == preheader)
+        .unwrap();
+    block.instructions.extend(hoisted);
+    true
+}
+
+/// moves invariant code out of every loop, inner loops first so what they
+/// move can keep moving out of the loops around them
+pub fn hoist_invariants(function: &mut Function) -> bool {
+    let mut changed = false;
+    let mut done: HashSet<BlockId> = HashSet::new();
+    // making a preheader changes the loops around it, so they're found
+    // again each time
+    while let Some(found) = loops(function)
+        .into_iter()
+        .find(|found| !done.contains(&found.header))
+    {
+        changed |= hoist(function, &found);
+        done.insert(found.header);
+    }
+    changed
+}
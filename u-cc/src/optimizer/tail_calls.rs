@@ -0,0 +1,148 @@
+//! Tail calls. A call whose result the function returns straight away
+//! doesn't need to come back to it: a call to the function itself becomes
+//! a jump back to the start with the parameters set to the arguments, so
+//! the recursion becomes a loop, and a call to another function is marked
+//! for code generation to leave the stack frame first and jump to it.
+//! Neither is done when the address of a local might get to the callee,
+//! since the local has to outlive the call, and calls to other functions
+//! stay calls when some arguments go on the stack, since that's where the
+//! caller's arguments are.
+
+use super::dead_code::escaping_slots;
+use crate::ir::{Block, Function, Instruction, Operand, Terminator};
+
+/// the index of the call in the block that the function returns the
+/// result of, if there is one, and whether it returns anything. The
+/// result can be copied around on the way, through blocks that do nothing
+/// else, like the ones inlining leaves.
+fn tail_call(function: &Function, position: usize) -> Option<(usize, bool)> {
+    let block = &function.blocks[position];
+    let index = block
+        .instructions
+        .iter()
+        .rposition(|instruction| !matches!(instruction, Instruction::Copy { .. }))?;
+    let mut result = match &block.instructions[index] {
+        Instruction::Call { dest, .. } => *dest,
+        _ => return None,
+    };
+    let mut instructions = &block.instructions[index + 1..];
+    let mut terminator = &block.terminator;
+    let mut visited = vec![block.id];
+    loop {
+        for instruction in instructions {
+            match (instruction, result) {
+                (Instruction::Copy { dest, src }, Some(reg)) if *src == Operand::Reg(reg) => {
+                    result = Some(*dest)
+                }
+                _ => return None,
+            }
+        }
+        match terminator {
+            Terminator::Return(None) => return Some((index, false)),
+            Terminator::Return(Some(value)) => {
+                return match result {
+                    Some(reg) if *value == Operand::Reg(reg) => Some((index, true)),
+                    _ => None,
+                }
+            }
+            Terminator::Jump(target) if !visited.contains(target) => {
+                visited.push(*target);
+                let block = function
+                    .blocks
+                    .iter()
+                    .find(|block| block.id == *target)
+                    .unwrap();
+                instructions = &block.instructions;
+                terminator = &block.terminator;
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// sends the recursive tail calls back to the start of the function, and
+/// marks the others, returning whether there were any
+pub fn optimize(function: &mut Function) -> bool {
+    if function.variadic || !escaping_slots(function).is_empty() {
+        return false;
+    }
+    // the old entry block becomes the top of the loop, and a new one goes
+    // to it, since nothing can jump to the entry
+    let top = function.blocks[0].id;
+    let mut changed = false;
+    let parameters = function.parameters.clone();
+    for position in 0..function.blocks.len() {
+        let (index, returns_value) = match tail_call(function, position) {
+            Some(found) => found,
+            None => continue,
+        };
+        let block = &mut function.blocks[position];
+        let (callee, arguments, in_registers, dest) = match &block.instructions[index] {
+            Instruction::Call {
+                function,
+                arguments,
+                in_registers,
+                dest,
+                ..
+            } => (function.clone(), arguments.clone(), *in_registers, *dest),
+            _ => unreachable!(),
+        };
+        // the callee would need the caller's stack arguments to be its own
+        let sibling = callee != function.name;
+        if sibling && in_registers < arguments.len() {
+            continue;
+        }
+        // the copies after the call aren't needed anymore
+        block.instructions.truncate(index + 1);
+        block.terminator = Terminator::Return(match returns_value {
+            true => dest.map(Operand::Reg),
+            false => None,
+        });
+        changed = true;
+        if sibling {
+            if let Some(Instruction::Call { tail, .. }) = block.instructions.last_mut() {
+                *tail = true;
+            }
+            continue;
+        }
+        // a call without a prototype can pass the wrong number
+        if arguments.len() != parameters.len() {
+            continue;
+        }
+        // every argument is worked out before any parameter changes
+        let temporaries: Vec<_> = parameters
+            .iter()
+            .map(|parameter| function.new_reg(parameter.ty))
+            .collect();
+        let block = &mut function.blocks[position];
+        block.instructions.pop();
+        for (temporary, argument) in temporaries.iter().zip(arguments) {
+            block
+                .instructions
+                .push(Instruction::pass(*temporary, argument));
+        }
+        for (parameter, temporary) in parameters.iter().zip(&temporaries) {
+            block.instructions.push(Instruction::Copy {
+                dest: *parameter,
+                src: (*temporary).into(),
+            });
+        }
+        block.terminator = Terminator::Jump(top);
+    }
+    let loops = function
+        .blocks
+        .iter()
+        .any(|block| block.terminator.successors().contains(&top));
+    if loops {
+        let entry = function.new_block_id();
+        function.blocks.insert(
+            0,
+            Block {
+                id: entry,
+                instructions: vec![],
+                terminator: Terminator::Jump(top),
+            },
+        );
+    }
+    changed
+}
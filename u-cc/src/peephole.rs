@@ -0,0 +1,474 @@
+//! A peephole pass over the allocated assembly. It looks at a few adjacent
+//! instructions at a time and rewrites them into fewer or cheaper ones,
+//! until nothing else matches. Labels and jumps end the window, since other
+//! code can jump in between them.
+
+use crate::asm::{Address, Instruction, Register};
+
+/// whether the instruction writes to all of a dword register, which also
+/// clears the upper half of the qword register
+fn writes_dword(instruction: &Instruction, register: Register) -> bool {
+    if register.size() != 4 {
+        return false;
+    }
+    let dest = match instruction {
+        Instruction::Mov(dest, _)
+        | Instruction::Movsx(dest, _)
+        | Instruction::Movzx(dest, _)
+        | Instruction::Add(dest, _)
+        | Instruction::Sub(dest, _)
+        | Instruction::Imul(dest, _)
+        | Instruction::And(dest, _)
+        | Instruction::Or(dest, _)
+        | Instruction::Xor(dest, _)
+        | Instruction::Lea(dest, _)
+        | Instruction::Neg(dest)
+        | Instruction::Not(dest) => dest,
+        _ => return false,
+    };
+    *dest == Address::Register(register)
+}
+
+/// whether the upper half of a dword register is known to be clear before
+/// the instructions that come next, since the last thing to write to the
+/// register wrote all of the dword
+fn upper_half_clear(before: &[Instruction], register: Register) -> bool {
+    let same = |named: &Register| named.resize(8) == register.resize(8);
+    for instruction in before.iter().rev() {
+        if writes_dword(instruction, register) {
+            return true;
+        }
+        match instruction {
+            // these only read the registers they name
+            Instruction::Mov(Address::Indirect(_), _)
+            | Instruction::Cmp(..)
+            | Instruction::Push(_)
+            | Instruction::Jcc(..)
+            | Instruction::Comment(_) => continue,
+            // these write registers they don't name, or other code can
+            // jump past them
+            Instruction::Call(..)
+            | Instruction::CallExternal(..)
+            | Instruction::Idiv(_)
+            | Instruction::Div(_)
+            | Instruction::Cdq
+            | Instruction::Cqo
+            | Instruction::Label(_)
+            | Instruction::Jmp(_)
+            | Instruction::Ret
+            | Instruction::TailCall(..)
+            | Instruction::TailCallExternal(..) => return false,
+            instruction => {
+                if instruction
+                    .clone()
+                    .registers_mut()
+                    .into_iter()
+                    .any(|named| same(named))
+                {
+                    return false;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// whether a later instruction reads the flags before they're set again.
+/// Code generation only tests flags right after setting them, so they're
+/// never live across a label.
+fn flags_live(rest: &[Instruction]) -> bool {
+    for instruction in rest {
+        match instruction {
+            Instruction::Jcc(..) | Instruction::Setcc(..) => return true,
+            Instruction::Mov(..)
+            | Instruction::Movsx(..)
+            | Instruction::Movzx(..)
+            | Instruction::Movsxd(..)
+            | Instruction::Lea(..)
+            | Instruction::Push(_)
+            | Instruction::Pop(_)
+            | Instruction::Cdq
+            | Instruction::Cqo
+            | Instruction::Not(_)
+            | Instruction::Comment(_) => continue,
+            // a shift by zero leaves the flags alone
+            Instruction::Shl(..) | Instruction::Sar(..) | Instruction::Shr(..) => continue,
+            _ => return false,
+        }
+    }
+    false
+}
+
+/// the k of a multiplier that's 2^k, other than 1
+fn power_of_two(val: i64) -> Option<i64> {
+    match val > 1 && val & (val - 1) == 0 {
+        true => Some(i64::from(val.trailing_zeros())),
+        false => None,
+    }
+}
+
+/// whether control can't get from this instruction to the next one
+fn is_unconditional(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Jmp(_)
+            | Instruction::Ret
+            | Instruction::TailCall(..)
+            | Instruction::TailCallExternal(..)
+    )
+}
+
+/// whether the instruction does something when it runs, as opposed to
+/// directing the assembler
+fn is_code(instruction: &Instruction) -> bool {
+    !matches!(
+        instruction,
+        Instruction::Label(_)
+            | Instruction::Global(_)
+            | Instruction::Extern(_)
+            | Instruction::Section(_)
+            | Instruction::Align(_)
+            | Instruction::AlignB(_)
+            | Instruction::Data(_)
+            | Instruction::Comment(_)
+    )
+}
+
+/// whether the label comes before the next instruction that does anything
+fn falls_into(label: &str, rest: &[Instruction]) -> bool {
+    for instruction in rest {
+        match instruction {
+            Instruction::Label(next) if next == label => return true,
+            Instruction::Label(_) | Instruction::Comment(_) => continue,
+            _ => return false,
+        }
+    }
+    false
+}
+
+/// rewrites the instruction at `i` and the ones after it, returning whether
+/// anything changed
+fn rewrite(instructions: &mut Vec<Instruction>, i: usize) -> bool {
+    let (current, rest) = instructions[i..].split_first().unwrap();
+    let next = rest.first();
+    match (current, next) {
+        // mov rax, rax does nothing, but mov eax, eax clears the upper half
+        // of rax unless that's been done already
+        (Instruction::Mov(Address::Register(dest), Address::Register(src)), _) if dest == src => {
+            if dest.size() != 4 || upper_half_clear(&instructions[..i], *dest) {
+                instructions.remove(i);
+                return true;
+            }
+            false
+        }
+        // storing a value and loading it straight back
+        (
+            Instruction::Mov(Address::Indirect(stored), Address::Register(src)),
+            Some(Instruction::Mov(Address::Register(dest), Address::Indirect(loaded))),
+        ) if stored == loaded && dest.size() == src.size() => {
+            instructions[i + 1] = Instruction::Mov((*dest).into(), (*src).into());
+            true
+        }
+        // loading a value and storing it back where it came from, unless
+        // the load replaced the pointer the address goes through
+        (
+            Instruction::Mov(Address::Register(dest), Address::Indirect(loaded)),
+            Some(Instruction::Mov(Address::Indirect(stored), Address::Register(src))),
+        ) if stored == loaded
+            && dest == src
+            && *loaded.base() != Address::Register(dest.resize(8)) =>
+        {
+            instructions.remove(i + 1);
+            true
+        }
+        // a store that's overwritten before anything could read it
+        (
+            Instruction::Mov(Address::Indirect(first), _),
+            Some(Instruction::Mov(Address::Indirect(second), src)),
+        ) if first == second && !matches!(src, Address::Indirect(_)) => {
+            instructions.remove(i);
+            true
+        }
+        // shl sets the flags differently
+        (Instruction::Imul(dest, Address::Immediate(val)), _) if !flags_live(rest) => {
+            match power_of_two(*val) {
+                Some(shift) => {
+                    instructions[i] = Instruction::Shl(dest.clone(), Address::Immediate(shift));
+                    true
+                }
+                None => false,
+            }
+        }
+        // xor is shorter, but sets the flags where mov doesn't. Zeroing the
+        // dword register clears the whole register.
+        (Instruction::Mov(Address::Register(dest), Address::Immediate(0)), _)
+            if !flags_live(rest) =>
+        {
+            let dest = match dest.size() {
+                8 => dest.resize(4),
+                _ => *dest,
+            };
+            instructions[i] = Instruction::Xor(dest.into(), dest.into());
+            true
+        }
+        (Instruction::Jmp(label), _) | (Instruction::Jcc(_, label), _)
+            if falls_into(label, rest) =>
+        {
+            instructions.remove(i);
+            true
+        }
+        // jumping over an unconditional jump is the opposite condition
+        (Instruction::Jcc(condition, over), Some(Instruction::Jmp(target)))
+            if falls_into(over, &rest[1..]) =>
+        {
+            instructions[i] = Instruction::Jcc(condition.negate(), target.clone());
+            instructions.remove(i + 1);
+            true
+        }
+        // nothing runs between a jump or a return and the next label
+        (current, Some(next)) if is_unconditional(current) && is_code(next) => {
+            instructions.remove(i + 1);
+            true
+        }
+        _ => false,
+    }
+}
+
+pub fn optimize(instructions: &mut Vec<Instruction>) {
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let mut i = 0;
+        while i < instructions.len() {
+            if rewrite(instructions, i) {
+                changed = true;
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::optimize;
+    use crate::asm::{Address, Condition, IndirectAddress, Instruction, Register::*};
+
+    fn local(offset: i32) -> Address {
+        IndirectAddress::offset(Box::new(Rbp.into()), offset)
+            .dword()
+            .into()
+    }
+
+    fn optimized(mut instructions: Vec<Instruction>) -> Vec<Instruction> {
+        optimize(&mut instructions);
+        instructions
+    }
+
+    #[test]
+    fn forwards_a_store_to_the_load_after_it() {
+        let instructions = vec![
+            Instruction::Mov(Eax.into(), 5.into()),
+            Instruction::Mov(local(-8), Eax.into()),
+            Instruction::Mov(Ecx.into(), local(-8)),
+            Instruction::Ret,
+        ];
+        assert_eq!(
+            optimized(instructions),
+            vec![
+                Instruction::Mov(Eax.into(), 5.into()),
+                Instruction::Mov(local(-8), Eax.into()),
+                Instruction::Mov(Ecx.into(), Eax.into()),
+                Instruction::Ret,
+            ]
+        );
+    }
+
+    #[test]
+    fn removes_a_load_of_what_was_just_stored() {
+        let instructions = vec![
+            Instruction::Mov(Eax.into(), 7.into()),
+            Instruction::Mov(local(-8), Eax.into()),
+            Instruction::Mov(Eax.into(), local(-8)),
+        ];
+        assert_eq!(
+            optimized(instructions),
+            vec![
+                Instruction::Mov(Eax.into(), 7.into()),
+                Instruction::Mov(local(-8), Eax.into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn removes_a_store_of_what_was_just_loaded() {
+        let instructions = vec![
+            Instruction::Mov(Eax.into(), local(-4)),
+            Instruction::Mov(local(-4), Eax.into()),
+        ];
+        assert_eq!(
+            optimized(instructions),
+            vec![Instruction::Mov(Eax.into(), local(-4))]
+        );
+    }
+
+    #[test]
+    fn keeps_a_store_through_the_register_that_was_loaded() {
+        let pointer = || -> Address {
+            IndirectAddress::indirect(Box::new(Rax.into()))
+                .qword()
+                .into()
+        };
+        let instructions = vec![
+            Instruction::Mov(Rax.into(), pointer()),
+            Instruction::Mov(pointer(), Rax.into()),
+        ];
+        assert_eq!(optimized(instructions.clone()), instructions);
+    }
+
+    #[test]
+    fn removes_a_store_that_is_overwritten() {
+        let instructions = vec![
+            Instruction::Mov(local(-4), Eax.into()),
+            Instruction::Mov(local(-4), 3.into()),
+        ];
+        assert_eq!(
+            optimized(instructions),
+            vec![Instruction::Mov(local(-4), 3.into())]
+        );
+    }
+
+    #[test]
+    fn keeps_a_store_to_another_address() {
+        let instructions = vec![
+            Instruction::Mov(local(-4), Eax.into()),
+            Instruction::Mov(local(-8), Eax.into()),
+            Instruction::Mov(Ecx.into(), local(-4)),
+        ];
+        assert_eq!(optimized(instructions.clone()), instructions);
+    }
+
+    #[test]
+    fn removes_self_moves() {
+        let instructions = vec![
+            Instruction::Mov(Rax.into(), Rax.into()),
+            Instruction::Add(Ecx.into(), 1.into()),
+            Instruction::Mov(Ecx.into(), Ecx.into()),
+            Instruction::Ret,
+        ];
+        assert_eq!(
+            optimized(instructions),
+            vec![Instruction::Add(Ecx.into(), 1.into()), Instruction::Ret]
+        );
+    }
+
+    #[test]
+    fn keeps_a_dword_self_move_that_zero_extends() {
+        let instructions = vec![
+            Instruction::Mov(Rcx.into(), Rdx.into()),
+            Instruction::Mov(local(-4), Ecx.into()),
+            Instruction::Mov(Ecx.into(), Ecx.into()),
+        ];
+        assert_eq!(optimized(instructions.clone()), instructions);
+    }
+
+    #[test]
+    fn removes_jumps_to_the_next_label() {
+        let instructions = vec![
+            Instruction::Jmp(".L0".to_string()),
+            Instruction::Label(".L1".to_string()),
+            Instruction::Label(".L0".to_string()),
+            Instruction::Ret,
+        ];
+        assert_eq!(
+            optimized(instructions),
+            vec![
+                Instruction::Label(".L1".to_string()),
+                Instruction::Label(".L0".to_string()),
+                Instruction::Ret,
+            ]
+        );
+    }
+
+    #[test]
+    fn inverts_a_branch_over_a_jump() {
+        let instructions = vec![
+            Instruction::Cmp(Eax.into(), 0.into()),
+            Instruction::Jcc(Condition::Equal, ".L0".to_string()),
+            Instruction::Jmp(".L1".to_string()),
+            Instruction::Label(".L0".to_string()),
+            Instruction::Ret,
+        ];
+        assert_eq!(
+            optimized(instructions),
+            vec![
+                Instruction::Cmp(Eax.into(), 0.into()),
+                Instruction::Jcc(Condition::NotEqual, ".L1".to_string()),
+                Instruction::Label(".L0".to_string()),
+                Instruction::Ret,
+            ]
+        );
+    }
+
+    #[test]
+    fn removes_code_after_a_jump() {
+        let instructions = vec![
+            Instruction::Jmp(".L0".to_string()),
+            Instruction::Mov(Eax.into(), 1.into()),
+            Instruction::Label(".L1".to_string()),
+            Instruction::Ret,
+        ];
+        assert_eq!(
+            optimized(instructions),
+            vec![
+                Instruction::Jmp(".L0".to_string()),
+                Instruction::Label(".L1".to_string()),
+                Instruction::Ret,
+            ]
+        );
+    }
+
+    #[test]
+    fn multiplies_by_a_power_of_two_with_a_shift() {
+        let instructions = vec![
+            Instruction::Imul(Eax.into(), 8.into()),
+            Instruction::Imul(Ecx.into(), 6.into()),
+            Instruction::Imul(Edx.into(), 1.into()),
+        ];
+        assert_eq!(
+            optimized(instructions),
+            vec![
+                Instruction::Shl(Eax.into(), 3.into()),
+                Instruction::Imul(Ecx.into(), 6.into()),
+                Instruction::Imul(Edx.into(), 1.into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn zeroes_registers_with_xor() {
+        let instructions = vec![
+            Instruction::Mov(Rax.into(), 0.into()),
+            Instruction::Mov(Cl.into(), 0.into()),
+            Instruction::Ret,
+        ];
+        assert_eq!(
+            optimized(instructions),
+            vec![
+                Instruction::Xor(Eax.into(), Eax.into()),
+                Instruction::Xor(Cl.into(), Cl.into()),
+                Instruction::Ret,
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_mov_zero_while_the_flags_are_live() {
+        let instructions = vec![
+            Instruction::Cmp(Ecx.into(), 3.into()),
+            Instruction::Mov(Eax.into(), 0.into()),
+            Instruction::Setcc(Condition::Less, Al),
+        ];
+        assert_eq!(optimized(instructions.clone()), instructions);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::asm::Syntax;
+
 #[cfg(target_os = "macos")]
 pub fn main_symbol() -> &'static str {
     "_main"
@@ -7,3 +9,30 @@ pub fn main_symbol() -> &'static str {
 pub fn main_symbol() -> &'static str {
     "main"
 }
+
+/// whether objects have a .note.GNU-stack section to keep the stack from
+/// being made executable
+#[cfg(target_os = "macos")]
+pub fn stack_note() -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+pub fn stack_note() -> bool {
+    true
+}
+
+/// position independent executables call into shared libraries through
+/// the PLT, which is the default for gcc on Linux
+#[cfg(target_os = "macos")]
+pub fn plt_suffix(_syntax: Syntax) -> &'static str {
+    ""
+}
+
+#[cfg(target_os = "linux")]
+pub fn plt_suffix(syntax: Syntax) -> &'static str {
+    match syntax {
+        Syntax::Nasm => " wrt ..plt",
+        Syntax::Att => "@PLT",
+    }
+}
@@ -0,0 +1,297 @@
+//! A small preprocessor that runs before parsing. It strips comments,
+//! includes the headers bundled with the compiler and expands object and
+//! function-like macros. Conditional compilation, `#` and `##` aren't
+//! supported, and a macro call has to fit on one line.
+
+use std::collections::{HashMap, HashSet};
+
+/// headers that ship with the compiler, since their contents depend on it
+fn bundled_header(name: &str) -> Option<&'static str> {
+    match name {
+        "stdarg.h" => Some(include_str!("../include/stdarg.h")),
+        _ => None,
+    }
+}
+
+enum Macro {
+    Object(String),
+    Function {
+        parameters: Vec<String>,
+        body: String,
+    },
+}
+
+pub fn preprocess(source: &str) -> Result<String, String> {
+    let mut preprocessor = Preprocessor {
+        macros: HashMap::new(),
+        output: String::new(),
+    };
+    preprocessor.file(source, &mut vec![])?;
+    Ok(preprocessor.output)
+}
+
+struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    output: String,
+}
+
+impl Preprocessor {
+    /// `including` holds the headers being included, to catch cycles
+    fn file(&mut self, source: &str, including: &mut Vec<String>) -> Result<(), String> {
+        let source = strip_comments(source)?;
+        // a backslash at the end of a line continues it
+        let source = source.replace("\\\n", "");
+        for line in source.lines() {
+            match line.trim_start().strip_prefix('#') {
+                Some(directive) => self.directive(directive.trim(), including)?,
+                None => {
+                    let line = self.expand(line, &HashSet::new())?;
+                    self.output.push_str(&line);
+                }
+            }
+            self.output.push('\n');
+        }
+        Ok(())
+    }
+
+    fn directive(&mut self, directive: &str, including: &mut Vec<String>) -> Result<(), String> {
+        let name_end = directive
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(directive.len());
+        let (name, rest) = directive.split_at(name_end);
+        let rest = rest.trim();
+        match name {
+            // a lone # does nothing
+            "" => Ok(()),
+            "include" => {
+                let header = match rest.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+                    Some(header) => header,
+                    None => return Err(format!("#include expects <header>, found {}", rest)),
+                };
+                let source = bundled_header(header)
+                    .ok_or_else(|| format!("{}: no such header is bundled with u-cc", header))?;
+                if including.iter().any(|name| name == header) {
+                    return Err(format!("#include of {} is recursive", header));
+                }
+                including.push(header.to_string());
+                self.file(source, including)?;
+                including.pop();
+                Ok(())
+            }
+            "define" => self.define(rest),
+            "undef" => {
+                self.macros.remove(rest);
+                Ok(())
+            }
+            other => Err(format!("unsupported preprocessing directive #{}", other)),
+        }
+    }
+
+    fn define(&mut self, definition: &str) -> Result<(), String> {
+        let name_end = definition
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(definition.len());
+        let (name, rest) = definition.split_at(name_end);
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err("macro names must be identifiers".to_string());
+        }
+        // only a parenthesis right after the name makes a function-like macro
+        let definition = match rest.strip_prefix('(') {
+            Some(rest) => {
+                let close = rest
+                    .find(')')
+                    .ok_or_else(|| format!("missing ')' in parameter list of {}", name))?;
+                let parameters = rest[..close]
+                    .split(',')
+                    .map(|parameter| parameter.trim().to_string())
+                    .filter(|parameter| !parameter.is_empty())
+                    .collect();
+                Macro::Function {
+                    parameters,
+                    body: rest[close + 1..].trim().to_string(),
+                }
+            }
+            None => Macro::Object(rest.trim().to_string()),
+        };
+        self.macros.insert(name.to_string(), definition);
+        Ok(())
+    }
+
+    /// replaces macros in `text`. A macro isn't expanded again inside its
+    /// own expansion, so `disabled` holds the ones being expanded.
+    fn expand<'a>(&'a self, text: &str, disabled: &HashSet<&'a str>) -> Result<String, String> {
+        let tokens = tokenize(text);
+        let mut output = String::new();
+        let mut i = 0;
+        while i < tokens.len() {
+            let token = tokens[i];
+            i += 1;
+            let (name, definition) = match self.macros.get_key_value(token) {
+                Some((name, definition)) if !disabled.contains(token) => (name, definition),
+                _ => {
+                    output.push_str(token);
+                    continue;
+                }
+            };
+            let mut inner = disabled.clone();
+            inner.insert(name);
+            match definition {
+                Macro::Object(body) => output.push_str(&self.expand(body, &inner)?),
+                Macro::Function { parameters, body } => {
+                    let open = tokens[i..]
+                        .iter()
+                        .position(|token| !token.trim().is_empty())
+                        .map(|offset| i + offset);
+                    // without arguments the name isn't a macro call
+                    let open = match open {
+                        Some(open) if tokens[open] == "(" => open,
+                        _ => {
+                            output.push_str(token);
+                            continue;
+                        }
+                    };
+                    let (arguments, end) = arguments(&tokens, open + 1)
+                        .ok_or_else(|| format!("unterminated call of macro {}", name))?;
+                    i = end;
+                    let arguments = match (parameters.len(), arguments.as_slice()) {
+                        (0, [argument]) if argument.trim().is_empty() => vec![],
+                        _ => arguments,
+                    };
+                    if arguments.len() != parameters.len() {
+                        return Err(format!(
+                            "macro {} takes {} arguments, but {} were given",
+                            name,
+                            parameters.len(),
+                            arguments.len()
+                        ));
+                    }
+                    // arguments are expanded before they're substituted, and
+                    // can call the macro themselves
+                    let mut expanded = HashMap::new();
+                    for (parameter, argument) in parameters.iter().zip(&arguments) {
+                        expanded
+                            .insert(parameter.as_str(), self.expand(argument.trim(), disabled)?);
+                    }
+                    let substituted: String = tokenize(body)
+                        .into_iter()
+                        .map(|token| match expanded.get(token) {
+                            Some(argument) => argument.as_str(),
+                            None => token,
+                        })
+                        .collect();
+                    output.push_str(&self.expand(&substituted, &inner)?);
+                }
+            }
+        }
+        Ok(output)
+    }
+}
+
+/// splits the arguments of a macro call at top level commas, starting
+/// after the opening parenthesis. Also returns where the call ends.
+fn arguments(tokens: &[&str], start: usize) -> Option<(Vec<String>, usize)> {
+    let mut arguments = vec![String::new()];
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(start) {
+        match *token {
+            ")" if depth == 0 => return Some((arguments, i + 1)),
+            "," if depth == 0 => {
+                arguments.push(String::new());
+                continue;
+            }
+            "(" => depth += 1,
+            ")" => depth -= 1,
+            _ => {}
+        }
+        arguments.last_mut().unwrap().push_str(token);
+    }
+    None
+}
+
+/// splits text into identifiers, numbers, literals, runs of whitespace and
+/// single punctuation characters, which is enough to find macro names
+fn tokenize(text: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let mut end = start + c.len_utf8();
+        let mut take_while = |end: &mut usize, predicate: &dyn Fn(char) -> bool| {
+            while let Some(&(i, c)) = chars.peek() {
+                if !predicate(c) {
+                    break;
+                }
+                *end = i + c.len_utf8();
+                chars.next();
+            }
+        };
+        if c.is_ascii_alphanumeric() || c == '_' {
+            take_while(&mut end, &|c| c.is_ascii_alphanumeric() || c == '_');
+        } else if c.is_whitespace() {
+            take_while(&mut end, &|c: char| c.is_whitespace());
+        } else if c == '"' || c == '\'' {
+            let mut escaped = false;
+            for (i, next) in chars.by_ref() {
+                end = i + next.len_utf8();
+                match next {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    _ if next == c => break,
+                    _ => {}
+                }
+            }
+        }
+        tokens.push(&text[start..end]);
+    }
+    tokens
+}
+
+/// replaces each comment with a space, keeping the newlines in block comments
+fn strip_comments(source: &str) -> Result<String, String> {
+    let mut output = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                output.push(c);
+                let mut escaped = false;
+                for next in chars.by_ref() {
+                    output.push(next);
+                    match next {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '\n' => break,
+                        _ if next == c => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+                output.push(' ');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut closed = false;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '*' if chars.peek() == Some(&'/') => {
+                            chars.next();
+                            closed = true;
+                            break;
+                        }
+                        '\n' => output.push('\n'),
+                        _ => {}
+                    }
+                }
+                if !closed {
+                    return Err("unterminated comment".to_string());
+                }
+                output.push(' ');
+            }
+            c => output.push(c),
+        }
+    }
+    Ok(output)
+}